@@ -1,15 +1,20 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 use color_eyre::Result;
+use crossbeam_channel::{unbounded, Receiver, TryRecvError};
 use csv::{ReaderBuilder, Trim, WriterBuilder};
 use tokio::runtime::Runtime;
 
+use std::fmt::Write;
+use std::fs::File;
 use std::path::PathBuf;
+use std::thread;
 use std::time::Duration;
 
 use lib::engines::{ActorLikeEngine, BasicEngine, StreamLikeEngine};
+use lib::input::InputOptions;
 use lib::transaction::IncomingTransaction;
-use lib::{AsyncEngine, SyncEngine};
+use lib::{process_pipelined, AsyncEngine, SyncEngine};
 
 fn run_sync(mut clients: impl SyncEngine) -> Result<()> {
     let mut reader = ReaderBuilder::new()
@@ -22,6 +27,15 @@ fn run_sync(mut clients: impl SyncEngine) -> Result<()> {
     Ok(())
 }
 
+fn run_sync_pipelined(mut clients: impl SyncEngine) -> Result<()> {
+    let options = InputOptions::default();
+    let reader = options.reader(File::open("./test_assets/larger/spec.csv")?);
+    process_pipelined(reader, &options, &mut clients)?;
+    let mut writer = WriterBuilder::new().from_path("/dev/null")?.into_inner()?;
+    clients.output(&mut writer)?;
+    Ok(())
+}
+
 async fn run_async(mut clients: impl AsyncEngine + Send + Sync) -> Result<()> {
     let mut reader = ReaderBuilder::new()
         .trim(Trim::All)
@@ -33,6 +47,176 @@ async fn run_async(mut clients: impl AsyncEngine + Send + Sync) -> Result<()> {
     Ok(())
 }
 
+/// Generates a dispute heavy workload in order to exercise the state transitions of a transaction.
+///
+/// For each round, every client makes a deposit which is then immediately disputed and resolved,
+/// so 2/3 of the rows are dispute/resolve transitions. On the final round every 10th client has
+/// their dispute charged back instead of resolved, so the chargeback path is also exercised
+/// without freezing accounts early.
+fn dispute_heavy_input(clients: u16, rounds: u32) -> Vec<u8> {
+    let mut input = String::from("type,client,tx,amount\n");
+    let mut tx = 0u32;
+    for round in 0..rounds {
+        for client in 1..=clients {
+            tx += 1;
+            let amount = format!("{}.{:04}", round % 100 + 1, tx % 10_000);
+            writeln!(input, "deposit,{},{},{}", client, tx, amount).unwrap();
+            writeln!(input, "dispute,{},{},", client, tx).unwrap();
+            if round + 1 == rounds && client % 10 == 0 {
+                writeln!(input, "chargeback,{},{},", client, tx).unwrap();
+            } else {
+                writeln!(input, "resolve,{},{},", client, tx).unwrap();
+            }
+        }
+    }
+    input.into_bytes()
+}
+
+/// Generates a deposit heavy workload, where every row is a brand new deposit, in order to
+/// exercise the hot path of recording a previously unseen transaction.
+fn deposit_heavy_input(clients: u16, rounds: u32) -> Vec<u8> {
+    let mut input = String::from("type,client,tx,amount\n");
+    let mut tx = 0u32;
+    for round in 0..rounds {
+        for client in 1..=clients {
+            tx += 1;
+            let amount = format!("{}.{:04}", round % 100 + 1, tx % 10_000);
+            writeln!(input, "deposit,{},{},{}", client, tx, amount).unwrap();
+        }
+    }
+    input.into_bytes()
+}
+
+/// Generates a workload where every client hashes to the same partition of the
+/// [`StreamLikeEngine`], which is its worst case as the work is no longer distributed.
+fn hot_partition_input(clients: u16, rounds: u32) -> Vec<u8> {
+    let partitions = num_cpus::get() as u16;
+    let mut input = String::from("type,client,tx,amount\n");
+    let mut tx = 0u32;
+    for round in 0..rounds {
+        for client in 0..clients {
+            tx += 1;
+            let client = client.wrapping_mul(partitions);
+            let amount = format!("{}.{:04}", round % 100 + 1, tx % 10_000);
+            writeln!(input, "deposit,{},{},{}", client, tx, amount).unwrap();
+            writeln!(input, "dispute,{},{},", client, tx).unwrap();
+            writeln!(input, "resolve,{},{},", client, tx).unwrap();
+        }
+    }
+    input.into_bytes()
+}
+
+fn run_sync_over(mut clients: impl SyncEngine, input: &[u8]) -> Result<()> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(input);
+    clients.process(reader.deserialize::<IncomingTransaction>())?;
+    let mut writer = WriterBuilder::new().from_path("/dev/null")?.into_inner()?;
+    clients.output(&mut writer)?;
+    Ok(())
+}
+
+async fn run_async_over(mut clients: impl AsyncEngine + Send + Sync, input: &[u8]) -> Result<()> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(input);
+    clients
+        .process(reader.deserialize::<IncomingTransaction>())
+        .await?;
+    let mut writer = WriterBuilder::new().from_path("/dev/null")?.into_inner()?;
+    clients.output(&mut writer).await?;
+    Ok(())
+}
+
+/// Receives until the channel is disconnected by spinning on `try_recv`, yielding whenever the
+/// channel is empty, which is how a partition of the [`StreamLikeEngine`] used to receive.
+fn spin_receive(r: Receiver<u32>) -> u64 {
+    let mut total = 0;
+    loop {
+        match r.try_recv() {
+            Ok(msg) => total += u64::from(msg),
+            Err(TryRecvError::Empty) => thread::yield_now(),
+            Err(TryRecvError::Disconnected) => return total,
+        }
+    }
+}
+
+/// Receives until the channel is disconnected, parking the thread whenever the channel is empty.
+fn blocking_receive(r: Receiver<u32>) -> u64 {
+    r.into_iter().map(u64::from).sum()
+}
+
+/// Sends `messages` round robin to a receiving thread per partition, in the same shape as the
+/// [`StreamLikeEngine`], so that only the way the partitions receive differs.
+fn receive_over(partitions: usize, messages: u32, receive: fn(Receiver<u32>) -> u64) -> u64 {
+    let (senders, handles): (Vec<_>, Vec<_>) = (0..partitions)
+        .map(|_| {
+            let (s, r) = unbounded();
+            (s, thread::spawn(move || receive(r)))
+        })
+        .unzip();
+    for msg in 0..messages {
+        senders[msg as usize % partitions].send(msg).unwrap();
+    }
+    drop(senders);
+    handles.into_iter().map(|h| h.join().unwrap()).sum()
+}
+
+pub fn dispute_benchmark(c: &mut Criterion) {
+    let input = dispute_heavy_input(250, 400);
+    let mut group = c.benchmark_group("trx-dispute-bench");
+    group.sample_size(20);
+    group.measurement_time(Duration::from_secs(30));
+    group.bench_function("basic", |b| {
+        b.iter(|| {
+            black_box(run_sync_over(BasicEngine::default(), &input).ok());
+        })
+    });
+    group.bench_function("stream", |b| {
+        b.iter(|| {
+            black_box(run_sync_over(StreamLikeEngine::default(), &input).ok());
+        })
+    });
+    group.bench_function("actor", |b| {
+        b.to_async(Runtime::new().unwrap())
+            .iter(|| black_box(run_async_over(ActorLikeEngine::default(), &input)))
+    });
+    group.finish()
+}
+
+pub fn deposit_benchmark(c: &mut Criterion) {
+    let input = deposit_heavy_input(250, 1_000);
+    let mut group = c.benchmark_group("trx-deposit-bench");
+    group.sample_size(20);
+    group.measurement_time(Duration::from_secs(30));
+    group.bench_function("basic", |b| {
+        b.iter(|| {
+            black_box(run_sync_over(BasicEngine::default(), &input).ok());
+        })
+    });
+    group.finish()
+}
+
+pub fn hot_partition_benchmark(c: &mut Criterion) {
+    let input = hot_partition_input(250, 400);
+    let mut group = c.benchmark_group("trx-hot-partition-bench");
+    group.sample_size(20);
+    group.measurement_time(Duration::from_secs(30));
+    group.bench_function("basic", |b| {
+        b.iter(|| {
+            black_box(run_sync_over(BasicEngine::default(), &input).ok());
+        })
+    });
+    group.bench_function("stream", |b| {
+        b.iter(|| {
+            black_box(run_sync_over(StreamLikeEngine::default(), &input).ok());
+        })
+    });
+    group.finish()
+}
+
 pub fn benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("trx-bench");
     group.sample_size(20);
@@ -42,6 +226,11 @@ pub fn benchmark(c: &mut Criterion) {
             black_box(run_sync(BasicEngine::default()).ok());
         })
     });
+    group.bench_function("basic-pipelined", |b| {
+        b.iter(|| {
+            black_box(run_sync_pipelined(BasicEngine::default()).ok());
+        })
+    });
     group.bench_function("stream", |b| {
         b.iter(|| {
             black_box(run_sync(StreamLikeEngine::default()).ok());
@@ -54,5 +243,26 @@ pub fn benchmark(c: &mut Criterion) {
     group.finish()
 }
 
-criterion_group!(benches, benchmark);
+pub fn receive_benchmark(c: &mut Criterion) {
+    let partitions = num_cpus::get();
+    let mut group = c.benchmark_group("trx-receive-bench");
+    group.sample_size(20);
+    group.measurement_time(Duration::from_secs(30));
+    group.bench_function("spin", |b| {
+        b.iter(|| black_box(receive_over(partitions, 1_000_000, spin_receive)))
+    });
+    group.bench_function("blocking", |b| {
+        b.iter(|| black_box(receive_over(partitions, 1_000_000, blocking_receive)))
+    });
+    group.finish()
+}
+
+criterion_group!(
+    benches,
+    benchmark,
+    dispute_benchmark,
+    deposit_benchmark,
+    hot_partition_benchmark,
+    receive_benchmark
+);
 criterion_main!(benches);
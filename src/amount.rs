@@ -1,14 +1,19 @@
 //! A wrapper around [`Decimal`] in order to perform mathematic calculates on decimals with a
 //! higher precision, alongside gaining some security benefits.
 
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
 use rust_decimal::Decimal;
 use serde::de::Error;
 use serde::{Deserialize, Serialize};
 
+use std::iter::Sum;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::str::FromStr;
 
-/// The precision we want to carry any decimal based operations to.
-const PRECISION: u32 = 4;
+use crate::error::AmountParseError;
+
+/// The default precision we want to carry any decimal based operations to.
+pub const PRECISION: u32 = 4;
 
 /// A wrapper around [`Decimal`] in order to perform mathematic calculates on decimals with a
 /// higher precision, alongside gaining some security benefits.
@@ -18,7 +23,8 @@ const PRECISION: u32 = 4;
 /// This is a wrapper type for the transaction amount, it serves two primary
 /// purposes.
 ///
-/// 1. To enforce the requirement of carrying 4dp through the application
+/// 1. To enforce the requirement of carrying 4dp _(or the configured precision)_ through the
+///    application
 /// 2. To ensure that the transaction amount is not printable (ie. ![`std::fmt::Debug`]). Given that logs
 ///    could be shipped to an external 3rd party for processing, it is likely we wouldn't want to
 ///    log specific transaction amounts. If we did log transaction amounts and this data was
@@ -28,20 +34,44 @@ const PRECISION: u32 = 4;
 /// # Notes
 ///
 /// - Under the guise of this exercise, the intended **construction** method for this type is through
-/// the deserialization of CSVs. As such the constructor is private, and no construction methods
-/// are offered in the public API. This would be re-visited if requirements change.
+///   the deserialization of CSVs, or equivalently parsing a string via [`FromStr`].
+/// - Constructing an amount from an `f32` is discouraged, as most decimals can't be exactly
+///   represented as a binary float _(eg. `0.1f32` is actually `0.100000001490116...`)_. The
+///   lenient [`TryFrom<f32>`] silently rounds this away, whereas
+///   [`Amount::try_from_f32_exact`] errors instead.
 /// - All instances of [`Amount`] that are created via `deserialization` will automatically
-/// be rounded to 4 decimal places _using the bankers rounding rule_
+///   be rounded to their precision _using the bankers rounding rule_
 /// - This type should not implement [`Deref`] or [`DerefMut`] without careful
-/// consideration, as doing so would potentially allow [`Debug`] & [`Display`] implementations
-/// through the dereferencing through to the [`Decimal`] type.
+///   consideration, as doing so would potentially allow [`Debug`] & [`Display`] implementations
+///   through the dereferencing through to the [`Decimal`] type.
+///
+/// ## Precision
+///
+/// The number of decimal places is set by the `DP` parameter, which defaults to [`PRECISION`]
+/// so `Amount` is always a 4dp amount. Other use cases _(eg. 8dp for satoshis)_ can instead use
+/// `Amount<8>`, in which case parsing, deserializing, [`TryFrom<f32>`] and [`Amount::round`] all
+/// round to 8dp. Amounts of different precisions can't be mixed.
+///
+/// ```
+/// use lib::Amount;
+///
+/// let amount: Amount<8> = "0.123456785".parse().unwrap();
+/// assert_eq!(amount.to_exact_string(), "0.12345678");
+/// ```
+///
+/// Where the precision can't be inferred from the context, the default can be selected with
+/// `<Amount>::`, eg. `<Amount>::default()`.
 ///
 /// ## Debug & Display not allowed
 ///
 /// Do not remove the two doc-tests below, they assure that [`Amount`] does not implement
 /// [`Debug`] or [`Display`]
 ///
-/// ```compile_fail
+/// With the `debug_amounts` feature enabled, [`Amount`] implements [`Debug`] so that structs
+/// containing it can derive [`Debug`], however the value is always redacted as `Amount(****)`.
+///
+#[cfg_attr(not(feature = "debug_amounts"), doc = "```compile_fail")]
+#[cfg_attr(feature = "debug_amounts", doc = "```ignore")]
 /// use lib::amount::Amount;
 ///
 /// let csv_row = csv::StringRecord::from(vec!["1.03235"]);
@@ -58,62 +88,374 @@ const PRECISION: u32 = 4;
 ///
 /// println!("{}", amount);
 /// ```
+///
+/// ## Provenance
+///
+/// With the `amount_provenance` feature enabled, each amount also records whether it was parsed
+/// from the input or is the result of a calculation, see [`Amount::provenance`]. This is
+/// intended for diagnosing rounding discrepancies, and is opt-in as it increases the size of
+/// every amount.
+///
 /// [`Debug`]: std::fmt::Debug
 /// [`Display`]: std::fmt::Display
 /// [`Deref`]: std::ops::Deref
 /// [`DerefMut`]: std::ops::DerefMut
-#[derive(PartialEq, PartialOrd, Clone, Copy, Serialize)]
-pub struct Amount(Decimal);
+#[derive(Clone, Copy)]
+pub struct Amount<const DP: u32 = PRECISION>(
+    Decimal,
+    #[cfg(feature = "amount_provenance")] Provenance,
+);
+
+/// Where an [`Amount`] came from, see [`Amount::provenance`]
+#[cfg(feature = "amount_provenance")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provenance {
+    /// The amount was provided as is, eg. parsed from the input
+    Input,
+    /// The amount is the result of a calculation, so may be subject to accumulated rounding
+    Computed,
+}
+
+/// Redacts the value of the amount, see the `debug_amounts` feature
+#[cfg(feature = "debug_amounts")]
+impl<const DP: u32> std::fmt::Debug for Amount<DP> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Amount(****)")
+    }
+}
+
+impl<const DP: u32> PartialEq for Amount<DP> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<const DP: u32> PartialOrd for Amount<DP> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<const DP: u32> Serialize for Amount<DP> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct("Amount", &self.0)
+    }
+}
 
-impl Default for Amount {
+/// The signed difference between two [`Amount`]s, eg. an adjustment made to a balance
+///
+/// As with [`Amount`], this deliberately does not implement [`Debug`] or [`Display`].
+///
+/// [`Debug`]: std::fmt::Debug
+/// [`Display`]: std::fmt::Display
+#[derive(PartialEq, PartialOrd, Clone, Copy, Default)]
+pub struct SignedAmount(Decimal);
+
+impl SignedAmount {
+    /// The signed change required to move from the `from` amount to the `to` amount
+    pub fn between(from: Amount, to: Amount) -> Self {
+        Self(to.0 - from.0)
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+
+    /// Formats the amount as a signed decimal string to exactly 4 decimal places
+    /// _eg. `-10.5000`_
+    ///
+    /// See [`Amount::to_exact_string`]
+    pub fn to_exact_string(self) -> String {
+        <Amount>::computed(self.0).to_exact_string()
+    }
+}
+
+impl Add<SignedAmount> for SignedAmount {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        SignedAmount(self.0 + rhs.0)
+    }
+}
+
+impl<const DP: u32> Default for Amount<DP> {
     fn default() -> Self {
-        Self(Decimal::ZERO)
+        Self::computed(Decimal::ZERO)
     }
 }
 
-impl Amount {
+impl<const DP: u32> Amount<DP> {
+    /// Wraps a decimal which was provided as is, eg. parsed from the input
+    //
+    // The numbered field syntax is required in order to `cfg` the provenance
+    #[allow(clippy::init_numbered_fields)]
+    fn input(value: Decimal) -> Self {
+        Self {
+            0: value,
+            #[cfg(feature = "amount_provenance")]
+            1: Provenance::Input,
+        }
+    }
+
+    /// Wraps a decimal which is the result of a calculation
+    #[allow(clippy::init_numbered_fields)]
+    fn computed(value: Decimal) -> Self {
+        Self {
+            0: value,
+            #[cfg(feature = "amount_provenance")]
+            1: Provenance::Computed,
+        }
+    }
+
+    /// Whether the amount was parsed from the input, or is the result of a calculation
+    ///
+    /// ```
+    /// use lib::amount::{Amount, Provenance};
+    ///
+    /// let amount: Amount = "1.5".parse().unwrap();
+    /// assert_eq!(amount.provenance(), Provenance::Input);
+    /// assert_eq!((amount + amount).provenance(), Provenance::Computed);
+    /// ```
+    #[cfg(feature = "amount_provenance")]
+    pub fn provenance(self) -> Provenance {
+        self.1
+    }
+
     pub fn round(self) -> Self {
-        Self(self.0.round_dp(PRECISION))
+        Self::computed(self.0.round_dp(DP))
+    }
+
+    /// Formats the amount as a decimal string to exactly its precision _eg. `10.5000` to 4
+    /// decimal places_
+    ///
+    /// This is deliberately an explicit method rather than an implementation of
+    /// [`Display`], the caller is responsible for ensuring the result doesn't end up in any logs.
+    ///
+    /// [`Display`]: std::fmt::Display
+    pub fn to_exact_string(self) -> String {
+        self.to_string_with_precision(DP)
+    }
+
+    /// Rounds the amount to the provided number of decimal places _using the bankers rounding
+    /// rule_, a precision greater than the amount's precision has no effect
+    pub fn round_to(self, dp: u32) -> Self {
+        Self::computed(self.0.round_dp(dp.min(DP)))
+    }
+
+    /// Formats the amount as a decimal string to exactly the provided number of decimal places
+    /// _(at most the amount's precision)_, eg. `10.50` for a precision of 2
+    ///
+    /// See [`Amount::to_exact_string`]
+    pub fn to_string_with_precision(self, dp: u32) -> String {
+        let dp = dp.min(DP);
+        let mut decimal = self.0.round_dp(dp);
+        decimal.rescale(dp);
+        decimal.to_string()
+    }
+
+    /// A lossy conversion to an `f64`, for use in statistical estimates where exactness isn't
+    /// required
+    pub(crate) fn to_f64_lossy(self) -> f64 {
+        self.0.to_f64().unwrap_or(f64::NAN)
+    }
+
+    /// A lossy conversion from an `f64`, rounding to the amount's precision. See
+    /// [`Amount::to_f64_lossy`]
+    pub(crate) fn from_f64_lossy(value: f64) -> Option<Self> {
+        Decimal::from_f64(value).map(|d| Self::computed(d).round())
+    }
+
+    /// Identifies whether the amount is exactly zero
+    pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// Identifies whether the amount is strictly greater than zero
+    pub fn is_positive(self) -> bool {
+        self.0.is_sign_positive() && !self.0.is_zero()
+    }
+
+    /// Adds the two amounts, returning `None` if the result would overflow
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self::computed)
+    }
+
+    /// Subtracts the two amounts, returning `None` if the result would overflow
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self::computed)
+    }
+
+    /// Returns the smaller of the two amounts
+    pub fn min(self, other: Self) -> Self {
+        Self::computed(self.0.min(other.0))
+    }
+
+    /// Returns the larger of the two amounts
+    pub fn max(self, other: Self) -> Self {
+        Self::computed(self.0.max(other.0))
+    }
+
+    /// Restricts the amount to be within the bounds of `lo` and `hi` _(inclusive)_
+    ///
+    /// ## Panics
+    ///
+    /// This function will panic if `lo` is greater than `hi`
+    pub fn clamp(self, lo: Self, hi: Self) -> Self {
+        Self::computed(self.0.clamp(lo.0, hi.0))
     }
 }
 
-impl Add<Amount> for Amount {
+impl Amount {
+    /// Constructs an amount from a [`Decimal`], rounding it to 4 decimal places _using the bankers
+    /// rounding rule_. This applies the same validation as parsing an amount from the input,
+    /// erroring with [`AmountParseError::Negative`] if the value is less than `0.0`
+    ///
+    /// ```
+    /// use lib::Amount;
+    /// use rust_decimal::Decimal;
+    ///
+    /// let amount = Amount::try_new(Decimal::new(103225, 5)).unwrap();
+    /// assert_eq!(amount.to_exact_string(), "1.0322");
+    /// assert!(Amount::try_new(Decimal::new(-1, 0)).is_err());
+    /// ```
+    ///
+    /// For other precisions use the equivalent [`TryFrom<Decimal>`] implementation.
+    pub fn try_new(value: Decimal) -> Result<Self, AmountParseError> {
+        Self::try_from(value)
+    }
+
+    /// Parses an amount from a decimal string in the same way as [`FromStr`], however any
+    /// oddly formatted value is rejected rather than normalized
+    ///
+    /// This errors with:
+    /// - [`AmountParseError::LeadingPlus`] for a leading `+` _(eg. `+1.00`)_
+    /// - [`AmountParseError::NegativeZero`] for a negative zero _(eg. `-0.0`)_
+    /// - [`AmountParseError::Whitespace`] for any leading or trailing whitespace
+    ///
+    /// ```
+    /// use lib::Amount;
+    /// use lib::error::AmountParseError;
+    ///
+    /// assert!(Amount::from_str_strict("1.00").is_ok());
+    /// assert_eq!(Amount::from_str_strict("+1.00").err(), Some(AmountParseError::LeadingPlus));
+    /// ```
+    pub fn from_str_strict(s: &str) -> Result<Self, AmountParseError> {
+        if s.trim() != s {
+            return Err(AmountParseError::Whitespace);
+        }
+        if s.starts_with('+') {
+            return Err(AmountParseError::LeadingPlus);
+        }
+        let decimal = parse_decimal(s)?;
+        if s.starts_with('-') && decimal.is_zero() {
+            return Err(AmountParseError::NegativeZero);
+        }
+        Self::try_from(decimal)
+    }
+
+    /// Converts an `f32` into an amount, erroring with [`AmountParseError::Inexact`] if the
+    /// value can't be represented exactly to 4 decimal places
+    ///
+    /// ```
+    /// use lib::Amount;
+    ///
+    /// assert!(Amount::try_from_f32_exact(1.25).is_ok());
+    /// assert!(Amount::try_from_f32_exact(0.1).is_err());
+    /// ```
+    pub fn try_from_f32_exact(value: f32) -> Result<Self, AmountParseError> {
+        let decimal = Decimal::from_f32_retain(value).ok_or(AmountParseError::Inexact)?;
+        if decimal.round_dp(PRECISION) != decimal {
+            return Err(AmountParseError::Inexact);
+        }
+        if decimal.is_sign_negative() && !decimal.is_zero() {
+            return Err(AmountParseError::Negative);
+        }
+        Ok(Self::input(decimal.normalize()))
+    }
+}
+
+impl<const DP: u32> Add<Amount<DP>> for Amount<DP> {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Amount(self.0 + rhs.0)
+        Self::computed(self.0 + rhs.0)
     }
 }
 
-impl AddAssign for Amount {
+impl<const DP: u32> AddAssign for Amount<DP> {
     fn add_assign(&mut self, rhs: Self) {
-        self.0 = self.0 + rhs.0
+        *self = *self + rhs
     }
 }
 
-impl Sub<Amount> for Amount {
+/// Sums the amounts starting from zero
+///
+/// ```
+/// use lib::Amount;
+///
+/// let deposits: Vec<Amount> = vec!["1.5".parse().unwrap(), "2.25".parse().unwrap()];
+/// let total = deposits.iter().copied().sum::<Amount>();
+/// assert_eq!(total.to_exact_string(), "3.7500");
+/// ```
+impl<const DP: u32> Sum for Amount<DP> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+impl<'a, const DP: u32> Sum<&'a Amount<DP>> for Amount<DP> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+impl<const DP: u32> Sub<Amount<DP>> for Amount<DP> {
     type Output = Self;
 
-    fn sub(self, rhs: Amount) -> Self::Output {
-        Amount(self.0 - rhs.0)
+    fn sub(self, rhs: Amount<DP>) -> Self::Output {
+        Self::computed(self.0 - rhs.0)
     }
 }
 
-impl SubAssign for Amount {
+impl<const DP: u32> SubAssign for Amount<DP> {
     fn sub_assign(&mut self, rhs: Self) {
-        self.0 = self.0 - rhs.0
+        *self = *self - rhs
     }
 }
 
-impl TryFrom<f32> for Amount {
-    type Error = rust_decimal::Error;
+/// Rounds the decimal to the amount's precision, erroring if it is less than `0.0`, see
+/// [`Amount::try_new`]
+impl<const DP: u32> TryFrom<Decimal> for Amount<DP> {
+    type Error = AmountParseError;
+
+    fn try_from(value: Decimal) -> Result<Self, Self::Error> {
+        let decimal = value.round_dp(DP);
+        if decimal.is_sign_negative() {
+            Err(AmountParseError::Negative)
+        } else {
+            Ok(Self::input(decimal))
+        }
+    }
+}
+
+/// **Deprecated:** this silently rounds away any imprecision inherent in the `f32`, prefer
+/// parsing a string or [`Amount::try_from_f32_exact`]
+impl<const DP: u32> TryFrom<f32> for Amount<DP> {
+    type Error = AmountParseError;
 
     fn try_from(value: f32) -> Result<Self, Self::Error> {
-        Ok(Self(Decimal::try_from(value)?).round())
+        match Decimal::try_from(value) {
+            Ok(decimal) => Ok(Self::input(decimal.round_dp(DP))),
+            Err(_) if value.is_finite() => Err(AmountParseError::Overflow),
+            Err(e) => Err(e.into()),
+        }
     }
 }
 
-impl TryInto<f32> for Amount {
+impl<const DP: u32> TryInto<f32> for Amount<DP> {
     type Error = rust_decimal::Error;
 
     fn try_into(self) -> Result<f32, Self::Error> {
@@ -121,26 +463,83 @@ impl TryInto<f32> for Amount {
     }
 }
 
-impl<'de> Deserialize<'de> for Amount {
+impl<const DP: u32> TryInto<f64> for Amount<DP> {
+    type Error = rust_decimal::Error;
+
+    fn try_into(self) -> Result<f64, Self::Error> {
+        self.0.try_into()
+    }
+}
+
+/// Parses an amount from a decimal string, rounding it to the amount's precision
+///
+/// ```
+/// use lib::Amount;
+///
+/// let amount: Amount = "10.5".parse().unwrap();
+/// assert_eq!(amount.to_exact_string(), "10.5000");
+/// ```
+impl<const DP: u32> FromStr for Amount<DP> {
+    type Err = AmountParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(parse_decimal(s)?)
+    }
+}
+
+/// Parses the string into a decimal without any loss of precision
+///
+/// [`rust_decimal`] only reports a string which is too large through the message of its error,
+/// so that case is instead identified from the string itself.
+fn parse_decimal(s: &str) -> Result<Decimal, AmountParseError> {
+    Decimal::from_str_exact(s).map_err(|e| match exceeds_max(s) {
+        true => AmountParseError::Overflow,
+        false => e.into(),
+    })
+}
+
+/// Whether the string is a plain decimal number whose integer part is larger than
+/// [`Decimal::MAX`]
+fn exceeds_max(s: &str) -> bool {
+    let unsigned = s.strip_prefix(&['-', '+'][..]).unwrap_or(s);
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if integer.is_empty()
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return false;
+    }
+    let integer = integer.trim_start_matches('0');
+    let max = Decimal::MAX.to_string();
+    (integer.len(), integer) > (max.len(), max.as_str())
+}
+
+impl<'de, const DP: u32> Deserialize<'de> for Amount<DP> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
         let bits = String::deserialize(deserializer)?;
-        let decimal = Decimal::from_str_exact(&bits)
-            .map(|d| d.round_dp(PRECISION))
-            .map_err(|e| Error::custom(&format!("{}", e)))?;
-
-        if decimal.is_sign_negative() {
-            Err(Error::custom(
-                "expected a value greater than or equal to 0.0",
-            ))
-        } else {
-            Ok(Amount(decimal))
-        }
+        bits.parse().map_err(Error::custom)
     }
 }
 
+/// Deserializes a balance which may be negative, eg. the available funds of a client whose
+/// deposit was disputed after it had been withdrawn
+///
+/// Unlike the [`Deserialize`] implementation of [`Amount`] a negative value isn't rejected, so
+/// this is only used to restore a balance which was previously calculated by the engine.
+pub(crate) fn deserialize_balance<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let bits = String::deserialize(deserializer)?;
+    let decimal = Decimal::from_str_exact(&bits).map_err(Error::custom)?;
+    Ok(Amount::computed(decimal.round_dp(PRECISION)))
+}
+
 #[cfg(test)]
 mod tests {
     use color_eyre::Result;
@@ -151,10 +550,15 @@ mod tests {
 
     impl Amount {
         pub fn new(num: f32) -> Result<Self> {
-            Ok(Amount(Decimal::try_from(num)?))
+            Ok(input(Decimal::try_from(num)?))
         }
     }
 
+    /// Wraps the decimal as is, without rounding it to the precision
+    fn input(value: Decimal) -> Amount {
+        Amount::input(value)
+    }
+
     // These tests deref Amount for the assertions, as we do not want Amount to implement [`fmt::Debug`]
     // however Decimal does. This implementation should be restricted to `cfg(test)`.
 
@@ -170,7 +574,7 @@ mod tests {
     fn correctly_deserializes_f32() -> Result<()> {
         let csv_row = csv::StringRecord::from(vec!["1.032"]);
         let amount: Amount = csv_row.deserialize(None)?;
-        assert_eq!(*amount, *Amount(Decimal::new(1032, 3)));
+        assert_eq!(*amount, *input(Decimal::new(1032, 3)));
         Ok(())
     }
 
@@ -178,7 +582,7 @@ mod tests {
     fn automatically_rounds_to_4_dp_when_deserializing() -> Result<()> {
         let csv_row = csv::StringRecord::from(vec!["1.03235"]);
         let amount: Amount = csv_row.deserialize(None)?;
-        assert_eq!(*amount, *Amount(Decimal::new(10324, 4)));
+        assert_eq!(*amount, *input(Decimal::new(10324, 4)));
         Ok(())
     }
 
@@ -186,7 +590,84 @@ mod tests {
     fn follows_bankers_rounding() -> Result<()> {
         let csv_row = csv::StringRecord::from(vec!["1.03225"]);
         let amount: Amount = csv_row.deserialize(None)?;
-        assert_eq!(*amount, *Amount(Decimal::new(10322, 4)));
+        assert_eq!(*amount, *input(Decimal::new(10322, 4)));
+        Ok(())
+    }
+
+    #[test]
+    fn constructs_from_a_decimal_in_the_same_way_as_deserializing() -> Result<()> {
+        let csv_row = csv::StringRecord::from(vec!["1.03225"]);
+        let deserialized: Amount = csv_row.deserialize(None)?;
+        let amount = Amount::try_new(Decimal::new(103225, 5))?;
+        assert_eq!(*amount, *deserialized);
+        assert_eq!(
+            Amount::try_new(Decimal::new(-1032, 3)).err(),
+            Some(AmountParseError::Negative)
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "debug_amounts")]
+    #[test]
+    fn redacts_the_value_when_debug_formatting() -> Result<()> {
+        let amount: Amount = "1.03235".parse()?;
+        assert_eq!(format!("{:?}", amount), "Amount(****)");
+        assert_eq!(format!("{:?}", Some(amount)), "Some(Amount(****))");
+        Ok(())
+    }
+
+    #[test]
+    fn respects_a_configured_precision() -> Result<()> {
+        let csv_row = csv::StringRecord::from(vec!["1.032250005"]);
+        let amount: Amount<8> = csv_row.deserialize(None)?;
+        assert_eq!(amount.to_exact_string(), "1.03225000");
+        assert_eq!(amount.round().to_exact_string(), "1.03225000");
+
+        let amount = Amount::<8>::try_from(Decimal::new(1032250015, 9))?;
+        assert_eq!(amount.to_exact_string(), "1.03225002");
+        let amount = Amount::<8>::try_from(0.5f32)?;
+        assert_eq!(amount.to_exact_string(), "0.50000000");
+
+        let default: Amount = csv_row.deserialize(None)?;
+        assert_eq!(default.to_exact_string(), "1.0323");
+        Ok(())
+    }
+
+    #[test]
+    fn sums_amounts_from_zero() -> Result<()> {
+        let amounts = ["1.0001", "2.5", "0.4999"]
+            .iter()
+            .map(|a| a.parse::<Amount>())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(*amounts.iter().sum::<Amount>(), Decimal::new(40000, 4));
+        assert_eq!(*amounts.into_iter().sum::<Amount>(), Decimal::new(40000, 4));
+        assert_eq!(*std::iter::empty::<Amount>().sum::<Amount>(), Decimal::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_oddly_formatted_amounts_when_parsing_strictly() -> Result<()> {
+        assert_eq!(
+            Amount::from_str_strict("+1.00").err(),
+            Some(AmountParseError::LeadingPlus)
+        );
+        assert_eq!(
+            Amount::from_str_strict("-0.0").err(),
+            Some(AmountParseError::NegativeZero)
+        );
+        assert_eq!(
+            Amount::from_str_strict("  1.0 ").err(),
+            Some(AmountParseError::Whitespace)
+        );
+        assert_eq!(
+            Amount::from_str_strict("-1.0").err(),
+            Some(AmountParseError::Negative)
+        );
+        assert_eq!(*Amount::from_str_strict("1.03225")?, Decimal::new(10322, 4));
+        assert!(
+            "+1.00".parse::<Amount>().is_ok(),
+            "the default parsing should remain lenient"
+        );
         Ok(())
     }
 
@@ -198,11 +679,80 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn rejects_an_f32_which_is_not_exact_to_4_dp() -> Result<()> {
+        assert_eq!(
+            Amount::try_from_f32_exact(0.1).err(),
+            Some(AmountParseError::Inexact)
+        );
+        assert_eq!(
+            Amount::try_from_f32_exact(f32::NAN).err(),
+            Some(AmountParseError::Inexact)
+        );
+        assert_eq!(
+            *Amount::try_from_f32_exact(0.125)?,
+            *input(Decimal::new(125, 3))
+        );
+        assert_eq!(
+            *Amount::try_from_f32_exact(10.0)?,
+            *input(Decimal::new(10, 0))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parses_from_a_string() -> Result<()> {
+        assert_eq!(*"0.1".parse::<Amount>()?, *input(Decimal::new(1, 1)));
+        assert_eq!(
+            *"1.03235".parse::<Amount>()?,
+            *input(Decimal::new(10324, 4))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reports_why_an_amount_failed_to_parse() -> Result<()> {
+        assert_eq!(
+            "-1".parse::<Amount>().err(),
+            Some(AmountParseError::Negative)
+        );
+        assert!(matches!(
+            "abc".parse::<Amount>(),
+            Err(AmountParseError::Invalid(_))
+        ));
+        assert!(matches!(
+            "1e5".parse::<Amount>(),
+            Err(AmountParseError::Invalid(_))
+        ));
+        assert_eq!(
+            "792281625142643375935439503350".parse::<Amount>().err(),
+            Some(AmountParseError::Overflow)
+        );
+        assert_eq!(
+            "79228162514264337593543950336.0".parse::<Amount>().err(),
+            Some(AmountParseError::Overflow)
+        );
+        assert!("0079228162514264337593543950335".parse::<Amount>().is_ok());
+        assert_eq!(
+            <Amount>::try_from(1e30f32).err(),
+            Some(AmountParseError::Overflow)
+        );
+        assert!(matches!(
+            <Amount>::try_from(f32::NAN),
+            Err(AmountParseError::Invalid(_))
+        ));
+        assert_eq!(
+            Amount::try_from_f32_exact(-1.0).err(),
+            Some(AmountParseError::Negative)
+        );
+        Ok(())
+    }
+
     #[test]
     fn correctly_carries_out_add_operations() -> Result<()> {
-        let lhs = Amount(Decimal::new(10234, 4));
-        let rhs = Amount(Decimal::new(30923, 4));
-        let expected = Amount(Decimal::new(41157, 4));
+        let lhs = input(Decimal::new(10234, 4));
+        let rhs = input(Decimal::new(30923, 4));
+        let expected = input(Decimal::new(41157, 4));
         let result = lhs + rhs;
         let mut assign_result = lhs;
         assign_result += rhs;
@@ -221,9 +771,9 @@ mod tests {
 
     #[test]
     fn correctly_carries_out_subtraction_operations() -> Result<()> {
-        let lhs = Amount(Decimal::new(30923, 4));
-        let rhs = Amount(Decimal::new(10234, 4));
-        let expected = Amount(Decimal::new(20689, 4));
+        let lhs = input(Decimal::new(30923, 4));
+        let rhs = input(Decimal::new(10234, 4));
+        let expected = input(Decimal::new(20689, 4));
         let result = lhs - rhs;
         let mut assign_result = lhs;
         assign_result -= rhs;
@@ -240,10 +790,99 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn formats_to_an_exact_4_dp_string() -> Result<()> {
+        assert_eq!(input(Decimal::new(105, 1)).to_exact_string(), "10.5000");
+        assert_eq!(input(Decimal::new(103235, 5)).to_exact_string(), "1.0324");
+        assert_eq!(<Amount>::default().to_exact_string(), "0.0000");
+        Ok(())
+    }
+
+    #[test]
+    fn identifies_zero_and_positive_amounts() -> Result<()> {
+        let zero = <Amount>::default();
+        assert!(zero.is_zero());
+        assert!(!zero.is_positive());
+        let rounded_to_zero = "0.00001".parse::<Amount>()?;
+        assert!(rounded_to_zero.is_zero());
+
+        let amount = Amount::new(0.0001)?;
+        assert!(!amount.is_zero());
+        assert!(amount.is_positive());
+        assert!((amount - amount).is_zero());
+        assert!(!(zero - amount).is_positive());
+        Ok(())
+    }
+
+    #[test]
+    fn checked_add_returns_none_on_overflow() -> Result<()> {
+        let max = input(Decimal::MAX);
+        assert!(max.checked_add(Amount::new(1.0)?).is_none());
+        let sum = Amount::new(1.5)?
+            .checked_add(Amount::new(2.25)?)
+            .expect("should not overflow");
+        assert_eq!(*sum, *Amount::new(3.75)?);
+        Ok(())
+    }
+
+    #[test]
+    fn min_returns_the_smaller_amount() -> Result<()> {
+        let lhs = input(Decimal::new(10234, 4));
+        let rhs = input(Decimal::new(30923, 4));
+        assert_eq!(*lhs.min(rhs), *lhs);
+        assert_eq!(*rhs.min(lhs), *lhs);
+        assert_eq!(
+            *lhs.min(lhs),
+            *lhs,
+            "equal amounts should return the same amount"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn max_returns_the_larger_amount() -> Result<()> {
+        let lhs = input(Decimal::new(10234, 4));
+        let rhs = input(Decimal::new(30923, 4));
+        assert_eq!(*lhs.max(rhs), *rhs);
+        assert_eq!(*rhs.max(lhs), *rhs);
+        assert_eq!(
+            *rhs.max(rhs),
+            *rhs,
+            "equal amounts should return the same amount"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn clamp_restricts_the_amount_to_the_bounds() -> Result<()> {
+        let lo = input(Decimal::new(10000, 4));
+        let hi = input(Decimal::new(50000, 4));
+        let within = input(Decimal::new(25000, 4));
+        assert_eq!(*input(Decimal::new(5000, 4)).clamp(lo, hi), *lo);
+        assert_eq!(*input(Decimal::new(90000, 4)).clamp(lo, hi), *hi);
+        assert_eq!(*within.clamp(lo, hi), *within);
+        assert_eq!(*lo.clamp(lo, hi), *lo, "the lower bound is inclusive");
+        assert_eq!(*hi.clamp(lo, hi), *hi, "the upper bound is inclusive");
+        assert_eq!(
+            *within.clamp(lo, lo),
+            *lo,
+            "equal bounds should always return the bound"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn clamp_panics_if_the_bounds_are_inverted() {
+        let lo = input(Decimal::new(10000, 4));
+        let hi = input(Decimal::new(50000, 4));
+        lo.clamp(hi, lo);
+    }
+
     #[test]
     fn its_safe_to_coerce_max_decimal_to_f32() -> Result<()> {
         let dec = Decimal::MAX;
-        let amt = Amount(dec);
+        let amt = input(dec);
         let _float: f32 = amt.try_into()?;
         Ok(())
     }
@@ -251,8 +890,36 @@ mod tests {
     #[test]
     fn its_safe_to_coerce_min_decimal_to_f32() -> Result<()> {
         let dec = Decimal::MIN;
-        let amt = Amount(dec);
+        let amt = input(dec);
         let _float: f32 = amt.try_into()?;
         Ok(())
     }
+
+    #[test]
+    fn its_safe_to_coerce_max_decimal_to_f64() -> Result<()> {
+        let dec = Decimal::MAX;
+        let amt = input(dec);
+        let _float: f64 = amt.try_into()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "amount_provenance")]
+    #[test]
+    fn tracks_whether_an_amount_came_from_the_input() -> Result<()> {
+        let csv_row = csv::StringRecord::from(vec!["1.5"]);
+        let amount: Amount = csv_row.deserialize(None)?;
+        assert_eq!(amount.provenance(), Provenance::Input);
+
+        let sum = amount + amount;
+        assert_eq!(sum.provenance(), Provenance::Computed);
+        assert!(
+            sum == input(Decimal::new(3, 0)),
+            "provenance should not affect equality"
+        );
+
+        let mut balance = amount;
+        balance -= amount;
+        assert_eq!(balance.provenance(), Provenance::Computed);
+        Ok(())
+    }
 }
@@ -0,0 +1,197 @@
+//! An opt-in cache of processed results, keyed by a hash of the input
+//!
+//! A service which repeatedly processes identical inputs can use [`process_cached`] to skip the
+//! processing entirely on a cache hit, instead writing the output which was previously computed
+//! for that input.
+//!
+//! The input is identified by the SHA-256 hash of its **exact bytes**, so any difference at all
+//! _(eg. whitespace, line endings or the order of the rows)_ is treated as a different input,
+//! even if it would produce the same balances. Similarly, the cached output was produced by
+//! whichever engine processed the input first, so a cache shouldn't be shared between engines
+//! that are configured differently.
+//!
+//! ```
+//! use lib::cache::{process_cached, InMemoryCache};
+//! use lib::engines::BasicEngine;
+//!
+//! let input = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+//! let mut cache = InMemoryCache::default();
+//!
+//! let mut first = vec![];
+//! let run = process_cached(input.as_bytes(), BasicEngine::default(), &mut cache, &mut first)?;
+//! assert!(!run.hit);
+//!
+//! let mut second = vec![];
+//! let run = process_cached(input.as_bytes(), BasicEngine::default(), &mut cache, &mut second)?;
+//! assert!(run.hit);
+//! assert_eq!(first, second);
+//! # Ok::<(), color_eyre::Report>(())
+//! ```
+
+use color_eyre::Result;
+use fnv::FnvHashMap;
+use sha2::{Digest, Sha256};
+
+use std::io::{Read, Write};
+
+use crate::input::{self, InputOptions};
+use crate::SyncEngine;
+
+/// The SHA-256 hash of an input
+pub type InputHash = [u8; 32];
+
+/// A store of previously computed outputs, keyed by the hash of the input which produced them
+///
+/// [`InMemoryCache`] is provided as a default, however this can be implemented over any
+/// external store _(eg. Redis)_ to share results between processes.
+pub trait ResultCache {
+    /// Returns the output previously stored for the input, if there is one
+    fn get(&self, hash: &InputHash) -> Option<Vec<u8>>;
+
+    /// Stores the output computed for the input
+    fn put(&mut self, hash: InputHash, output: Vec<u8>);
+}
+
+/// A [`ResultCache`] which holds every output in memory for the lifetime of the cache
+#[derive(Debug, Default, Clone)]
+pub struct InMemoryCache {
+    outputs: FnvHashMap<InputHash, Vec<u8>>,
+}
+
+impl InMemoryCache {
+    /// The number of outputs currently cached
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+}
+
+impl ResultCache for InMemoryCache {
+    fn get(&self, hash: &InputHash) -> Option<Vec<u8>> {
+        self.outputs.get(hash).cloned()
+    }
+
+    fn put(&mut self, hash: InputHash, output: Vec<u8>) {
+        self.outputs.insert(hash, output);
+    }
+}
+
+/// The result of [`process_cached`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachedRun {
+    /// Whether the output was taken from the cache
+    pub hit: bool,
+    /// The number of rows which were processed by the engine, this is always `0` on a cache hit
+    pub processed: usize,
+}
+
+/// Hashes the exact bytes of the input
+pub fn hash_input(input: &[u8]) -> InputHash {
+    Sha256::digest(input).into()
+}
+
+/// Reads the csv input with the default [`InputOptions`], writing the output of processing it to
+/// the `writer`
+///
+/// If the `cache` already holds an output for this exact input, that output is written instead
+/// and the `engine` is left unused. Otherwise the input is processed by the `engine` and its
+/// output is stored in the `cache` before being written.
+///
+/// _Note:_ the entire input is read into memory, as it must be hashed before it's processed.
+pub fn process_cached<E: SyncEngine>(
+    mut input: impl Read,
+    mut engine: E,
+    cache: &mut impl ResultCache,
+    mut writer: impl Write,
+) -> Result<CachedRun> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+    let hash = hash_input(&bytes);
+
+    if let Some(output) = cache.get(&hash) {
+        writer.write_all(&output)?;
+        return Ok(CachedRun {
+            hit: true,
+            processed: 0,
+        });
+    }
+
+    let options = InputOptions::default();
+    let reader = options.reader(&*bytes);
+    let mut processed = 0;
+    engine.process(input::deserialize(reader, &options)?.inspect(|_| processed += 1))?;
+
+    let mut output = vec![];
+    engine.output(&mut output)?;
+    writer.write_all(&output)?;
+    cache.put(hash, output);
+    Ok(CachedRun {
+        hit: false,
+        processed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::engines::BasicEngine;
+
+    const INPUT: &str = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n";
+
+    #[test]
+    fn reuses_the_output_of_an_identical_input() -> Result<()> {
+        let mut cache = InMemoryCache::default();
+
+        let mut first = vec![];
+        let run = process_cached(
+            INPUT.as_bytes(),
+            BasicEngine::default(),
+            &mut cache,
+            &mut first,
+        )?;
+        assert_eq!(
+            run,
+            CachedRun {
+                hit: false,
+                processed: 2
+            }
+        );
+
+        let mut second = vec![];
+        let run = process_cached(
+            INPUT.as_bytes(),
+            BasicEngine::default(),
+            &mut cache,
+            &mut second,
+        )?;
+        assert_eq!(
+            run,
+            CachedRun {
+                hit: true,
+                processed: 0
+            }
+        );
+        assert_eq!(first, second);
+        assert_eq!(cache.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn only_matches_the_exact_bytes_of_the_input() -> Result<()> {
+        let mut cache = InMemoryCache::default();
+        process_cached(INPUT.as_bytes(), BasicEngine::default(), &mut cache, vec![])?;
+
+        // The same transactions with different whitespace are a different input
+        let input = INPUT.replace(',', ", ");
+        let run = process_cached(input.as_bytes(), BasicEngine::default(), &mut cache, vec![])?;
+        assert!(!run.hit);
+        assert_eq!(cache.len(), 2);
+        Ok(())
+    }
+}
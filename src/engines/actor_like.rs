@@ -7,6 +7,17 @@
 //! has it's own functionality to carry out, and a mailbox to receive messages to carry out said
 //! task.
 //!
+//! ## Backpressure
+//!
+//! By default each client's mailbox is unbounded, so a client receiving a flood of transactions
+//! buffers all of them in memory until its task catches up. An engine created with
+//! [`ActorLikeEngine::with_mailbox_capacity`] instead bounds each mailbox, so
+//! [`AsyncEngine::publish_transaction`] waits until the client's mailbox has room.
+//!
+//! As [`AsyncEngine::process`] publishes each row in turn, a full mailbox pauses reading the
+//! input for **every** client until that client's task has made room, not just for the client
+//! whose mailbox is full.
+//!
 //! # Examples
 //!
 //! ```
@@ -29,26 +40,43 @@
 //! ```
 
 use async_trait::async_trait;
-use color_eyre::{eyre::eyre, Result};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
 use fnv::FnvHashMap;
-use futures::future::join_all;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
-use tokio::task::{self, JoinHandle};
+use tokio::sync::mpsc::{
+    channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender,
+};
+use tokio::task::JoinSet;
 use tracing::{error, warn};
 
 use std::io::Write;
-use std::mem;
 
+use crate::error::TransactionError;
+use crate::output::{OutputOptions, StreamingOutput};
 use crate::storage::{Client, ClientStorage};
-use crate::transaction::IncomingTransaction;
+use crate::transaction::{IncomingTransaction, TransactionType};
 
 use super::AsyncEngine;
 
 /// An aysnc implementation which processes each client independently
 ///
-/// Behind the scenes it creates a [`tokio::task`] for each client. Any csv row associated
+/// Behind the scenes it spawns a task onto a [`JoinSet`] for each client. Any csv row associated
 /// with that client is then sent to the task through a channel.
 ///
+/// The tasks are reaped as they complete once the output is written, so any task which panicked
+/// is surfaced as soon as it's reached rather than once every task has finished.
+///
+/// A client's task stops once its account is frozen _(or an unrecoverable error occurs)_, after
+/// which any further transactions for the client are dropped. These are counted rather than
+/// silently discarded, see [`ActorLikeEngine::output_with_dropped_count`].
+///
+/// As no task holds more than one client, a [`TransactionType::Transfer`] is rejected with
+/// [`TransactionError::UnsupportedTransfer`]. No handlers can be registered with the client of
+/// each task, so a custom transaction type is skipped as a
+/// [`TransactionError::UnknownTransactionType`].
+///
 /// This is a lightweight simplified interpretation of the `actor` pattern.
 ///
 /// In reality given the lack of compute required by each task coupled with the lack of network
@@ -56,25 +84,157 @@ use super::AsyncEngine;
 /// introduced we should quickly start to see the benefits.
 #[derive(Default)]
 pub struct ActorLikeEngine {
-    join_handles: Vec<JoinHandle<Client>>,
-    channels: FnvHashMap<u16, UnboundedSender<IncomingTransaction>>,
+    /// Each task returns its client along with the number of transactions it dropped
+    tasks: JoinSet<(Client, usize)>,
+    channels: FnvHashMap<u16, Mailbox>,
+    mailbox_capacity: Option<usize>,
+    /// The number of transactions which couldn't be sent as the client's task had already stopped
+    dropped: usize,
+}
+
+/// The sending half of a client's mailbox
+enum Mailbox {
+    Unbounded(UnboundedSender<IncomingTransaction>),
+    Bounded(Sender<IncomingTransaction>),
+}
+
+/// The receiving half of a client's mailbox, which is owned by the client's task
+enum Inbox {
+    Unbounded(UnboundedReceiver<IncomingTransaction>),
+    Bounded(Receiver<IncomingTransaction>),
+}
+
+impl Mailbox {
+    fn new(capacity: Option<usize>) -> (Self, Inbox) {
+        match capacity {
+            Some(capacity) => {
+                let (s, r) = channel(capacity);
+                (Self::Bounded(s), Inbox::Bounded(r))
+            }
+            None => {
+                let (s, r) = unbounded_channel();
+                (Self::Unbounded(s), Inbox::Unbounded(r))
+            }
+        }
+    }
+
+    /// Sends the transaction, waiting for the mailbox to have room if it's bounded
+    ///
+    /// Returns the transaction if the client's task has stopped
+    async fn send(&self, transaction: IncomingTransaction) -> Option<IncomingTransaction> {
+        match self {
+            Self::Unbounded(s) => s.send(transaction).err().map(|e| e.0),
+            Self::Bounded(s) => s.send(transaction).await.err().map(|e| e.0),
+        }
+    }
+}
+
+impl Inbox {
+    async fn recv(&mut self) -> Option<IncomingTransaction> {
+        match self {
+            Self::Unbounded(r) => r.recv().await,
+            Self::Bounded(r) => r.recv().await,
+        }
+    }
+
+    fn close(&mut self) {
+        match self {
+            Self::Unbounded(r) => r.close(),
+            Self::Bounded(r) => r.close(),
+        }
+    }
+}
+
+impl ActorLikeEngine {
+    /// Creates a new engine where each client's mailbox holds at most `capacity` transactions
+    /// _(at least 1)_ which are waiting to be processed
+    ///
+    /// Once a client's mailbox is full, publishing a transaction to it waits until the client's
+    /// task has made room. See the [module level docs](self#backpressure) for how this affects
+    /// [`AsyncEngine::process`].
+    pub fn with_mailbox_capacity(capacity: usize) -> Self {
+        Self {
+            mailbox_capacity: Some(capacity.max(1)),
+            ..Default::default()
+        }
+    }
+
+    /// Identifies whether a task has been spawned for the client with the provided id
+    pub fn has_task(&self, id: u16) -> bool {
+        self.channels.contains_key(&id)
+    }
+
+    /// The same as [`AsyncEngine::output_with_options`], however the number of transactions
+    /// which were dropped as their client's task had already stopped is also returned
+    pub async fn output_with_dropped_count(
+        mut self,
+        writer: impl Write + Send + Sync,
+        options: OutputOptions,
+    ) -> Result<usize> {
+        // Close the channels
+        self.channels.clear();
+
+        // Finish up the tasks, writing each client as soon as its task completes unless they
+        // have to be sorted
+        let sort = options.sort_by_id;
+        let mut dropped = self.dropped;
+        let mut clients = vec![];
+        let mut writer = StreamingOutput::with_options(writer, options);
+        while let Some(output) = self.tasks.join_next().await {
+            let (client, discarded) =
+                output.wrap_err("a client task failed to run to completion")?;
+            dropped += discarded;
+            if sort {
+                clients.push(client);
+            } else {
+                writer.write_client(&client)?;
+            }
+        }
+        writer.write_clients(&clients)?;
+        writer.finish()?;
+        Ok(dropped)
+    }
+
+    async fn send(&mut self, transaction: IncomingTransaction) {
+        let client_id = transaction.client;
+        if let Some(c) = self.channels.get(&client_id) {
+            if c.send(transaction).await.is_some() {
+                warn!(
+                    "dropping transaction for client {} as its task has stopped processing",
+                    client_id
+                );
+                self.dropped += 1;
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl AsyncEngine for ActorLikeEngine {
     async fn publish_transaction(&mut self, transaction: IncomingTransaction) -> Result<()> {
+        if transaction.ty == TransactionType::Heartbeat {
+            return Ok(());
+        }
+        if transaction.ty == TransactionType::Transfer {
+            return Err(TransactionError::UnsupportedTransfer { tx: transaction.tx }.into());
+        }
         let client_id = transaction.client;
-        if let Some(c) = self.channels.get(&client_id) {
-            c.send(transaction).ok();
+        if self.channels.contains_key(&client_id) {
+            self.send(transaction).await;
             return Ok(());
         }
-        let (tx, mut rx) = unbounded_channel();
+        let (tx, mut rx) = Mailbox::new(self.mailbox_capacity);
         let cli = Client::new(client_id);
-        let handle = task::spawn(async move {
+        self.tasks.spawn(async move {
             let mut cli = cli;
             'process: while let Some(trx) = rx.recv().await {
                 let IncomingTransaction { ty, tx, amount, .. } = trx;
                 if let Err(e) = cli.process_transaction(tx, ty, amount) {
+                    if e.downcast_ref::<TransactionError>()
+                        .is_some_and(TransactionError::is_recoverable)
+                    {
+                        continue 'process;
+                    }
                     warn!(error = %e, "stopping processing for client {}", cli.id);
                     // If we have an error we have either had:
                     // - An unexpected, unrecoverable error
@@ -84,12 +244,17 @@ impl AsyncEngine for ActorLikeEngine {
                     break 'process;
                 }
             }
-            cli
+            // Any transactions which were already queued for the client are dropped
+            rx.close();
+            let mut dropped = 0;
+            while rx.recv().await.is_some() {
+                dropped += 1;
+            }
+            (cli, dropped)
         });
         self.channels.insert(client_id, tx);
-        self.join_handles.push(handle);
-        if let Some(c) = self.channels.get(&client_id) {
-            c.send(transaction).ok();
+        if self.channels.contains_key(&client_id) {
+            self.send(transaction).await;
         } else {
             error!(
                 "somehow failed to add the channel and join handle for client {}",
@@ -100,18 +265,207 @@ impl AsyncEngine for ActorLikeEngine {
         Ok(())
     }
 
-    /// Outputs the current state of the clients to the provided writer
-    async fn output(mut self, writer: impl Write + Send + Sync) -> Result<()> {
-        // Close the channels
-        self.channels.clear();
+    /// Outputs the current state of the clients to the provided writer, logging the number of
+    /// transactions which were dropped as their client's task had already stopped
+    async fn output_with_options(
+        self,
+        writer: impl Write + Send + Sync,
+        options: OutputOptions,
+    ) -> Result<()> {
+        let dropped = self.output_with_dropped_count(writer, options).await?;
+        if dropped > 0 {
+            warn!(
+                dropped,
+                "transactions were dropped as their client had stopped processing"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Amount;
+
+    #[tokio::test]
+    async fn has_a_task_once_a_client_has_transacted() -> Result<()> {
+        let mut engine = ActorLikeEngine::default();
+        assert!(!engine.has_task(1));
+        engine
+            .publish_transaction(IncomingTransaction {
+                ty: TransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(Amount::new(1.0)?),
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: None,
+            })
+            .await?;
+        assert!(engine.has_task(1));
+        assert!(!engine.has_task(2));
+        Ok(())
+    }
 
-        // Finish up the tasks
-        let clients = join_all(mem::take(&mut self.join_handles)).await;
-        let mut writer = csv::Writer::from_writer(writer);
-        for client in clients {
-            writer.serialize(client?)?;
+    #[tokio::test]
+    async fn rejects_transfers_between_clients() -> Result<()> {
+        let mut engine = ActorLikeEngine::default();
+        let err = engine
+            .publish_transaction(IncomingTransaction {
+                ty: TransactionType::Transfer,
+                client: 1,
+                tx: 1,
+                amount: Some(Amount::new(1.0)?),
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: Some(2),
+            })
+            .await
+            .expect_err("the transfer should be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::UnsupportedTransfer { tx: 1 })
+        );
+        assert!(!engine.has_task(1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn skips_unknown_transaction_types() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndepost,1,2,1.0\nwithdrawal,1,3,1.0\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        let mut engine = ActorLikeEngine::default();
+        engine
+            .process(reader.deserialize::<IncomingTransaction>())
+            .await?;
+
+        let mut result = vec![];
+        let dropped = engine
+            .output_with_dropped_count(&mut result, OutputOptions::default())
+            .await?;
+        assert_eq!(dropped, 0, "the client should carry on processing");
+        assert_eq!(
+            String::from_utf8(result)?,
+            "client,available,held,total,locked\n1,4.0000,0.0000,4.0000,false\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_the_index_of_each_row_which_was_skipped() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,x,2,1.0\ndeposit,2,3,1.0\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        let mut engine = ActorLikeEngine::default();
+        let report = engine
+            .process_lenient(reader.deserialize::<IncomingTransaction>())
+            .await;
+        assert_eq!(report.processed, 2);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].0, 1);
+        assert!(engine.has_task(1) && engine.has_task(2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn counts_the_transactions_dropped_once_a_client_has_stopped() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\nchargeback,1,1,\ndeposit,1,2,1.0\ndeposit,2,3,1.0\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let mut engine = ActorLikeEngine::default();
+        engine
+            .process(reader.deserialize::<IncomingTransaction>())
+            .await?;
+        // Once client 1's task has stopped the deposit is rejected when it's sent, however if the
+        // task is still running it's dropped from the queue instead, either way it's counted
+        tokio::task::yield_now().await;
+        engine
+            .publish_transaction(IncomingTransaction {
+                ty: TransactionType::Deposit,
+                client: 1,
+                tx: 4,
+                amount: Some(Amount::new(1.0)?),
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: None,
+            })
+            .await?;
+
+        let dropped = engine
+            .output_with_dropped_count(vec![], OutputOptions::default())
+            .await?;
+        assert_eq!(dropped, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn waits_for_room_in_a_full_mailbox() -> Result<()> {
+        let mut engine = ActorLikeEngine::with_mailbox_capacity(1);
+        for tx in 1..=100 {
+            engine
+                .publish_transaction(IncomingTransaction {
+                    ty: TransactionType::Deposit,
+                    client: 1,
+                    tx,
+                    amount: Some(Amount::new(1.0)?),
+                    timestamp: None,
+                    row_id: None,
+                    reason: None,
+                    dest: None,
+                })
+                .await?;
+        }
+
+        let mut result = vec![];
+        let dropped = engine
+            .output_with_dropped_count(&mut result, OutputOptions::default())
+            .await?;
+        assert_eq!(dropped, 0);
+        let record = csv::Reader::from_reader(&*result)
+            .records()
+            .next()
+            .expect("client 1 should be output")?;
+        assert_eq!(&record[1], "100.0000");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn collects_every_client_from_their_tasks() -> Result<()> {
+        let mut engine = ActorLikeEngine::default();
+        for client in 1..=50 {
+            engine
+                .publish_transaction(IncomingTransaction {
+                    ty: TransactionType::Deposit,
+                    client,
+                    tx: client as u32,
+                    amount: Some(Amount::new(1.0)?),
+                    timestamp: None,
+                    row_id: None,
+                    reason: None,
+                    dest: None,
+                })
+                .await?;
         }
-        writer.flush()?;
+        assert_eq!(engine.tasks.len(), 50);
+
+        let mut result = vec![];
+        engine.output(&mut result).await?;
+        let mut clients = csv::Reader::from_reader(&*result)
+            .records()
+            .map(|r| Ok(r?[0].parse::<u16>()?))
+            .collect::<Result<Vec<_>>>()?;
+        clients.sort_unstable();
+        assert_eq!(clients, (1..=50).collect::<Vec<_>>());
         Ok(())
     }
 }
@@ -0,0 +1,238 @@
+//! A wrapper around [`BasicEngine`] which additionally records a per-transaction audit trail.
+//!
+//! As each transaction is applied, a csv row of
+//! `client,tx,type,available_after,held_after,reason` is written to the audit writer, showing
+//! the client's balances once that transaction had been processed. Transactions which are
+//! skipped _(eg. a withdrawal with insufficient funds)_ are still audited, with their balances
+//! left unchanged. The `reason` is only populated for a dispute which provided one.
+//!
+//! If the input has a row id column _(see
+//! [`InputOptions::row_id`](crate::input::InputOptions::row_id))_, a `row_id` column can be
+//! added before the `reason` with [`AuditingEngine::with_row_id`].
+//!
+//! # Examples
+//!
+//! ```
+//! use lib::SyncEngine;
+//! use lib::transaction::IncomingTransaction;
+//! use lib::engines::{AuditingEngine, BasicEngine};
+//! use csv::{ReaderBuilder, Trim};
+//! use std::io;
+//!
+//! let mut reader = ReaderBuilder::new()
+//!     .trim(Trim::All)
+//!     .from_path("./test_assets/simple/spec.csv")
+//!     .unwrap();
+//! let mut engine = AuditingEngine::new(BasicEngine::default(), io::stderr());
+//! engine.process(reader.deserialize::<IncomingTransaction>()).unwrap();
+//! engine.output(io::stdout()).unwrap();
+//! ```
+
+use color_eyre::Result;
+use serde::Serialize;
+
+use std::io::Write;
+
+use crate::engines::BasicEngine;
+use crate::output::{OutputOptions, StreamingOutput};
+use crate::transaction::{IncomingTransaction, TransactionType};
+
+use super::SyncEngine;
+
+/// A [`BasicEngine`] which writes an audit row to the provided writer for every transaction it
+/// processes
+pub struct AuditingEngine<W: Write> {
+    engine: BasicEngine,
+    audit: StreamingOutput<W>,
+    with_row_id: bool,
+}
+
+/// A single row of the audit trail
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    client: u16,
+    tx: u32,
+    #[serde(rename = "type")]
+    ty: &'a str,
+    available_after: String,
+    held_after: String,
+    /// Only `None` when the `row_id` column is omitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    row_id: Option<Option<String>>,
+    reason: Option<String>,
+}
+
+impl<W: Write> AuditingEngine<W> {
+    pub fn new(engine: BasicEngine, audit: W) -> Self {
+        Self {
+            engine,
+            audit: StreamingOutput::new(audit),
+            with_row_id: false,
+        }
+    }
+
+    /// Adds a `row_id` column to the audit trail, holding the
+    /// [`IncomingTransaction::row_id`] of each transaction
+    ///
+    /// This should be set when the input is read with
+    /// [`InputOptions::row_id`](crate::input::InputOptions::row_id).
+    pub fn with_row_id(mut self) -> Self {
+        self.with_row_id = true;
+        self
+    }
+
+    /// Flushes the audit trail, returning the wrapped [`BasicEngine`]
+    pub fn into_inner(mut self) -> Result<BasicEngine> {
+        self.audit.flush()?;
+        Ok(self.engine)
+    }
+}
+
+impl<W: Write> SyncEngine for AuditingEngine<W> {
+    fn publish_transaction(&mut self, transaction: IncomingTransaction) -> Result<()> {
+        let (client_id, tx, ty, row_id, reason) = (
+            transaction.client,
+            transaction.tx,
+            transaction.ty.clone(),
+            Some(transaction.row_id.clone()).filter(|_| self.with_row_id),
+            transaction
+                .reason
+                .clone()
+                .filter(|_| transaction.ty == TransactionType::Dispute),
+        );
+        self.engine.publish_transaction(transaction)?;
+        // The client may not exist if it was pruned, in which case there is nothing to audit
+        if let Some(client) = self.engine.client(client_id) {
+            self.audit.serialize(AuditRecord {
+                client: client_id,
+                tx,
+                ty: ty.as_str(),
+                available_after: client.available_amount().to_exact_string(),
+                held_after: client.held_amount().to_exact_string(),
+                row_id,
+                reason,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the audit trail before outputting the current state of the clients to the
+    /// provided writer
+    fn output_with_options(self, writer: impl Write, options: OutputOptions) -> Result<()> {
+        self.into_inner()?.output_with_options(writer, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::Amount;
+
+    #[test]
+    fn audits_the_balances_after_each_transaction() -> Result<()> {
+        let mut audit = vec![];
+        let mut engine = AuditingEngine::new(BasicEngine::default(), &mut audit);
+        let rows = [
+            (TransactionType::Deposit, Some(Amount::new(10.0)?)),
+            (TransactionType::Dispute, None),
+            (TransactionType::Resolve, None),
+        ];
+        for (ty, amount) in rows {
+            engine.publish_transaction(IncomingTransaction {
+                ty,
+                client: 1,
+                tx: 1,
+                amount,
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: None,
+            })?;
+        }
+        engine.output(vec![])?;
+
+        let mut reader = csv::Reader::from_reader(&*audit);
+        assert_eq!(
+            reader.headers()?,
+            &csv::StringRecord::from(vec![
+                "client",
+                "tx",
+                "type",
+                "available_after",
+                "held_after",
+                "reason"
+            ])
+        );
+        let rows = reader
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(
+            rows,
+            vec![
+                csv::StringRecord::from(vec!["1", "1", "deposit", "10.0000", "0.0000", ""]),
+                csv::StringRecord::from(vec!["1", "1", "dispute", "0.0000", "10.0000", ""]),
+                csv::StringRecord::from(vec!["1", "1", "resolve", "10.0000", "0.0000", ""]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn audits_the_row_id_and_dispute_reason_of_each_transaction() -> Result<()> {
+        let mut audit = vec![];
+        let mut engine = AuditingEngine::new(BasicEngine::default(), &mut audit).with_row_id();
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(10.0)?),
+            timestamp: None,
+            row_id: Some("ext-1".to_string()),
+            reason: Some("ignored".to_string()),
+            dest: None,
+        })?;
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            row_id: Some("ext-2".to_string()),
+            reason: Some("fraud".to_string()),
+            dest: None,
+        })?;
+        engine.output(vec![])?;
+
+        let mut reader = csv::Reader::from_reader(&*audit);
+        assert_eq!(
+            reader.headers()?,
+            &csv::StringRecord::from(vec![
+                "client",
+                "tx",
+                "type",
+                "available_after",
+                "held_after",
+                "row_id",
+                "reason"
+            ])
+        );
+        let rows = reader
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(
+            rows,
+            vec![
+                csv::StringRecord::from(vec![
+                    "1", "1", "deposit", "10.0000", "0.0000", "ext-1", ""
+                ]),
+                csv::StringRecord::from(vec![
+                    "1", "1", "dispute", "0.0000", "10.0000", "ext-2", "fraud"
+                ]),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -20,13 +20,31 @@
 //! engine.output(io::stdout()).unwrap();
 //! ```
 
-use color_eyre::Result;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Report, Result,
+};
 use fnv::FnvHashMap;
+use serde::Serialize;
+use tracing::{error, warn};
 
+use std::collections::VecDeque;
+use std::fs::{self, File};
 use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::storage::{Client, ClientStorage};
-use crate::transaction::IncomingTransaction;
+use crate::error::TransactionError;
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::output::{OutputOptions, StreamingOutput};
+use crate::stats::{AmountStats, Stats};
+use crate::storage::{Client, ClientStorage, DisputePolicy};
+use crate::transaction::{
+    CustomTransaction, CustomTransactionHandlers, IncomingTransaction, TransactionType,
+};
+use crate::Amount;
 
 use super::SyncEngine;
 
@@ -35,44 +53,1709 @@ use super::SyncEngine;
 /// Each csv row is processed exactly in order and processing of
 /// the next row won't start until the previous is complete
 #[derive(Default)]
-pub struct BasicEngine(FnvHashMap<u16, Client>);
+pub struct BasicEngine {
+    clients: FnvHashMap<u16, Client>,
+    custom_handlers: CustomTransactionHandlers,
+    validate_client_references: bool,
+    client_mismatches: Vec<ClientMismatch>,
+    /// The clients which deposited each transaction id, this is only tracked if duplicate
+    /// deposit detection is enabled
+    deposit_ids: Option<FnvHashMap<u32, Vec<u16>>>,
+    prune_empty_clients: bool,
+    frozen_output: Option<StreamingOutput<Box<dyn Write + Send>>>,
+    amount_stats: Option<AmountStats>,
+    stats: Stats,
+    fail_on_freeze: bool,
+    /// How each client is created when it's first seen
+    client_settings: ClientSettings,
+    out_of_order_lookahead: Option<usize>,
+    /// Disputes, resolves and chargebacks which arrived before the transaction they reference,
+    /// along with the row after which they're no longer held
+    orphans: VecDeque<(IncomingTransaction, usize)>,
+    row: usize,
+}
+
+/// A record of a dispute, resolve or chargeback which referenced a transaction belonging to a
+/// different client to the one stated on the row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientMismatch {
+    /// The transaction id referenced by the row
+    pub tx: u32,
+    /// The client stated on the row
+    pub referenced_by: u16,
+    /// The client which the transaction actually belongs to
+    pub owned_by: u16,
+}
+
+/// A transaction id which appeared as a deposit more than once across the whole input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateDeposit {
+    /// The repeated transaction id
+    pub tx: u32,
+    /// The client stated on each deposit row with this id, in the order they were processed
+    pub clients: Vec<u16>,
+}
 
 impl SyncEngine for BasicEngine {
-    fn publish_transaction(
+    fn publish_transaction(&mut self, transaction: IncomingTransaction) -> Result<()> {
+        let lookahead = match self.out_of_order_lookahead {
+            Some(lookahead) => lookahead,
+            None => return self.apply_transaction(transaction),
+        };
+        self.row += 1;
+        while let Some((_, held_until)) = self.orphans.front() {
+            if *held_until >= self.row {
+                break;
+            }
+            if let Some((orphan, _)) = self.orphans.pop_front() {
+                self.apply_orphan(orphan)?;
+            }
+        }
+
+        let (client, tx) = (transaction.client, transaction.tx);
+        let can_be_referenced = matches!(
+            transaction.ty,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        );
+        let references_existing = matches!(
+            transaction.ty,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        );
+        let is_known = self
+            .clients
+            .get(&client)
+            .is_some_and(|c| c.contains_transaction(tx));
+        if references_existing && !is_known {
+            self.orphans.push_back((transaction, self.row + lookahead));
+            return Ok(());
+        }
+
+        self.apply_transaction(transaction)?;
+        if can_be_referenced && !self.orphans.is_empty() {
+            let (matching, remaining) = std::mem::take(&mut self.orphans)
+                .into_iter()
+                .partition::<VecDeque<_>, _>(|(o, _)| o.client == client && o.tx == tx);
+            self.orphans = remaining;
+            for (orphan, _) in matching {
+                self.apply_orphan(orphan)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Outputs the current state of the clients to the provided writer by
+    /// serializing the results into a csv format
+    ///
+    /// Any orphaned transactions which are still held _(see
+    /// [`BasicEngine::with_out_of_order_lookahead`])_ are processed first.
+    fn output_with_options(mut self, writer: impl Write, options: OutputOptions) -> Result<()> {
+        while let Some((orphan, _)) = self.orphans.pop_front() {
+            self.apply_orphan(orphan)?;
+        }
+        let mut writer = StreamingOutput::with_options(writer, options);
+        let frozen_already_output = self.frozen_output.is_some();
+        writer.write_clients(
+            self.clients
+                .values()
+                .filter(|c| !(frozen_already_output && c.is_locked())),
+        )?;
+        writer.finish()?;
+        if self.fail_on_freeze && self.stats.frozen_accounts > 0 {
+            return Err(TransactionError::AccountsFrozen {
+                count: self.stats.frozen_accounts,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl BasicEngine {
+    /// The name of the engine, as it's referred to in the output of a run _(eg. see
+    /// `--emit-metadata`)_
+    pub const NAME: &'static str = "basic";
+
+    /// Creates an engine which already holds the provided clients, eg. to seed the engine with
+    /// opening balances loaded from a database _(see [`Client::with_state`] and
+    /// [`Client::restore`])_
+    ///
+    /// This errors if more than one of the clients has the same id. The clients are held as
+    /// they are, so any of the builder settings which apply to each client _(eg.
+    /// [`BasicEngine::with_dispute_window`])_ only affect the clients created afterwards. Any
+    /// of the clients which are already frozen are counted by [`BasicEngine::frozen_count`].
+    ///
+    /// ```
+    /// use lib::engines::BasicEngine;
+    /// use lib::storage::{AccountStatus, Client};
+    /// use lib::Amount;
+    ///
+    /// let opening = |id| Client::with_state(id, "10".parse::<Amount>().unwrap(), Amount::default(), AccountStatus::Active);
+    /// let engine = BasicEngine::from_clients([opening(1), opening(2)])?;
+    /// assert!(engine.contains_client(2));
+    /// assert!(BasicEngine::from_clients([opening(1), opening(1)]).is_err());
+    /// # Ok::<(), color_eyre::Report>(())
+    /// ```
+    pub fn from_clients(clients: impl IntoIterator<Item = Client>) -> Result<Self> {
+        let mut engine = Self::default();
+        for client in clients {
+            let id = client.id;
+            if client.is_locked() {
+                engine.stats.frozen_accounts += 1;
+            }
+            if engine.clients.insert(id, client).is_some() {
+                return Err(eyre!(
+                    "unable to create the engine as client {} was provided more than once",
+                    id
+                ));
+            }
+        }
+        Ok(engine)
+    }
+
+    /// Applies a transaction which was held as an orphan, so any error references the orphan
+    /// rather than the row which was being processed when it was released
+    fn apply_orphan(&mut self, orphan: IncomingTransaction) -> Result<()> {
+        let (ty, tx) = (orphan.ty.clone(), orphan.tx);
+        self.apply_transaction(orphan).wrap_err_with(|| {
+            format!(
+                "failed to process the held {} for transaction id {}",
+                ty.as_str(),
+                tx
+            )
+        })
+    }
+
+    fn apply_transaction(
         &mut self,
         IncomingTransaction {
             ty,
             client,
             tx,
             amount,
+            timestamp,
+            row_id,
+            reason,
+            dest,
         }: IncomingTransaction,
     ) -> Result<()> {
-        let client = self.0.entry(client).or_insert_with(|| Client::new(client));
+        if ty == TransactionType::Heartbeat {
+            return Ok(());
+        }
+        // An unknown type is most likely a typo of a native type, so it's counted as a rejected
+        // transaction rather than as a custom one
+        let handler = match &ty {
+            TransactionType::Custom(name) => match self.custom_handlers.get(name) {
+                Some(handler) => Some(Arc::clone(handler)),
+                None => {
+                    let e = TransactionError::UnknownTransactionType {
+                        tx,
+                        name: name.clone(),
+                    };
+                    warn!(error = %e, "skipping transaction {} for client {}", tx, client);
+                    #[cfg(feature = "metrics")]
+                    metrics::record_error();
+                    self.stats.skipped_errors += 1;
+                    return Ok(());
+                }
+            },
+            _ => None,
+        };
+        #[cfg(feature = "metrics")]
+        metrics::record_transaction(&ty);
+        self.stats.record(&ty);
+        if self.validate_client_references {
+            self.check_client_reference(&ty, client, tx);
+        }
+        if let (Some(stats), TransactionType::Deposit | TransactionType::Withdrawal, Some(amount)) =
+            (self.amount_stats.as_mut(), &ty, amount)
+        {
+            stats.record(amount);
+        }
+        if let (Some(deposit_ids), TransactionType::Deposit) = (self.deposit_ids.as_mut(), &ty) {
+            deposit_ids.entry(tx).or_default().push(client);
+        }
+        if ty == TransactionType::Transfer {
+            return self.apply_transfer(client, tx, amount, dest);
+        }
+        let client_id = client;
+        let is_new_client = self.prune_empty_clients && !self.clients.contains_key(&client_id);
+        let settings = self.client_settings;
+        let client = self
+            .clients
+            .entry(client_id)
+            .or_insert_with(|| settings.new_client(client_id));
         if !client.is_locked() {
-            match client.process_transaction(tx, ty, amount) {
-                // TODO - Make this an enum match instead of a string
-                Err(e) if !e.to_string().starts_with("[FROZEN_ACCOUNT]") => return Err(e),
+            // Counting the open disputes requires a scan of the client's transactions, so it is
+            // only done for the transactions which can change it
+            #[cfg(feature = "metrics")]
+            let open_disputes = matches!(
+                ty,
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+            )
+            .then(|| client.open_dispute_count());
+            let is_dispute = ty == TransactionType::Dispute;
+            let result = match handler {
+                Some(handler) => handler.apply(client, tx, amount),
+                None => client.process_transaction_at(tx, ty, amount, timestamp),
+            };
+            if let (true, Ok(()), Some(reason)) = (is_dispute, &result, reason) {
+                client.set_dispute_reason(tx, reason);
+            }
+            #[cfg(feature = "metrics")]
+            if let Some(before) = open_disputes {
+                metrics::record_open_disputes_change(before, client.open_dispute_count());
+            }
+            match result {
+                // A rejected transaction doesn't stop processing
+                Err(e)
+                    if e.downcast_ref::<TransactionError>()
+                        .is_some_and(TransactionError::is_recoverable) =>
+                {
+                    #[cfg(feature = "metrics")]
+                    metrics::record_error();
+                    self.stats.skipped_errors += 1;
+                }
+                Err(e)
+                    if !matches!(
+                        e.downcast_ref::<TransactionError>(),
+                        Some(TransactionError::FrozenAccount { .. })
+                    ) =>
+                {
+                    #[cfg(feature = "metrics")]
+                    metrics::record_error();
+                    return Err(match row_id {
+                        Some(row_id) => e.wrap_err(format!(
+                            "failed to process the transaction with row id {}",
+                            row_id
+                        )),
+                        None => e,
+                    });
+                }
                 _ => {}
             }
+            if client.is_locked() {
+                self.stats.frozen_accounts += 1;
+                #[cfg(feature = "metrics")]
+                metrics::record_frozen_account();
+                if let Some(output) = self.frozen_output.as_mut() {
+                    output.write_client(client)?;
+                    output.flush()?;
+                }
+            } else if is_new_client && client.is_empty() {
+                self.clients.remove(&client_id);
+            }
         }
         Ok(())
     }
 
-    /// Outputs the current state of the clients to the provided writer by
-    /// serializing the results into a csv format
-    fn output(self, writer: impl Write) -> Result<()> {
-        let mut writer = csv::Writer::from_writer(writer);
-        for client in self.0.values() {
-            writer.serialize(client)?;
+    /// Moves the `amount` from the `source` client to the `dest` client, see [`Client::transfer`]
+    ///
+    /// A transfer which is missing either its amount or its destination, or whose source and
+    /// destination are the same client, is ignored.
+    fn apply_transfer(
+        &mut self,
+        source: u16,
+        tx: u32,
+        amount: Option<Amount>,
+        dest: Option<u16>,
+    ) -> Result<()> {
+        let (amount, dest) = match (amount, dest) {
+            (Some(amount), Some(dest)) => (amount, dest),
+            _ => {
+                warn!(
+                    "unable to process transfer {} without both an amount and a destination client",
+                    tx
+                );
+                return Ok(());
+            }
+        };
+        if source == dest {
+            warn!(
+                "ignoring transfer {} as client {} is both the source and the destination",
+                tx, source
+            );
+            return Ok(());
+        }
+        // Both clients are taken out of the map so they can be borrowed at the same time
+        let settings = self.client_settings;
+        let [mut source, mut destination] = [source, dest].map(|id| {
+            let is_new = self.prune_empty_clients && !self.clients.contains_key(&id);
+            let client = self
+                .clients
+                .remove(&id)
+                .unwrap_or_else(|| settings.new_client(id));
+            (client, is_new)
+        });
+        let result = source.0.transfer(&mut destination.0, tx, amount);
+        for (client, is_new) in [source, destination] {
+            if !(is_new && client.is_empty()) {
+                self.clients.insert(client.id, client);
+            }
+        }
+        match result {
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<TransactionError>(),
+                    Some(TransactionError::FrozenAccount { .. })
+                ) =>
+            {
+                Ok(())
+            }
+            Err(e)
+                if e.downcast_ref::<TransactionError>()
+                    .is_some_and(TransactionError::is_recoverable) =>
+            {
+                #[cfg(feature = "metrics")]
+                metrics::record_error();
+                self.stats.skipped_errors += 1;
+                Ok(())
+            }
+            result => result,
         }
-        writer.flush()?;
-        Ok(())
     }
-}
 
-impl BasicEngine {
-    /// Consumes `self` and returns an iterator over the currently stored [`Client`]
+    /// Enables validation that any dispute, resolve or chargeback references a transaction that
+    /// belongs to the client stated on the row.
+    ///
+    /// These rows are only ever processed against the stated client's own transactions, however
+    /// with validation enabled any row which instead references another client's transaction is
+    /// reported, see [`BasicEngine::client_mismatches`].
+    ///
+    /// _Note:_ this requires searching every client when a row references an unknown
+    /// transaction id, so it should only be enabled when diagnosing malformed input.
+    pub fn with_client_reference_validation(mut self) -> Self {
+        self.validate_client_references = true;
+        self
+    }
+
+    /// Enables detection of any transaction id which appears as a deposit more than once across
+    /// the whole input, regardless of which clients the deposits belong to
+    ///
+    /// Each client already rejects a deposit which repeats one of its own transaction ids,
+    /// however the same id being deposited into different clients is accepted. Either case may
+    /// indicate that the upstream system published a deposit twice, so every repeated id is
+    /// reported by [`BasicEngine::duplicate_deposits`] rather than stopping processing.
+    ///
+    /// _Note:_ this holds every deposit id in memory for the lifetime of the engine.
+    pub fn with_duplicate_deposit_detection(mut self) -> Self {
+        self.deposit_ids = Some(Default::default());
+        self
+    }
+
+    /// Rejects any dispute of a deposit which arrives more than `window` after the deposit, based
+    /// on the optional `timestamp` of each transaction, see [`Client::process_transaction_at`]
+    ///
+    /// By default there is no dispute window.
+    pub fn with_dispute_window(mut self, window: Duration) -> Self {
+        self.client_settings.dispute_window = Some(window);
+        self
+    }
+
+    /// Sets how every client handles a dispute of a deposit whose funds are no longer
+    /// available, see [`Client::with_dispute_policy`]
+    ///
+    /// By default the dispute is applied and the client's available funds go negative.
+    pub fn with_dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.client_settings.dispute_policy = policy;
+        self
+    }
+
+    /// Records the history of every client's transactions, see [`Client::with_history`]
+    ///
+    /// _Note:_ this holds an event for every transaction processed in memory for the lifetime of
+    /// the engine.
+    pub fn with_client_history(mut self) -> Self {
+        self.client_settings.history = true;
+        self
+    }
+
+    /// Holds any dispute, resolve or chargeback which references a transaction that hasn't been
+    /// seen yet for up to `rows` further rows, applying it as soon as the referenced deposit or
+    /// withdrawal arrives
+    ///
+    /// This handles mildly out-of-order input _(eg. a dispute which arrives just before its
+    /// deposit)_ without sorting the whole input. Once the look-ahead is exceeded the row is
+    /// processed as normal, ie. ignored as the transaction doesn't exist. By default no rows are
+    /// held.
+    pub fn with_out_of_order_lookahead(mut self, rows: usize) -> Self {
+        self.out_of_order_lookahead = Some(rows);
+        self
+    }
+
+    /// Fails the run with [`TransactionError::AccountsFrozen`] if any account was frozen while
+    /// processing, eg. to alert on any chargeback
+    ///
+    /// Processing isn't affected, the error is only returned by [`SyncEngine::output`] once the
+    /// output has been written in full.
+    pub fn with_fail_on_freeze(mut self) -> Self {
+        self.fail_on_freeze = true;
+        self
+    }
+
+    /// Prevents clients from being created by transactions which are ignored _(eg. a resolve for
+    /// a transaction that doesn't exist)_, so that they don't appear in the output
+    pub fn with_empty_client_pruning(mut self) -> Self {
+        self.prune_empty_clients = true;
+        self
+    }
+
+    /// Writes a client's final row to the provided sink as soon as their account is frozen,
+    /// rather than waiting for the end of the run
+    ///
+    /// As a frozen account can never change again, these clients are then left out of the
+    /// output written by [`SyncEngine::output`].
+    pub fn with_frozen_account_output(
+        mut self,
+        sink: impl Write + Send + 'static,
+        options: OutputOptions,
+    ) -> Self {
+        self.frozen_output = Some(StreamingOutput::with_options(Box::new(sink), options));
+        self
+    }
+
+    /// Iterates over the balances of every client without consuming the engine, yielding the
+    /// `(id, available, held, locked)` of each client in no particular order
+    pub fn iter_balances(&self) -> impl Iterator<Item = (u16, Amount, Amount, bool)> + '_ {
+        self.clients
+            .values()
+            .map(|c| (c.id, c.available_amount(), c.held_amount(), c.is_locked()))
+    }
+
+    /// The client with the provided id, if it has been created
+    pub fn client(&self, id: u16) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    /// Combines the clients of two engines, eg. those which processed separate shards of the
+    /// same input
+    ///
+    /// The engines are expected to hold disjoint sets of clients. Any client held by both
+    /// engines has only been partially processed by each of them, so rather than picking one
+    /// the merge fails, listing the overlapping client ids.
+    ///
+    /// The [`Stats`], client mismatches and deposit ids of `other` are combined with those of
+    /// this engine, whereas the settings and amount stats of this engine are kept. Any orphaned
+    /// transactions still held by `other` are processed before its clients are taken.
+    pub fn merge(mut self, mut other: BasicEngine) -> Result<BasicEngine> {
+        while let Some((orphan, _)) = other.orphans.pop_front() {
+            other.apply_orphan(orphan)?;
+        }
+        let mut overlapping = other
+            .clients
+            .keys()
+            .filter(|id| self.clients.contains_key(id))
+            .copied()
+            .collect::<Vec<_>>();
+        if !overlapping.is_empty() {
+            overlapping.sort_unstable();
+            return Err(eyre!(
+                "unable to merge the engines as both hold the clients {:?}",
+                overlapping
+            ));
+        }
+        self.clients.extend(other.clients);
+        self.stats += other.stats;
+        self.client_mismatches.extend(other.client_mismatches);
+        if let (Some(deposit_ids), Some(other_ids)) = (self.deposit_ids.as_mut(), other.deposit_ids)
+        {
+            for (tx, clients) in other_ids {
+                deposit_ids.entry(tx).or_default().extend(clients);
+            }
+        }
+        Ok(self)
+    }
+
+    /// The number of accounts which have been frozen so far
+    ///
+    /// This is tracked as transactions are processed, so it is cheap to call mid-stream
+    /// regardless of the number of clients.
+    pub fn frozen_count(&self) -> usize {
+        self.stats.frozen_accounts
+    }
+
+    /// The counts of the transactions processed so far
+    ///
+    /// As with [`BasicEngine::frozen_count`], this is cheap to call mid-stream.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Writes a csv row of `client,tx,reason` for every transaction which is currently under
+    /// dispute, ordered by client and then transaction id
+    ///
+    /// The `reason` is only populated if one was provided with the dispute.
+    pub fn write_open_disputes(&self, writer: impl Write) -> Result<()> {
+        #[derive(Serialize)]
+        struct OpenDispute<'a> {
+            client: u16,
+            tx: u32,
+            reason: Option<&'a str>,
+        }
+
+        let mut clients = self.clients.values().collect::<Vec<_>>();
+        clients.sort_unstable_by_key(|c| c.id);
+        let mut output = StreamingOutput::new(writer);
+        for client in clients {
+            for (tx, reason) in client.open_disputes() {
+                output.serialize(OpenDispute {
+                    client: client.id,
+                    tx,
+                    reason,
+                })?;
+            }
+        }
+        output.flush()
+    }
+
+    /// Identifies whether a client with the provided id has been created
+    pub fn contains_client(&self, id: u16) -> bool {
+        self.clients.contains_key(&id)
+    }
+
+    /// Gathers [`AmountStats`] over the amount of every deposit and withdrawal, see
+    /// [`BasicEngine::amount_stats`]
+    pub fn with_amount_stats(mut self) -> Self {
+        self.amount_stats = Some(AmountStats::default());
+        self
+    }
+
+    /// The distribution of deposit and withdrawal amounts, this is only populated if
+    /// [`BasicEngine::with_amount_stats`] is enabled
+    pub fn amount_stats(&self) -> Option<&AmountStats> {
+        self.amount_stats.as_ref()
+    }
+
+    /// Any mismatched client references identified while processing, this is only populated if
+    /// [`BasicEngine::with_client_reference_validation`] is enabled
+    pub fn client_mismatches(&self) -> &[ClientMismatch] {
+        &self.client_mismatches
+    }
+
+    /// Every transaction id which appeared as a deposit more than once, ordered by id, this is
+    /// only populated if [`BasicEngine::with_duplicate_deposit_detection`] is enabled
+    pub fn duplicate_deposits(&self) -> Vec<DuplicateDeposit> {
+        let mut duplicates = self
+            .deposit_ids
+            .iter()
+            .flatten()
+            .filter(|(_, clients)| clients.len() > 1)
+            .map(|(tx, clients)| DuplicateDeposit {
+                tx: *tx,
+                clients: clients.clone(),
+            })
+            .collect::<Vec<_>>();
+        duplicates.sort_unstable_by_key(|d| d.tx);
+        duplicates
+    }
+
+    fn check_client_reference(&mut self, ty: &TransactionType, client_id: u16, tx: u32) {
+        if !matches!(
+            ty,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        ) {
+            return;
+        }
+        if let Some(c) = self.clients.get(&client_id) {
+            if c.contains_transaction(tx) {
+                return;
+            }
+        }
+        if let Some(owner) = self
+            .clients
+            .values()
+            .find(|c| c.id != client_id && c.contains_transaction(tx))
+        {
+            warn!(
+                "{:?} for transaction id {} states client {} however the transaction belongs to client {}, it will be ignored",
+                ty, tx, client_id, owner.id
+            );
+            self.client_mismatches.push(ClientMismatch {
+                tx,
+                referenced_by: client_id,
+                owned_by: owner.id,
+            });
+        }
+    }
+
+    /// Registers a [`CustomTransaction`] handler which will be used to process any incoming
+    /// transaction whose `type` matches the provided name
+    ///
+    /// The built-in transaction types can not be overridden, registering a handler against one
+    /// of their names has no effect.
+    pub fn register_transaction_type(
+        &mut self,
+        name: impl Into<String>,
+        handler: impl CustomTransaction + Send + Sync + 'static,
+    ) {
+        self.custom_handlers.insert(name.into(), Arc::new(handler));
+    }
+
+    /// Outputs the current state of each client to its own `<client_id>.csv` file within the
+    /// provided directory, creating the directory if it doesn't exist
+    ///
+    /// A failure to write any individual client's file does not stop the remaining files from
+    /// being written, instead every failure is collected and returned alongside the id of the
+    /// client it relates to.
+    pub fn output_per_client(self, dir: &Path) -> Result<Vec<(u16, Report)>> {
+        fs::create_dir_all(dir)?;
+        let failures = self
+            .clients
+            .values()
+            .filter_map(|client| {
+                let write = || -> Result<()> {
+                    let file = File::create(dir.join(format!("{}.csv", client.id)))?;
+                    let mut writer = StreamingOutput::new(file);
+                    writer.write_client(client)?;
+                    writer.flush()
+                };
+                write().err().map(|e| {
+                    error!(error = %e, "failed to write the output file for client {}", client.id);
+                    (client.id, e)
+                })
+            })
+            .collect();
+        Ok(failures)
+    }
+
+    /// Sums the available and held funds across every client _(including frozen accounts)_,
+    /// returning them as `(available, held)`
+    ///
+    /// Returns `None` if either total overflows.
+    pub fn book_totals(&self) -> Option<(Amount, Amount)> {
+        self.clients.values().try_fold(
+            (Amount::default(), Amount::default()),
+            |(available, held), c| {
+                Some((
+                    available.checked_add(c.available_amount())?,
+                    held.checked_add(c.held_amount())?,
+                ))
+            },
+        )
+    }
+
+    /// The `n` clients with the highest total funds _(including frozen accounts)_, sorted in
+    /// descending order of their total
+    ///
+    /// Clients with the same total are ordered by their id. A client whose total funds overflow
+    /// can't be ranked, so is left out.
+    ///
+    /// ```
+    /// use lib::SyncEngine;
+    /// use lib::engines::BasicEngine;
+    /// use lib::transaction::IncomingTransaction;
+    ///
+    /// let mut reader = csv::ReaderBuilder::new()
+    ///     .trim(csv::Trim::All)
+    ///     .from_path("./test_assets/simple/spec.csv")?;
+    /// let mut engine = BasicEngine::default();
+    /// engine.process(reader.deserialize::<IncomingTransaction>())?;
+    /// for (client, total) in engine.top_by_total(1) {
+    ///     println!("{}: {}", client, total.to_exact_string());
+    /// }
+    /// # Ok::<(), color_eyre::Report>(())
+    /// ```
+    pub fn top_by_total(&self, n: usize) -> Vec<(u16, Amount)> {
+        let mut totals = self
+            .clients
+            .values()
+            .filter_map(|c| c.total_amount().map(|total| (c.id, total)))
+            .collect::<Vec<_>>();
+        totals.sort_by(|(lhs_id, lhs), (rhs_id, rhs)| {
+            rhs.partial_cmp(lhs)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(lhs_id.cmp(rhs_id))
+        });
+        totals.truncate(n);
+        totals
+    }
+
+    /// Consumes the engine, yielding every client
+    ///
+    /// _Note:_ any orphaned transactions which are still held are dropped, the partitions of the
+    /// [`StreamLikeEngine`](super::StreamLikeEngine) never hold them
+    #[cfg(any(feature = "stream_engine", feature = "rayon_engine"))]
     pub(crate) fn clients(self) -> impl Iterator<Item = Client> {
-        self.0.into_values()
+        self.clients.into_values()
+    }
+}
+
+/// The settings applied to each client as the engine creates it
+#[derive(Default, Clone, Copy)]
+struct ClientSettings {
+    dispute_window: Option<Duration>,
+    dispute_policy: DisputePolicy,
+    history: bool,
+}
+
+impl ClientSettings {
+    /// Creates a client which isn't yet known to the engine
+    fn new_client(self, client_id: u16) -> Client {
+        let mut client = Client::new(client_id).with_dispute_policy(self.dispute_policy);
+        if let Some(window) = self.dispute_window {
+            client = client.with_dispute_window(window);
+        }
+        if self.history {
+            client = client.with_history();
+        }
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::engines::DeadlineOutcome;
+    use crate::input::{self, InputOptions};
+
+    /// Processes every row of the csv `input` with the engine
+    fn process_csv(engine: &mut BasicEngine, input: &str) -> Result<()> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        engine.process(reader.deserialize::<IncomingTransaction>())
+    }
+
+    struct Fee;
+
+    impl CustomTransaction for Fee {
+        fn apply(
+            &self,
+            client: &mut Client,
+            transaction_id: u32,
+            amount: Option<Amount>,
+        ) -> Result<()> {
+            if let Some(amount) = amount {
+                client.withdraw(transaction_id, amount)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A [`CustomTransaction`] which always fails to be applied
+    struct Failing;
+
+    impl CustomTransaction for Failing {
+        fn apply(&self, _: &mut Client, _: u32, _: Option<Amount>) -> Result<()> {
+            Err(color_eyre::eyre::eyre!("unable to apply the transaction"))
+        }
+    }
+
+    /// A [`Write`] implementation which can be inspected while the engine still owns it
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transfers_funds_between_clients() -> Result<()> {
+        let input = "type,client,tx,amount,dest\n\
+            deposit,1,1,10.0,\n\
+            transfer,1,2,4.0,2\n\
+            transfer,2,3,5.0,1\n\
+            transfer,1,4,1.0,1\n\
+            transfer,1,5,1.0,\n\
+            transfer,1,6,1.0,3\n\
+            withdrawal,2,7,4.0,\n";
+        let mut engine = BasicEngine::default().with_empty_client_pruning();
+        process_csv(&mut engine, input)?;
+
+        let balance = |id| {
+            engine
+                .client(id)
+                .map(|c| c.available_amount().to_exact_string())
+        };
+        // Only the transfers to client 2 and then client 3 are applied, the others are either
+        // unfunded, to the same client or missing their destination
+        assert_eq!(balance(1).as_deref(), Some("5.0000"));
+        assert_eq!(balance(2).as_deref(), Some("0.0000"));
+        assert_eq!(balance(3).as_deref(), Some("1.0000"));
+        Ok(())
+    }
+
+    #[test]
+    fn a_rejected_transfer_does_not_create_either_client_when_pruning() -> Result<()> {
+        let mut engine = BasicEngine::default().with_empty_client_pruning();
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Transfer,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(1.0)?),
+            timestamp: None,
+            row_id: None,
+            reason: None,
+            dest: Some(2),
+        })?;
+        assert!(engine.client(1).is_none());
+        assert!(engine.client(2).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn outputs_frozen_accounts_as_soon_as_they_are_frozen() -> Result<()> {
+        let sink = SharedSink::default();
+        let mut engine = BasicEngine::default()
+            .with_frozen_account_output(sink.clone(), OutputOptions::default());
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\ndispute,1,1,\nchargeback,1,1,\n";
+        process_csv(&mut engine, input)?;
+
+        let emitted = String::from_utf8(sink.0.lock().unwrap().clone())?;
+        let emitted = csv::Reader::from_reader(emitted.as_bytes())
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(emitted.len(), 1, "only the frozen client should be emitted");
+        assert_eq!(
+            emitted[0],
+            csv::StringRecord::from(vec!["1", "0.0000", "0.0000", "0.0000", "true"])
+        );
+
+        // Processing continues as normal for other clients
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Deposit,
+            client: 2,
+            tx: 3,
+            amount: Some(Amount::new(1.0)?),
+            timestamp: None,
+            row_id: None,
+            reason: None,
+            dest: None,
+        })?;
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        let rows = csv::Reader::from_reader(&*result)
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(
+            rows.len(),
+            1,
+            "the frozen client should not be output twice"
+        );
+        assert_eq!(&rows[0][0], "2");
+        Ok(())
+    }
+
+    #[test]
+    fn errors_reference_the_provided_row_index() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        engine.register_transaction_type("failing", Failing);
+        let rows = vec![
+            (
+                41,
+                IncomingTransaction {
+                    ty: TransactionType::Deposit,
+                    client: 1,
+                    tx: 1,
+                    amount: Some(Amount::new(1.0)?),
+                    timestamp: None,
+                    row_id: None,
+                    reason: None,
+                    dest: None,
+                },
+            ),
+            (
+                42,
+                IncomingTransaction {
+                    ty: TransactionType::Custom("failing".to_string()),
+                    client: 1,
+                    tx: 2,
+                    amount: None,
+                    timestamp: None,
+                    row_id: None,
+                    reason: None,
+                    dest: None,
+                },
+            ),
+        ];
+        let error = engine
+            .process_indexed(rows.into_iter())
+            .expect_err("the custom transaction should fail");
+        assert_eq!(
+            error.to_string(),
+            "failed to process the transaction on row 42"
+        );
+        assert_eq!(
+            error.root_cause().to_string(),
+            "unable to apply the transaction"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors_reference_the_external_row_id() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        engine.register_transaction_type("failing", Failing);
+        let input = "row_id,type,client,tx,amount\nabc-1,deposit,1,1,1.0\nabc-2,failing,1,2,\n";
+        let options = InputOptions {
+            row_id: true,
+            ..Default::default()
+        };
+        let reader = options.reader(input.as_bytes());
+        let error = engine
+            .process_indexed(
+                input::deserialize(reader, &options)?
+                    .enumerate()
+                    .map(|(index, trx)| trx.map(|trx| (index, trx)).expect("expected a valid row")),
+            )
+            .expect_err("the custom transaction should fail");
+        let messages = error.chain().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "failed to process the transaction on row 1",
+                "failed to process the transaction with row id abc-2",
+                "unable to apply the transaction",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reports_the_reason_of_each_open_dispute() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        let input = "type,client,tx,amount,reason\ndeposit,1,1,5.0,\ndeposit,1,2,3.0,\ndeposit,2,3,1.0,\ndispute,1,1,,fraud\ndispute,1,2,,\ndispute,2,3,,duplicate\nresolve,2,3,,\n";
+        process_csv(&mut engine, input)?;
+
+        let mut result = vec![];
+        engine.write_open_disputes(&mut result)?;
+        assert_eq!(
+            String::from_utf8(result)?,
+            "client,tx,reason\n1,1,fraud\n1,2,\n",
+            "the resolved dispute should no longer be reported"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn applies_disputes_which_arrive_before_their_deposit() -> Result<()> {
+        let mut engine = BasicEngine::default().with_out_of_order_lookahead(2);
+        let input = "type,client,tx,amount\ndispute,1,1,\ndeposit,1,2,1.0\ndeposit,1,1,5.0\ndispute,2,3,\ndeposit,2,4,1.0\ndeposit,2,5,1.0\ndeposit,2,6,1.0\ndeposit,2,3,2.0\n";
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1");
+        assert_eq!(*client.available_amount(), *Amount::new(1.0)?);
+        assert_eq!(
+            *client.held_amount(),
+            *Amount::new(5.0)?,
+            "the dispute arrived within the look-ahead so should be applied"
+        );
+        let client = engine.client(2).expect("client 2");
+        assert_eq!(*client.available_amount(), *Amount::new(5.0)?);
+        assert_eq!(
+            *client.held_amount(),
+            *Amount::new(0.0)?,
+            "the dispute arrived outside of the look-ahead so should be ignored"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn applies_disputes_as_soon_as_their_withdrawal_arrives() -> Result<()> {
+        let mut engine = BasicEngine::default().with_out_of_order_lookahead(5);
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndispute,1,2,\nwithdrawal,1,2,3.0\nchargeback,1,2,\n";
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1");
+        assert_eq!(
+            *client.available_amount(),
+            *Amount::new(10.0)?,
+            "the dispute should be applied before the chargeback which follows the withdrawal"
+        );
+        assert_eq!(*client.held_amount(), *Amount::new(0.0)?);
+        assert!(client.is_locked());
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_disputes_outside_of_the_dispute_window() -> Result<()> {
+        let mut engine = BasicEngine::default().with_dispute_window(Duration::from_secs(60));
+        let input = "type,client,tx,amount,timestamp\ndeposit,1,1,5.0,0\ndeposit,1,2,3.0,100\ndispute,1,1,,120\ndispute,1,2,,120\n";
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1");
+        assert_eq!(*client.available_amount(), *Amount::new(5.0)?);
+        assert_eq!(*client.held_amount(), *Amount::new(3.0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_disputes_which_would_overdraw_when_configured() -> Result<()> {
+        let mut engine =
+            BasicEngine::default().with_dispute_policy(DisputePolicy::RejectIfInsufficient);
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,4.0\ndispute,1,1,\ndeposit,1,3,2.0\n";
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1");
+        assert_eq!(*client.available_amount(), *Amount::new(3.0)?);
+        assert!(!client.has_open_disputes());
+        Ok(())
+    }
+
+    #[test]
+    fn records_the_history_of_every_client_when_enabled() -> Result<()> {
+        let mut engine = BasicEngine::default().with_client_history();
+        let input = "type,client,tx,amount,dest\ndeposit,1,1,5.0,\ntransfer,1,2,2.0,2\n";
+        process_csv(&mut engine, input)?;
+
+        let transitions = |id| {
+            engine.client(id).map(|c| {
+                c.history()
+                    .iter()
+                    .map(|e| (e.tx, e.to.clone()))
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            transitions(1),
+            Some(vec![
+                (1, TransactionType::Deposit),
+                (2, TransactionType::Transfer)
+            ])
+        );
+        assert_eq!(transitions(2), Some(vec![(2, TransactionType::Transfer)]));
+        Ok(())
+    }
+
+    #[test]
+    fn merges_the_clients_of_engines_which_processed_separate_shards() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\nwithdrawal,1,3,1.0\ndispute,2,2,\nchargeback,2,2,\ndeposit,3,4,1.0\n";
+        let shard = |filter: fn(u16) -> bool| -> Result<BasicEngine> {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_reader(input.as_bytes());
+            let mut engine = BasicEngine::default();
+            engine.process_filtered(reader.deserialize::<IncomingTransaction>(), filter)?;
+            Ok(engine)
+        };
+        let merged = shard(|client| client % 2 == 0)?.merge(shard(|client| client % 2 == 1)?)?;
+        let whole = shard(|_| true)?;
+
+        let balances = |engine: &BasicEngine| {
+            let mut balances = engine
+                .iter_balances()
+                .map(|(id, available, held, locked)| {
+                    (
+                        id,
+                        available.to_exact_string(),
+                        held.to_exact_string(),
+                        locked,
+                    )
+                })
+                .collect::<Vec<_>>();
+            balances.sort();
+            balances
+        };
+        assert_eq!(balances(&merged), balances(&whole));
+        assert_eq!(merged.frozen_count(), 1);
+
+        let err = shard(|client| client < 3)?
+            .merge(shard(|client| client > 1)?)
+            .err()
+            .expect("both engines hold client 2");
+        assert_eq!(
+            err.to_string(),
+            "unable to merge the engines as both hold the clients [2]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn processes_transactions_against_the_seeded_clients() -> Result<()> {
+        let opening = Client::with_state(
+            1,
+            Amount::new(10.0)?,
+            Amount::default(),
+            crate::storage::AccountStatus::Active,
+        );
+        let mut engine = BasicEngine::from_clients([opening])?;
+        let input = "type,client,tx,amount\nwithdrawal,1,1,4.0\ndeposit,2,2,1.0\n";
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1");
+        assert_eq!(client.available_amount().to_exact_string(), "6.0000");
+        assert!(engine.contains_client(2));
+        Ok(())
+    }
+
+    #[test]
+    fn counts_the_seeded_clients_which_are_already_frozen() -> Result<()> {
+        let opening =
+            |id, status| Client::with_state(id, Amount::default(), Amount::default(), status);
+        let engine = BasicEngine::from_clients([
+            opening(1, crate::storage::AccountStatus::Frozen),
+            opening(2, crate::storage::AccountStatus::Active),
+        ])?;
+        assert_eq!(engine.frozen_count(), 1);
+        assert_eq!(engine.stats().frozen_accounts, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn counts_the_transactions_it_processes() -> Result<()> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,5.0\n\
+            withdrawal,1,1,1.0\n\
+            withdrawal,1,2,1.0\n\
+            heartbeat,0,0,\n\
+            deposit,2,3,3.0\n\
+            dispute,2,3,\n\
+            chargeback,2,3,\n\
+            deposit,2,4,1.0\n";
+        let mut engine = BasicEngine::default();
+        process_csv(&mut engine, input)?;
+
+        assert_eq!(
+            *engine.stats(),
+            Stats {
+                deposits: 3,
+                withdrawals: 2,
+                disputes: 1,
+                chargebacks: 1,
+                frozen_accounts: 1,
+                // The withdrawal reusing the id of the deposit is rejected
+                skipped_errors: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(engine.stats().transactions(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_the_clients_which_do_not_pass_the_filter() -> Result<()> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path("./test_assets/interleaved_disputes/spec.csv")?;
+        let mut engine = BasicEngine::default();
+        engine.process_filtered(reader.deserialize::<IncomingTransaction>(), |client| {
+            client == 2
+        })?;
+        assert!(!engine.contains_client(1));
+
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        let rows = csv::Reader::from_reader(&*result)
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][0], "2");
+        Ok(())
+    }
+
+    #[test]
+    fn counts_the_accounts_which_have_been_frozen() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\ndeposit,3,3,1.0\ndispute,1,1,\nchargeback,1,1,\ndispute,2,2,\n";
+        process_csv(&mut engine, input)?;
+        assert_eq!(engine.frozen_count(), 1);
+
+        for ty in [TransactionType::Chargeback, TransactionType::Chargeback] {
+            // The second chargeback is ignored as the account is already frozen
+            engine.publish_transaction(IncomingTransaction {
+                ty,
+                client: 2,
+                tx: 2,
+                amount: None,
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: None,
+            })?;
+        }
+        assert_eq!(engine.frozen_count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_rows_which_fail_to_be_processed_when_lenient() -> Result<()> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path("./test_assets/bad_rows/spec.csv")?;
+        let mut engine = BasicEngine::default();
+        assert!(
+            engine
+                .process(reader.deserialize::<IncomingTransaction>())
+                .is_err(),
+            "processing is strict by default"
+        );
+
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path("./test_assets/bad_rows/spec.csv")?;
+        let mut engine = BasicEngine::default();
+        let report = engine.process_lenient(reader.deserialize::<IncomingTransaction>());
+        assert_eq!(report.processed, 4);
+        let skipped = report
+            .skipped
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        assert_eq!(skipped, vec![1, 3], "the index of each record which failed");
+        assert_eq!(
+            engine
+                .client(1)
+                .map(|c| c.available_amount().to_exact_string()),
+            Some("4.3750".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writes_rows_which_fail_to_deserialize_to_the_dead_letter_queue() -> Result<()> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path("./test_assets/bad_rows/spec.csv")?;
+        let mut engine = BasicEngine::default();
+        let mut dlq = vec![];
+        let dead_lettered = engine.process_with_deadletter(&mut reader, &mut dlq)?;
+        assert_eq!(dead_lettered, 2);
+
+        let dead = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(&*dlq)
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(dead.len(), 2);
+        assert_eq!(&dead[0][0], "3");
+        assert_eq!(
+            dead[0].iter().skip(2).collect::<Vec<_>>(),
+            vec!["deposit", "2", "abc", "5.3"]
+        );
+        assert_eq!(&dead[1][0], "5");
+        assert_eq!(
+            dead[1].iter().skip(2).collect::<Vec<_>>(),
+            vec!["withdrawal", "1", "4", "-2.5"]
+        );
+
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        let mut rows = csv::Reader::from_reader(&*result)
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        rows.sort_by_key(|r| r[0].to_string());
+        assert_eq!(
+            rows,
+            vec![
+                csv::StringRecord::from(vec!["1", "4.3750", "0.0000", "4.3750", "false"]),
+                csv::StringRecord::from(vec!["2", "2.0000", "0.0000", "2.0000", "false"]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn iterates_over_the_balances_of_every_client() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        process_csv(
+            &mut engine,
+            &std::fs::read_to_string("./test_assets/interleaved_disputes/spec.csv")?,
+        )?;
+
+        let balances = engine
+            .iter_balances()
+            .map(|(id, available, held, locked)| {
+                (
+                    id,
+                    (available.to_exact_string(), held.to_exact_string(), locked),
+                )
+            })
+            .collect::<std::collections::BTreeMap<_, _>>();
+        assert_eq!(
+            balances,
+            [
+                (1, ("7.0000".to_string(), "0.0000".to_string(), true)),
+                (2, ("1.1234".to_string(), "0.0000".to_string(), true)),
+                (3, ("0.0000".to_string(), "0.7500".to_string(), false)),
+            ]
+            .into_iter()
+            .collect()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn continues_processing_after_a_duplicate_chargeback() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,1,\nchargeback,1,1,\nchargeback,1,1,\ndeposit,2,2,1.0\n";
+        let mut engine = BasicEngine::default();
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1 should exist");
+        assert!(client.is_locked());
+        assert_eq!(client.charged_back_transactions(), &[1]);
+        assert!(
+            engine.contains_client(2),
+            "processing should have continued"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_heartbeats_interspersed_with_transactions() -> Result<()> {
+        let input = "type,client,tx,amount\n\
+            heartbeat,0,0,\n\
+            deposit,1,1,5.0\n\
+            heartbeat,1,1,\n\
+            dispute,1,1,\n\
+            heartbeat,2,0,\n\
+            resolve,1,1,\n";
+        let mut engine = BasicEngine::default();
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1 should exist");
+        assert_eq!(client.available_amount().to_exact_string(), "5.0000");
+        assert_eq!(client.held_amount().to_exact_string(), "0.0000");
+        assert!(
+            !engine.contains_client(0) && !engine.contains_client(2),
+            "heartbeats shouldn't create clients"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_transactions_for_a_frozen_account_and_continues_processing() -> Result<()> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,5.0\n\
+            deposit,1,2,3.0\n\
+            dispute,1,1,\n\
+            chargeback,1,1,\n\
+            deposit,1,3,10.0\n\
+            withdrawal,1,4,1.0\n\
+            deposit,2,5,1.0\n";
+        let mut engine = BasicEngine::default();
+        process_csv(&mut engine, input)?;
+
+        let client = engine.client(1).expect("client 1 should exist");
+        assert!(client.is_locked());
+        assert_eq!(
+            client.available_amount().to_exact_string(),
+            "3.0000",
+            "transactions after the chargeback should be skipped"
+        );
+        let client = engine.client(2).expect("processing should have continued");
+        assert_eq!(client.available_amount().to_exact_string(), "1.0000");
+        Ok(())
+    }
+
+    #[test]
+    fn fails_after_writing_the_output_if_an_account_was_frozen() -> Result<()> {
+        let process = |input: &str| -> Result<(Result<()>, String)> {
+            let mut engine = BasicEngine::default().with_fail_on_freeze();
+            process_csv(&mut engine, input)?;
+            let mut output = vec![];
+            let result = engine.output(&mut output);
+            Ok((result, String::from_utf8(output)?))
+        };
+
+        let (result, output) = process("type,client,tx,amount\ndeposit,1,1,5.0\n")?;
+        assert!(result.is_ok());
+        assert_eq!(output.lines().count(), 2);
+
+        let (result, output) = process(
+            "type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,1,\nchargeback,1,1,\ndeposit,2,2,1.0\n",
+        )?;
+        assert_eq!(
+            result
+                .expect_err("an account was frozen")
+                .downcast_ref::<TransactionError>(),
+            Some(&TransactionError::AccountsFrozen { count: 1 })
+        );
+        assert_eq!(
+            output.lines().count(),
+            3,
+            "the output should still be written"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn gathers_amount_stats_when_enabled() -> Result<()> {
+        let mut engine = BasicEngine::default().with_amount_stats();
+        for tx in 1..=1_000 {
+            let ty = if tx % 2 == 0 {
+                TransactionType::Withdrawal
+            } else {
+                TransactionType::Deposit
+            };
+            engine.publish_transaction(IncomingTransaction {
+                ty,
+                client: (tx % 7) as u16,
+                tx,
+                amount: Some(tx.to_string().parse()?),
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: None,
+            })?;
+        }
+        // Only deposits and withdrawals are recorded
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            row_id: None,
+            reason: None,
+            dest: None,
+        })?;
+
+        let stats = engine.amount_stats().expect("stats should be enabled");
+        assert_eq!(stats.count(), 1_000);
+        let median = stats
+            .median()
+            .expect("expected a median")
+            .to_exact_string()
+            .parse::<f64>()?;
+        assert!((median - 500.0).abs() < 10.0, "median was {}", median);
+        assert!(BasicEngine::default().amount_stats().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn contains_a_client_once_it_has_transacted() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        assert!(!engine.contains_client(1));
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(1.0)?),
+            timestamp: None,
+            row_id: None,
+            reason: None,
+            dest: None,
+        })?;
+        assert!(engine.contains_client(1));
+        assert!(!engine.contains_client(2));
+        Ok(())
+    }
+
+    #[test]
+    fn stops_reading_once_the_deadline_passes() -> Result<()> {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(100);
+        let pulled = std::cell::Cell::new(0);
+        let mut transactions = (1..=10).map(|tx| {
+            pulled.set(pulled.get() + 1);
+            if tx == 3 {
+                std::thread::sleep(deadline.saturating_duration_since(std::time::Instant::now()));
+            }
+            Ok::<_, Report>(IncomingTransaction {
+                ty: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(Amount::new(1.0)?),
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: None,
+            })
+        });
+        let mut engine = BasicEngine::default();
+        let outcome = engine.process_with_deadline(transactions.by_ref(), deadline)?;
+        assert_eq!(
+            outcome,
+            DeadlineOutcome::Expired {
+                processed: 3,
+                remaining: Some(7)
+            }
+        );
+        assert_eq!(pulled.get(), 3);
+        assert_eq!(
+            engine.client(1).map(|c| c.available_funds()).transpose()?,
+            Some(3.0)
+        );
+        assert_eq!(transactions.count(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn does_not_materialize_clients_that_only_saw_ignored_rows() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\nresolve,2,5,\ndispute,3,1,\n";
+        let mut engine = BasicEngine::default().with_empty_client_pruning();
+        process_csv(&mut engine, input)?;
+
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        let rows = csv::Reader::from_reader(&*result)
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(rows.len(), 1, "only client 1 should be output");
+        assert_eq!(&rows[0][0], "1");
+        Ok(())
+    }
+
+    #[test]
+    fn outputs_a_file_per_client() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        process_csv(
+            &mut engine,
+            &std::fs::read_to_string("./test_assets/interleaved_disputes/spec.csv")?,
+        )?;
+
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("clients");
+        let failures = engine.output_per_client(&out)?;
+        assert!(failures.is_empty(), "expected every file to be written");
+
+        let mut files = fs::read_dir(&out)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        files.sort();
+        assert_eq!(files, vec!["1.csv", "2.csv", "3.csv"]);
+
+        let rows = csv::Reader::from_path(out.join("3.csv"))?
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(rows.len(), 1, "each file should contain a single row");
+        assert_eq!(
+            rows[0],
+            csv::StringRecord::from(vec!["3", "0.0000", "0.7500", "0.7500", "false"])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn book_totals_sum_every_clients_balances() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        process_csv(
+            &mut engine,
+            &std::fs::read_to_string("./test_assets/interleaved_disputes/spec.csv")?,
+        )?;
+
+        // Client 1 is left with 7.0 available once deposit 4 is charged back, client 2 with
+        // 1.1234 once deposit 2 is charged back and client 3 with 0.75 held by the dispute of
+        // deposit 9, having withdrawn everything else
+        let (available, held) = engine.book_totals().expect("the totals shouldn't overflow");
+        assert_eq!(available.to_exact_string(), "8.1234");
+        assert_eq!(held.to_exact_string(), "0.7500");
+        Ok(())
+    }
+
+    #[test]
+    fn book_totals_are_none_when_they_overflow() -> Result<()> {
+        let mut engine = BasicEngine::default();
+        process_csv(
+            &mut engine,
+            "type,client,tx,amount\n\
+            deposit,1,1,50000000000000000000000000000\n\
+            deposit,2,2,50000000000000000000000000000\n",
+        )?;
+        assert!(engine.contains_client(1) && engine.contains_client(2));
+        assert!(engine.book_totals().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn ranks_the_clients_with_the_highest_total_funds() -> Result<()> {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,5.0\n\
+            deposit,2,2,10.0001\n\
+            deposit,3,3,10.0\n\
+            deposit,4,4,10.0001\n\
+            dispute,4,4,\n";
+        let mut engine = BasicEngine::default();
+        process_csv(&mut engine, input)?;
+
+        let top = engine
+            .top_by_total(2)
+            .into_iter()
+            .map(|(id, total)| (id, total.to_exact_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            top,
+            vec![(2, "10.0001".to_string()), (4, "10.0001".to_string())],
+            "held funds count towards the total, ties are ordered by id"
+        );
+        assert_eq!(engine.top_by_total(10).len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_disputes_which_reference_another_clients_transaction() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\ndispute,2,1,\n";
+        let mut engine = BasicEngine::default().with_client_reference_validation();
+        process_csv(&mut engine, input)?;
+
+        assert_eq!(
+            engine.client_mismatches(),
+            &[ClientMismatch {
+                tx: 1,
+                referenced_by: 2,
+                owned_by: 1,
+            }]
+        );
+        let owner = engine.clients.get(&1).expect("client 1 should exist");
+        assert_eq!(
+            owner.available_funds()?,
+            10.0,
+            "the dispute should be ignored"
+        );
+        assert_eq!(owner.held_funds()?, 0.0, "the dispute should be ignored");
+        Ok(())
+    }
+
+    #[test]
+    fn reports_a_deposit_id_which_is_repeated_across_clients() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\ndeposit,3,1,10.0\n";
+        let mut engine = BasicEngine::default().with_duplicate_deposit_detection();
+        process_csv(&mut engine, input)?;
+
+        assert_eq!(
+            engine.duplicate_deposits(),
+            vec![DuplicateDeposit {
+                tx: 1,
+                clients: vec![1, 3],
+            }]
+        );
+        let client = engine.clients.get(&3).expect("client 3 should exist");
+        assert_eq!(
+            client.available_funds()?,
+            10.0,
+            "the duplicate should still be processed"
+        );
+        assert!(BasicEngine::default().duplicate_deposits().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn dispatches_custom_transaction_types_to_registered_handlers() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\nfee,1,2,2.5\ninterest,1,3,1.0\n";
+        let mut engine = BasicEngine::default();
+        engine.register_transaction_type("fee", Fee);
+        process_csv(&mut engine, input)?;
+
+        let client = engine.clients.get(&1).expect("client 1 should exist");
+        assert_eq!(client.available_funds()?, 7.5);
+        assert_eq!(client.held_funds()?, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_transaction_types_without_a_registered_handler() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndepost,1,2,2.5\nDeposit,1,3,1.0\n";
+        let mut engine = BasicEngine::default();
+        process_csv(&mut engine, input)?;
+
+        let client = engine.clients.get(&1).expect("client 1 should exist");
+        assert_eq!(client.available_funds()?, 10.0);
+        assert_eq!(engine.stats().custom, 0);
+        assert_eq!(engine.stats().skipped_errors, 2);
+        Ok(())
     }
 }
@@ -14,6 +14,12 @@
 //!    which partition the transaction gets sent to - _allowing us to keep ordering_
 //! 3. An async task based engine [`ActorLikeEngine`], which is something akin to a very lightweight actor
 //!    pattern where each engine gets their own `actor/task`
+//! 4. A batch engine [`RayonLikeEngine`], which groups the transactions by client and then
+//!    processes the clients in parallel on a [`rayon`] work-stealing thread pool _(behind the
+//!    opt-in `rayon_engine` feature)_
+//!
+//! [`AuditingEngine`] can also wrap a [`BasicEngine`] in order to record a per-transaction audit
+//! trail.
 
 #[cfg(feature = "actor_engine")]
 pub mod actor_like;
@@ -25,20 +31,63 @@ pub use actor_like::ActorLikeEngine;
 pub mod stream_like;
 #[cfg(feature = "stream_engine")]
 #[doc(inline)]
-pub use stream_like::StreamLikeEngine;
+pub use stream_like::{
+    InFlightTransaction, PartitionStats, StreamLikeEngine, WorkerPanicPolicy, WorkerPool,
+};
+
+#[cfg(feature = "rayon_engine")]
+pub mod rayon_like;
+#[cfg(feature = "rayon_engine")]
+#[doc(inline)]
+pub use rayon_like::RayonLikeEngine;
 
 #[cfg(feature = "basic_engine")]
 pub mod basic;
 #[cfg(feature = "basic_engine")]
 #[doc(inline)]
-pub use basic::BasicEngine;
+pub use basic::{BasicEngine, ClientMismatch, DuplicateDeposit};
 
-use color_eyre::Result;
+#[cfg(feature = "basic_engine")]
+pub mod audit;
+#[cfg(feature = "basic_engine")]
+#[doc(inline)]
+pub use audit::AuditingEngine;
 
+#[cfg(feature = "sync")]
+use color_eyre::eyre::WrapErr;
+#[cfg(any(feature = "sync", feature = "async"))]
+use color_eyre::{Report, Result};
+#[cfg(feature = "async")]
+use tracing::Instrument;
+#[cfg(any(feature = "sync", feature = "async"))]
+use tracing::{field, info_span, warn, Span};
+
+#[cfg(feature = "sync")]
+use std::io::Read;
+#[cfg(any(feature = "sync", feature = "async"))]
 use std::io::Write;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::time::Instant;
 
+#[cfg(any(feature = "sync", feature = "async"))]
+use crate::output::OutputOptions;
+#[cfg(any(feature = "sync", feature = "async"))]
 use crate::transaction::IncomingTransaction;
 
+/// The span which [`SyncEngine::process`] and [`AsyncEngine::process`] run within, its fields
+/// are recorded by [`record_process_span`] once processing finishes
+#[cfg(any(feature = "sync", feature = "async"))]
+fn process_span() -> Span {
+    info_span!("process", count = field::Empty, elapsed = field::Empty)
+}
+
+/// Records the number of transactions published and the time taken on a [`process_span`]
+#[cfg(any(feature = "sync", feature = "async"))]
+fn record_process_span(span: &Span, count: usize, start: Instant) {
+    span.record("count", count);
+    span.record("elapsed", field::debug(start.elapsed()));
+}
+
 /// This trait representations the synchronous interface required to process a series of incoming
 /// transactions
 #[cfg(feature = "sync")]
@@ -52,20 +101,266 @@ pub trait SyncEngine {
     /// on every element of the iterator. If either the `Item` yielded by the iterator, or the
     /// publish_transaction call **errors** proccessing will be interupted and this function will
     /// return an error
-    fn process(
+    ///
+    /// The transactions are processed within a `process` span, which records the number of
+    /// transactions published and the time taken once processing finishes.
+    fn process<E>(
         &mut self,
-        iter: impl Iterator<Item = std::result::Result<IncomingTransaction, csv::Error>>,
-    ) -> Result<()> {
+        mut iter: impl Iterator<Item = std::result::Result<IncomingTransaction, E>>,
+    ) -> Result<()>
+    where
+        E: Into<Report>,
+    {
+        let span = process_span();
+        let _entered = span.enter();
+        let start = Instant::now();
+        let mut count = 0;
+        let result = iter.try_for_each(|trx| {
+            self.publish_transaction(trx.map_err(Into::into)?)?;
+            count += 1;
+            Ok(())
+        });
+        record_process_span(&span, count, start);
+        result
+    }
+
+    /// The same as [`SyncEngine::process`], however any row which fails to deserialize, or which
+    /// errors when it's published, is logged and skipped rather than interupting processing
+    ///
+    /// Returns a [`ProcessReport`] of how many rows were processed, along with the index and
+    /// error of each row which was skipped. Note that a skipped row may have been partially
+    /// applied if [`SyncEngine::publish_transaction`] errored part way through, so this is best
+    /// suited to engines which only error for rows that they reject outright _(eg. the
+    /// `BasicEngine`)_.
+    fn process_lenient<E>(
+        &mut self,
+        iter: impl Iterator<Item = std::result::Result<IncomingTransaction, E>>,
+    ) -> ProcessReport
+    where
+        E: Into<Report>,
+    {
+        let mut report = ProcessReport::default();
+        for (index, trx) in iter.enumerate() {
+            let result = trx
+                .map_err(Into::into)
+                .and_then(|trx| self.publish_transaction(trx));
+            report.record(index, result);
+        }
+        report
+    }
+
+    /// The same as [`SyncEngine::process`], however any transaction whose client id doesn't pass
+    /// the `filter` is skipped before it reaches [`SyncEngine::publish_transaction`], so those
+    /// clients are never created
+    fn process_filtered<E>(
+        &mut self,
+        iter: impl Iterator<Item = std::result::Result<IncomingTransaction, E>>,
+        filter: impl Fn(u16) -> bool,
+    ) -> Result<()>
+    where
+        E: Into<Report>,
+    {
         for trx in iter {
-            self.publish_transaction(trx?)?;
+            let trx = trx.map_err(Into::into)?;
+            if filter(trx.client) {
+                self.publish_transaction(trx)?;
+            }
         }
         Ok(())
     }
 
+    /// The same as [`SyncEngine::process`], however the transactions have already been
+    /// deserialized and are each paired with the index of the row they came from
+    ///
+    /// Any error produced while processing a transaction references the provided index, which
+    /// allows transactions parsed elsewhere to still produce useful diagnostics.
+    fn process_indexed(
+        &mut self,
+        iter: impl Iterator<Item = (usize, IncomingTransaction)>,
+    ) -> Result<()> {
+        for (index, trx) in iter {
+            self.publish_transaction(trx)
+                .wrap_err_with(|| format!("failed to process the transaction on row {}", index))?;
+        }
+        Ok(())
+    }
+
+    /// The same as [`SyncEngine::process`], however processing stops once the `deadline` has
+    /// passed, returning a [`DeadlineOutcome`] of whether every transaction was processed
+    ///
+    /// Each transaction is either fully processed or not processed at all, so the state of the
+    /// engine remains consistent. The deadline is checked before each transaction is read, so once
+    /// it has passed nothing more is read from the iterator. If the remaining transactions are
+    /// needed _(eg. to count or resume them)_ pass the iterator in with [`Iterator::by_ref`].
+    ///
+    /// For an engine which hands its transactions off to workers _(eg. the `StreamLikeEngine`)_
+    /// a transaction counts as processed once it's published. Stopping the publisher is enough to
+    /// stop these engines, as the workers only ever apply what was published and they're drained
+    /// when the output is written.
+    fn process_with_deadline<E>(
+        &mut self,
+        mut iter: impl Iterator<Item = std::result::Result<IncomingTransaction, E>>,
+        deadline: Instant,
+    ) -> Result<DeadlineOutcome>
+    where
+        E: Into<Report>,
+    {
+        let mut processed = 0;
+        loop {
+            if Instant::now() >= deadline {
+                return Ok(DeadlineOutcome::expired(processed, &iter));
+            }
+            match iter.next() {
+                Some(trx) => self.publish_transaction(trx.map_err(Into::into)?)?,
+                None => return Ok(DeadlineOutcome::Completed),
+            }
+            processed += 1;
+        }
+    }
+
+    /// Processes every row of the reader in the same manner as [`SyncEngine::process`], however
+    /// any row which fails to deserialize is written to the `dlq` writer rather than interupting
+    /// processing. Returns the number of rows which were written to the `dlq`.
+    ///
+    /// Each row written to the `dlq` is a csv row of the line number the row started on, the
+    /// error and then the raw fields of the row _(eg. `3,"error message",deposit,1,abc,1.0`)_.
+    ///
+    /// An I/O error from the reader, or an error from [`SyncEngine::publish_transaction`] will still
+    /// interupt processing.
+    fn process_with_deadletter<R: Read>(
+        &mut self,
+        reader: &mut csv::Reader<R>,
+        dlq: impl Write,
+    ) -> Result<usize> {
+        let headers = if reader.has_headers() {
+            Some(reader.headers()?.clone())
+        } else {
+            None
+        };
+        let mut dlq = csv::WriterBuilder::new()
+            .flexible(true)
+            .has_headers(false)
+            .from_writer(dlq);
+        let mut dead_lettered = 0;
+        let mut record = csv::StringRecord::new();
+        loop {
+            let (line, result) = match reader.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => (
+                    record.position().map(csv::Position::line),
+                    record.deserialize::<IncomingTransaction>(headers.as_ref()),
+                ),
+                Err(e) if e.is_io_error() => return Err(e.into()),
+                Err(e) => {
+                    record.clear();
+                    (e.position().map(csv::Position::line), Err(e))
+                }
+            };
+            match result {
+                Ok(trx) => self.publish_transaction(trx)?,
+                Err(e) => {
+                    warn!(error = %e, "unable to deserialize the row on line {:?}, writing it to the dead letter queue", line);
+                    let line = line.map(|l| l.to_string()).unwrap_or_default();
+                    let error = e.to_string();
+                    dlq.write_record(
+                        [line.as_str(), error.as_str()]
+                            .into_iter()
+                            .chain(record.iter()),
+                    )?;
+                    dead_lettered += 1;
+                }
+            }
+        }
+        dlq.flush()?;
+        Ok(dead_lettered)
+    }
+
     /// The implementation of how an [`IncomingTransaction`] should be processed
     fn publish_transaction(&mut self, transaction: IncomingTransaction) -> Result<()>;
+
     /// How the results should be outputted once processing is complete
-    fn output(self, writer: impl Write) -> Result<()>;
+    ///
+    /// # Default Implementation
+    ///
+    /// The default implementation calls [`SyncEngine::output_with_options`] with the default
+    /// [`OutputOptions`]
+    fn output(self, writer: impl Write) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.output_with_options(writer, OutputOptions::default())
+    }
+
+    /// How the results should be outputted once processing is complete, respecting the provided
+    /// [`OutputOptions`]
+    fn output_with_options(self, writer: impl Write, options: OutputOptions) -> Result<()>;
+}
+
+/// The outcome of leniently processing the transactions, see [`SyncEngine::process_lenient`]
+/// and [`AsyncEngine::process_lenient`]
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Debug, Default)]
+pub struct ProcessReport {
+    /// The number of rows which were processed successfully
+    pub processed: usize,
+    /// Every row which was skipped, as it either failed to deserialize or errored when it was
+    /// published, along with the zero-based index of the row
+    ///
+    /// The index counts every item yielded by the iterator, so for a csv reader it's the index of
+    /// the record excluding the header _(ie. the first record is `0`)_.
+    pub skipped: Vec<(usize, Report)>,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl ProcessReport {
+    fn record(&mut self, index: usize, result: Result<()>) {
+        match result {
+            Ok(()) => self.processed += 1,
+            Err(e) => {
+                warn!(error = %e, "skipping row {} as it failed to be processed", index);
+                self.skipped.push((index, e));
+            }
+        }
+    }
+}
+
+/// The outcome of processing the transactions with a deadline, see
+/// [`SyncEngine::process_with_deadline`] and [`AsyncEngine::process_with_deadline`]
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadlineOutcome {
+    /// Every transaction was processed before the deadline passed
+    Completed,
+    /// The deadline passed before every transaction was processed
+    ///
+    /// Nothing further was read from the iterator, so the unprocessed transactions are only
+    /// counted if the iterator knows exactly how many it has left _(see
+    /// [`Iterator::size_hint`])_.
+    Expired {
+        /// The number of transactions which were processed before the deadline passed
+        processed: usize,
+        /// The number of transactions which were left unprocessed, if it's known
+        remaining: Option<usize>,
+    },
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl DeadlineOutcome {
+    fn expired(processed: usize, unprocessed: &impl Iterator) -> Self {
+        let remaining = match unprocessed.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        };
+        warn!(
+            ?remaining,
+            "the deadline passed before processing completed, {} transactions were processed",
+            processed
+        );
+        Self::Expired {
+            processed,
+            remaining,
+        }
+    }
 }
 
 /// This trait representations the async interface required to process a series of incoming
@@ -82,18 +377,100 @@ pub trait AsyncEngine {
     /// on every element of the iterator and `await`s the resposne. If either the `Item` yielded by the iterator, or the
     /// publish_transaction call **errors** proccessing will be interupted and this function will
     /// return an error
-    async fn process(
+    ///
+    /// As with [`SyncEngine::process`], the transactions are processed within a `process` span.
+    async fn process<E>(
         &mut self,
-        iter: impl Iterator<Item = std::result::Result<IncomingTransaction, csv::Error>> + Send + Sync,
-    ) -> Result<()> {
-        for trx in iter {
-            self.publish_transaction(trx?).await?;
+        iter: impl Iterator<Item = std::result::Result<IncomingTransaction, E>> + Send + Sync,
+    ) -> Result<()>
+    where
+        E: Into<Report> + Send,
+    {
+        let span = process_span();
+        let start = Instant::now();
+        let mut count = 0;
+        let result = async {
+            for trx in iter {
+                self.publish_transaction(trx.map_err(Into::into)?).await?;
+                count += 1;
+            }
+            Ok(())
+        }
+        .instrument(span.clone())
+        .await;
+        record_process_span(&span, count, start);
+        result
+    }
+
+    /// The same as [`AsyncEngine::process`], however any row which fails to deserialize, or
+    /// which errors when it's published, is logged and skipped rather than interupting processing
+    ///
+    /// See [`SyncEngine::process_lenient`].
+    async fn process_lenient<E>(
+        &mut self,
+        iter: impl Iterator<Item = std::result::Result<IncomingTransaction, E>> + Send + Sync,
+    ) -> ProcessReport
+    where
+        E: Into<Report> + Send,
+    {
+        let mut report = ProcessReport::default();
+        for (index, trx) in iter.enumerate() {
+            let result = match trx {
+                Ok(trx) => self.publish_transaction(trx).await,
+                Err(e) => Err(e.into()),
+            };
+            report.record(index, result);
+        }
+        report
+    }
+
+    /// The same as [`AsyncEngine::process`], however processing stops once the `deadline` has
+    /// passed, returning a [`DeadlineOutcome`] of whether every transaction was processed
+    ///
+    /// See [`SyncEngine::process_with_deadline`].
+    async fn process_with_deadline<E>(
+        &mut self,
+        mut iter: impl Iterator<Item = std::result::Result<IncomingTransaction, E>> + Send + Sync,
+        deadline: Instant,
+    ) -> Result<DeadlineOutcome>
+    where
+        E: Into<Report> + Send,
+    {
+        let mut processed = 0;
+        loop {
+            if Instant::now() >= deadline {
+                return Ok(DeadlineOutcome::expired(processed, &iter));
+            }
+            match iter.next() {
+                Some(trx) => self.publish_transaction(trx.map_err(Into::into)?).await?,
+                None => return Ok(DeadlineOutcome::Completed),
+            }
+            processed += 1;
         }
-        Ok(())
     }
 
     /// The implementation of how an [`IncomingTransaction`] should be processed
     async fn publish_transaction(&mut self, transaction: IncomingTransaction) -> Result<()>;
+
     /// How the results should be outputted once processing is complete
-    async fn output(self, writer: impl Write + Send + Sync) -> Result<()>;
+    ///
+    /// # Default Implementation
+    ///
+    /// The default implementation calls [`AsyncEngine::output_with_options`] with the default
+    /// [`OutputOptions`]
+    async fn output(self, writer: impl Write + Send + Sync) -> Result<()>
+    where
+        Self: Sized,
+    {
+        self.output_with_options(writer, OutputOptions::default())
+            .await
+    }
+
+    /// How the results should be outputted once processing is complete, respecting the provided
+    /// [`OutputOptions`]
+    async fn output_with_options(
+        self,
+        writer: impl Write + Send + Sync,
+        options: OutputOptions,
+    ) -> Result<()>;
 }
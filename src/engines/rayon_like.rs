@@ -0,0 +1,173 @@
+//! An implementation which processes each client in parallel on a [`rayon`] work-stealing
+//! thread pool
+//!
+//! Unlike the other engines this is a _batch_ model. As each transaction is published it's
+//! only grouped with the other transactions of its client, nothing is processed until the
+//! output is requested. At that point each client's transactions are processed in the order
+//! they were published using the [`BasicEngine`] implementation, with the clients spread across
+//! the threads of the global [`rayon`] thread pool.
+//!
+//! The ordering within a client is therefore maintained, while the ordering across clients
+//! doesn't affect the result. As the threads steal work from each other, a client with far more
+//! transactions than the others doesn't hold up the remaining clients, however every
+//! transaction is held in memory until the output is written.
+//!
+//! # Examples
+//!
+//! ```
+//! use lib::SyncEngine;
+//! use lib::transaction::IncomingTransaction;
+//! use lib::engines::RayonLikeEngine;
+//! use csv::{ReaderBuilder, Trim};
+//! use std::path::PathBuf;
+//! use std::io;
+//!
+//! let path = PathBuf::from("./test_assets/simple/spec.csv");
+//! let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(path).unwrap();
+//! let mut engine: RayonLikeEngine = Default::default();
+//! let iter = reader.deserialize::<IncomingTransaction>();
+//! engine.process(iter).unwrap();
+//! engine.output(io::stdout()).unwrap();
+//! ```
+//!
+//! [`BasicEngine`]: crate::engines::BasicEngine
+
+use color_eyre::{eyre::WrapErr, Result};
+use fnv::FnvHashMap;
+use rayon::prelude::*;
+
+use std::io::Write;
+
+use crate::engines::BasicEngine;
+use crate::error::TransactionError;
+use crate::output::{OutputOptions, StreamingOutput};
+use crate::storage::Client;
+use crate::transaction::{IncomingTransaction, TransactionType};
+
+use super::SyncEngine;
+
+/// A batch implementation which processes each client's transactions in parallel
+///
+/// Each client's transactions are processed by their own instance of [`BasicEngine`], so a
+/// [`TransactionType::Transfer`] between two clients is rejected with
+/// [`TransactionError::UnsupportedTransfer`]
+///
+/// No handlers can be registered with the engine of each client, so a custom transaction type is
+/// skipped as a [`TransactionError::UnknownTransactionType`]
+#[derive(Default)]
+pub struct RayonLikeEngine {
+    transactions: FnvHashMap<u16, Vec<IncomingTransaction>>,
+}
+
+impl SyncEngine for RayonLikeEngine {
+    fn publish_transaction(&mut self, transaction: IncomingTransaction) -> Result<()> {
+        match transaction.ty {
+            TransactionType::Heartbeat => return Ok(()),
+            TransactionType::Transfer => {
+                return Err(TransactionError::UnsupportedTransfer { tx: transaction.tx }.into());
+            }
+            _ => {}
+        }
+        self.transactions
+            .entry(transaction.client)
+            .or_default()
+            .push(transaction);
+        Ok(())
+    }
+
+    /// Processes every client's transactions in parallel, before writing the resulting state of
+    /// the clients to the provided writer
+    ///
+    /// If processing any client fails, an error is returned without writing the output.
+    fn output_with_options(self, writer: impl Write, options: OutputOptions) -> Result<()> {
+        let clients = self
+            .transactions
+            .into_par_iter()
+            .map(|(id, transactions)| {
+                let mut engine = BasicEngine::default();
+                for transaction in transactions {
+                    engine
+                        .publish_transaction(transaction)
+                        .wrap_err_with(|| format!("failed to process client {}", id))?;
+                }
+                Ok(engine.clients().collect::<Vec<_>>())
+            })
+            .collect::<Result<Vec<Vec<Client>>>>()?;
+
+        let mut writer = StreamingOutput::with_options(writer, options);
+        writer.write_clients(clients.iter().flatten())?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rejects_transfers_between_clients() -> Result<()> {
+        let input = "type,client,tx,amount,dest\ndeposit,1,1,5.0,\ntransfer,1,2,1.0,2\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let mut engine = RayonLikeEngine::default();
+        let err = engine
+            .process(reader.deserialize::<IncomingTransaction>())
+            .expect_err("the transfer should be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::UnsupportedTransfer { tx: 2 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_unknown_transaction_types() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndepost,1,2,1.0\nwithdrawal,1,3,1.0\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input.as_bytes());
+        let mut engine = RayonLikeEngine::default();
+        engine.process(reader.deserialize::<IncomingTransaction>())?;
+
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        assert_eq!(
+            String::from_utf8(result)?,
+            "client,available,held,total,locked\n1,4.0000,0.0000,4.0000,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn preserves_the_order_of_each_clients_transactions() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\nwithdrawal,1,3,2.0\ndeposit,1,4,1.0\ndispute,1,1,\nwithdrawal,2,5,1.0\n";
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let mut engine = RayonLikeEngine::default();
+        engine.process(reader.deserialize::<IncomingTransaction>())?;
+        assert_eq!(engine.transactions[&1].len(), 4);
+
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        let mut rows = csv::Reader::from_reader(&*result)
+            .records()
+            .map(|r| Ok(r?.iter().map(str::to_string).collect::<Vec<_>>()))
+            .collect::<Result<Vec<_>>>()?;
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["1", "-1.0000", "5.0000", "4.0000", "false"],
+                vec!["2", "0.0000", "0.0000", "0.0000", "false"],
+            ]
+        );
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 //! An implementation that runs across multiple threads mimicing a _stream-like_ processing model.
 //!
 //! Each thread holds a distinct number of clients. The number of threads spun up is proportaional
-//! to the number of cpus running the process _identified via [`num_cpus::get`]_.
+//! to the number of cpus running the process _identified via [`num_cpus::get`]_, unless a fixed
+//! number of partitions is requested via [`StreamLikeEngine::with_threads`] or
+//! [`StreamLikeEngine::with_fixed_partitions`].
 //!
 //! For each incoming transaction, it's client id is identified and _"hashed"_ to
 //! identify which thread the transaction should be sent to. Each thread processes
@@ -12,6 +14,64 @@
 //! Similar to [`BasicEngine`], the overall ordering of transactions is maintained, however
 //! the workload is distributed over multiple threads.
 //!
+//! ## Partition skew
+//!
+//! The workload is only distributed if the client ids are spread across the partitions. In the
+//! worst case, where every client id maps to the same partition _(eg. every id is a multiple of
+//! the number of cpus)_, all of the work is carried out by a single thread and the overhead of
+//! the channels makes this slower than using [`BasicEngine`] directly. The results are still
+//! correct, and [`StreamLikeEngine::output_with_stats`] can be used to identify this skew. The
+//! `trx-hot-partition-bench` benchmark group measures this case.
+//!
+//! ## Worker panics
+//!
+//! If processing a transaction panics, the panic is caught by the partition and the transaction
+//! which was in-flight is recorded in [`PartitionStats::panicked`]. What happens next is dictated
+//! by the [`WorkerPanicPolicy`] the engine was created with:
+//!
+//! - [`WorkerPanicPolicy::Discard`] _(the default)_ - the partition stops processing, any
+//!   transactions still queued for it are dropped _(and counted in
+//!   [`PartitionStats::discarded`])_ and none of its clients are included in the output.
+//! - [`WorkerPanicPolicy::SkipTransaction`] - the in-flight transaction is skipped and the
+//!   partition carries on processing its remaining transactions.
+//!
+//! In either case the effect of the in-flight transaction is undefined, the panic may have
+//! occurred part way through updating the client _(eg. after the transaction was recorded, but
+//! before the funds were credited)_. Under [`WorkerPanicPolicy::SkipTransaction`] the client of the
+//! in-flight transaction may therefore be left in an inconsistent state, which is why it is not
+//! the default. Restarting the partition with a fresh [`BasicEngine`] isn't offered, as the state
+//! of every client the partition had already processed would be lost.
+//!
+//! ## Failed partitions
+//!
+//! If a partition stops because processing a transaction returned an error, its engine is lost
+//! and the state of its clients can't be output. By default the output then fails with that
+//! error, rather than silently writing an incomplete set of clients. Engines which would rather
+//! write the clients of the remaining partitions can opt in via
+//! [`StreamLikeEngine::with_lossy_output`], in which case each failure is only logged.
+//!
+//! ## Sharing threads between engines
+//!
+//! Each engine spawns a thread per partition, so creating several engines in one process _(eg.
+//! one per input file)_ can oversubscribe the machine. Instead the engines can be created with
+//! [`StreamLikeEngine::with_pool`], running their partitions on a shared [`WorkerPool`] so the
+//! total number of threads stays bounded.
+//!
+//! ## Backpressure
+//!
+//! By default each partition is fed through an unbounded channel, so if the transactions are
+//! published faster than a partition can process them _(eg. when reading a large input from
+//! disk)_, the backlog is buffered in memory with no upper limit. An engine created with
+//! [`StreamLikeEngine::with_capacity`] instead bounds each partition's channel, blocking
+//! [`SyncEngine::publish_transaction`] until the partition has room for the transaction.
+//!
+//! ## Tracing
+//!
+//! Each partition processes its transactions within its own `partition` span, which is a child
+//! of the span the engine was created within. Once the output is requested, the span records
+//! the number of transactions the partition processed, so a slow partition can be identified
+//! alongside the `process` span of [`SyncEngine::process`].
+//!
 //! # Examples
 //!
 //! ```
@@ -32,15 +92,22 @@
 //!
 //! [`BasicEngine`]: crate::engines::BasicEngine
 
-use color_eyre::Result;
-use crossbeam_channel::{unbounded, Sender, TryRecvError};
-use tracing::error;
+use color_eyre::{eyre::eyre, Result};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use tracing::{error, field, info, info_span, Span};
 
 use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crate::engines::BasicEngine;
-use crate::transaction::IncomingTransaction;
+use crate::error::TransactionError;
+use crate::output::{OutputOptions, StreamingOutput};
+use crate::stats::Stats;
+use crate::transaction::{IncomingTransaction, TransactionType};
 
 use super::SyncEngine;
 
@@ -48,84 +115,1021 @@ use super::SyncEngine;
 ///
 /// Each thread runs their own instance of [`BasicEngine`]
 pub struct StreamLikeEngine {
-    join_handles: Vec<JoinHandle<Result<BasicEngine>>>,
+    workers: Workers,
     channels: Vec<Sender<IncomingTransaction>>,
+    sort_output: bool,
+    lossy_output: bool,
+    stream_output: bool,
 }
 
+/// The engine of a partition _(if it wasn't discarded)_, along with the partition's stats
+type WorkerOutput = (Option<BasicEngine>, PartitionStats);
+
+/// How the partitions of a [`StreamLikeEngine`] are run
+enum Workers {
+    /// Each partition runs on its own dedicated thread
+    Threads(Vec<JoinHandle<Result<WorkerOutput>>>),
+    /// The partitions are run on a [`WorkerPool`] which may be shared with other engines
+    Pooled {
+        pool: WorkerPool,
+        partitions: Vec<Arc<PooledPartition>>,
+    },
+}
+
+/// Dictates how a partition of a [`StreamLikeEngine`] behaves if processing a transaction panics
+///
+/// See the [module level docs](self#worker-panics) for the data-loss semantics of each policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorkerPanicPolicy {
+    /// Stop processing the partition and leave its clients out of the output
+    #[default]
+    Discard,
+    /// Skip the in-flight transaction and continue processing the partition
+    SkipTransaction,
+}
+
+/// A transaction which was being processed when a partition panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InFlightTransaction {
+    /// The id of the client the transaction belonged to
+    pub client: u16,
+    /// The id of the transaction
+    pub tx: u32,
+}
+
+/// A summary of the work carried out by a single partition of a [`StreamLikeEngine`], useful
+/// for diagnosing any skew in how the clients are distributed across partitions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartitionStats {
+    /// The index of the partition
+    pub partition: usize,
+    /// The number of transactions the partition processed
+    pub processed: usize,
+    /// The time the partition spent processing transactions _(excluding time spent waiting)_
+    pub duration: Duration,
+    /// The number of transactions still waiting to be processed when output was requested
+    pub backlog: usize,
+    /// The transactions which were in-flight when the partition panicked
+    pub panicked: Vec<InFlightTransaction>,
+    /// The number of transactions which were dropped as the partition had stopped processing
+    /// after a panic _(see [`WorkerPanicPolicy::Discard`])_
+    pub discarded: usize,
+    /// The counts of the transactions processed by the partition's engine, these can be summed
+    /// to get the counts of the whole engine
+    pub transactions: Stats,
+}
+
+/// A fixed number of threads which can be shared by multiple [`StreamLikeEngine`]s, so that
+/// the total number of threads stays bounded regardless of how many engines exist
+///
+/// Cloning the pool is cheap, and every clone shares the same threads. The threads exit once
+/// every clone of the pool, and every engine created with it, has been dropped.
+///
+/// ```
+/// use lib::SyncEngine;
+/// use lib::transaction::IncomingTransaction;
+/// use lib::engines::{StreamLikeEngine, WorkerPanicPolicy, WorkerPool};
+/// use csv::{ReaderBuilder, Trim};
+/// use std::io;
+///
+/// let pool = WorkerPool::new(2);
+/// for path in ["./test_assets/simple/spec.csv", "./test_assets/larger/spec.csv"] {
+///     let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(path).unwrap();
+///     let mut engine = StreamLikeEngine::with_pool(&pool, WorkerPanicPolicy::default());
+///     engine.process(reader.deserialize::<IncomingTransaction>()).unwrap();
+///     engine.output(io::stdout()).unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct WorkerPool {
+    jobs: Sender<Job>,
+    threads: usize,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+impl WorkerPool {
+    /// Spawns a pool of `threads` threads _(at least 1)_
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+        let (jobs, r) = unbounded::<Job>();
+        for _ in 0..threads {
+            let r = r.clone();
+            thread::spawn(move || {
+                for job in r {
+                    job();
+                }
+            });
+        }
+        Self { jobs, threads }
+    }
+
+    /// The number of threads in the pool
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        // The pool's threads only exit once every sender has been dropped, so this can't fail
+        let _ = self.jobs.send(Box::new(job));
+    }
+}
+
+/// The state of a single partition, which processes its transactions in order
+struct Partition {
+    engine: Option<BasicEngine>,
+    stats: PartitionStats,
+    policy: WorkerPanicPolicy,
+    /// The span the partition processes its transactions within, which records the number of
+    /// transactions processed once the partition finishes
+    span: Span,
+}
+
+impl Partition {
+    fn new(partition: usize, policy: WorkerPanicPolicy) -> Self {
+        Self {
+            engine: Some(BasicEngine::default()),
+            stats: PartitionStats {
+                partition,
+                ..Default::default()
+            },
+            policy,
+            span: info_span!("partition", partition, processed = field::Empty),
+        }
+    }
+
+    fn process(&mut self, msg: IncomingTransaction) -> Result<()> {
+        let engine = match self.engine.as_mut() {
+            Some(engine) => engine,
+            None => {
+                self.stats.discarded += 1;
+                return Ok(());
+            }
+        };
+        let in_flight = InFlightTransaction {
+            client: msg.client,
+            tx: msg.tx,
+        };
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| engine.publish_transaction(msg)));
+        self.stats.duration += start.elapsed();
+        match result {
+            Ok(result) => {
+                result?;
+                self.stats.processed += 1;
+            }
+            Err(_) => {
+                error!(
+                    policy = ?self.policy,
+                    "partition {} panicked while processing transaction {} for client {}",
+                    self.stats.partition, in_flight.tx, in_flight.client
+                );
+                self.stats.panicked.push(in_flight);
+                if self.policy == WorkerPanicPolicy::Discard {
+                    if let Some(engine) = self.engine.take() {
+                        self.stats.transactions = *engine.stats();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> WorkerOutput {
+        if let Some(engine) = self.engine.as_ref() {
+            self.stats.transactions = *engine.stats();
+        }
+        self.span.record("processed", self.stats.processed);
+        (self.engine, self.stats)
+    }
+}
+
+/// A partition run on a [`WorkerPool`], rather than its own thread
+///
+/// Whenever a transaction is published to an idle partition, a job is scheduled on the pool to
+/// drain its queue. Only one job is scheduled at a time, so the transactions are still processed
+/// in order.
+struct PooledPartition {
+    queue: Receiver<IncomingTransaction>,
+    scheduled: AtomicBool,
+    /// Holds the error which stopped the partition if processing failed, and is emptied once
+    /// the partition has been output
+    state: Mutex<Option<Result<Partition>>>,
+}
+
+impl PooledPartition {
+    fn schedule(self: &Arc<Self>, pool: &WorkerPool) {
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            let partition = Arc::clone(self);
+            pool.execute(move || partition.drain());
+        }
+    }
+
+    fn drain(&self) {
+        loop {
+            self.drain_queue();
+            self.scheduled.store(false, Ordering::Release);
+            // A transaction may have been queued after the queue was drained, but before the
+            // partition was marked as idle, in which case no job would have been scheduled for it
+            if self.queue.is_empty() || self.scheduled.swap(true, Ordering::AcqRel) {
+                break;
+            }
+        }
+    }
+
+    fn drain_queue(&self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let span = match state.as_ref() {
+            Some(Ok(partition)) => partition.span.clone(),
+            _ => Span::none(),
+        };
+        let _entered = span.enter();
+        while let Ok(msg) = self.queue.try_recv() {
+            if let Some(Ok(partition)) = state.as_mut() {
+                if let Err(e) = partition.process(msg) {
+                    *state = Some(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Processes any transactions which are still queued, before taking the partition's output
+    fn finish(&self) -> Result<WorkerOutput> {
+        self.drain_queue();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.take() {
+            Some(partition) => partition.map(Partition::finish),
+            None => Err(eyre!("the partition has already been output")),
+        }
+    }
+}
+
+/// _Note:_ the partitions of the default engine have unbounded queues, so a producer which
+/// outpaces the partitions can buffer an arbitrary number of transactions in memory, see
+/// [`StreamLikeEngine::with_capacity`]
 impl Default for StreamLikeEngine {
     fn default() -> Self {
-        let cpus = num_cpus::get();
-        let mut join_handles = Vec::with_capacity(cpus);
-        let mut channels = Vec::with_capacity(cpus);
-        for _ in 0..cpus {
-            let (s, r) = unbounded();
+        Self::new(WorkerPanicPolicy::default())
+    }
+}
+
+impl StreamLikeEngine {
+    /// Creates a new engine, where each partition follows the provided [`WorkerPanicPolicy`]
+    pub fn new(policy: WorkerPanicPolicy) -> Self {
+        Self::with_partitions(num_cpus::get(), policy, false, None)
+    }
+
+    /// Creates a new engine with `threads` partitions _(at least 1)_, each running on its own
+    /// thread, rather than one per cpu
+    ///
+    /// This is useful when the input only contains a handful of distinct clients, as any
+    /// partition without a client would otherwise sit idle. Unlike
+    /// [`StreamLikeEngine::with_fixed_partitions`] the clients are output in the order their
+    /// partitions finish.
+    pub fn with_threads(threads: usize) -> Self {
+        Self::with_partitions(threads.max(1), WorkerPanicPolicy::default(), false, None)
+    }
+
+    /// Creates a new engine where each partition queues at most `channel_cap` transactions
+    /// _(at least 1)_ which are waiting to be processed
+    ///
+    /// Once a partition's queue is full, publishing a transaction to it blocks until the
+    /// partition has processed enough of its backlog to make room, bounding the memory used by
+    /// the engine regardless of how quickly the input is read. The engines created by the other
+    /// constructors don't bound their queues, see the [module level docs](self#backpressure).
+    pub fn with_capacity(channel_cap: usize) -> Self {
+        Self::with_partitions(
+            num_cpus::get(),
+            WorkerPanicPolicy::default(),
+            false,
+            Some(channel_cap.max(1)),
+        )
+    }
+
+    /// Creates a new engine with exactly `partitions` partitions _(at least 1)_, regardless of
+    /// the number of cpus available
+    ///
+    /// As the partition a client is assigned to then doesn't depend on the machine, the same
+    /// input is always grouped in the same way. The clients are also output in order of their
+    /// id, so the output is identical across machines.
+    pub fn with_fixed_partitions(partitions: usize) -> Self {
+        Self::with_partitions(partitions.max(1), WorkerPanicPolicy::default(), true, None)
+    }
+
+    /// Creates a new engine whose partitions are run on the provided [`WorkerPool`], rather
+    /// than each being given a dedicated thread
+    ///
+    /// The engine has one partition per thread in the pool, and doesn't spawn any threads of
+    /// its own.
+    pub fn with_pool(pool: &WorkerPool, policy: WorkerPanicPolicy) -> Self {
+        let mut partitions = Vec::with_capacity(pool.threads());
+        let mut channels = Vec::with_capacity(pool.threads());
+        for partition in 0..pool.threads() {
+            let (s, r) = unbounded::<IncomingTransaction>();
+            partitions.push(Arc::new(PooledPartition {
+                queue: r,
+                scheduled: AtomicBool::new(false),
+                state: Mutex::new(Some(Ok(Partition::new(partition, policy)))),
+            }));
+            channels.push(s);
+        }
+        Self {
+            workers: Workers::Pooled {
+                pool: pool.clone(),
+                partitions,
+            },
+            channels,
+            sort_output: false,
+            lossy_output: false,
+            stream_output: false,
+        }
+    }
+
+    /// Writes the clients of the partitions which succeeded even if another partition failed,
+    /// leaving the failed partition's clients out of the output
+    ///
+    /// The failure is only logged, so the output may be incomplete without the caller being
+    /// aware of it. See the [module level docs](self#failed-partitions).
+    pub fn with_lossy_output(mut self) -> Self {
+        self.lossy_output = true;
+        self
+    }
+
+    /// Writes the clients of each partition as soon as that partition has finished, flushing
+    /// the writer after each one, rather than collecting every client before writing any of them
+    ///
+    /// The partitions are finished in order, so a partition is written before the next
+    /// partition is waited on.
+    ///
+    /// This bounds the memory used by the output to the clients of a single partition. As
+    /// the clients are written before every partition has finished, if a later partition fails
+    /// the output will already contain the clients of the earlier partitions _(see the [module
+    /// level docs](self#failed-partitions))_.
+    ///
+    /// This has no effect on an engine created with [`StreamLikeEngine::with_fixed_partitions`],
+    /// as its clients must all be collected in order to be sorted.
+    pub fn with_streaming_output(mut self) -> Self {
+        self.stream_output = true;
+        self
+    }
+
+    fn with_partitions(
+        partitions: usize,
+        policy: WorkerPanicPolicy,
+        sort_output: bool,
+        capacity: Option<usize>,
+    ) -> Self {
+        let mut join_handles = Vec::with_capacity(partitions);
+        let mut channels = Vec::with_capacity(partitions);
+        for partition in 0..partitions {
+            let (s, r) = match capacity {
+                Some(capacity) => bounded::<IncomingTransaction>(capacity),
+                None => unbounded::<IncomingTransaction>(),
+            };
+            // The partition is created before the thread is spawned, so its span is a child of
+            // the span the engine was created within
+            let mut partition = Partition::new(partition, policy);
             let handle = thread::spawn(move || {
-                let mut client = BasicEngine::default();
-                'process: loop {
-                    match r.try_recv() {
-                        Ok(msg) => {
-                            client.publish_transaction(msg)?;
-                        }
-                        Err(TryRecvError::Empty) => thread::yield_now(),
-                        Err(TryRecvError::Disconnected) => break 'process,
-                    };
+                let span = partition.span.clone();
+                let _entered = span.enter();
+                // Parks the thread while the channel is empty, finishing once it's disconnected
+                for msg in r {
+                    partition.process(msg)?;
                 }
-                Ok(client)
+                Ok(partition.finish())
             });
             join_handles.push(handle);
             channels.push(s);
         }
         Self {
-            join_handles,
+            workers: Workers::Threads(join_handles),
             channels,
+            sort_output,
+            lossy_output: false,
+            stream_output: false,
         }
     }
 }
 
 impl SyncEngine for StreamLikeEngine {
+    /// Routes the transaction to the partition of its client
+    ///
+    /// Transfers are rejected with [`TransactionError::UnsupportedTransfer`], as the two clients
+    /// may be held by different partitions. No handlers can be registered with the engine of
+    /// each partition, so a custom transaction type is skipped as a
+    /// [`TransactionError::UnknownTransactionType`].
     fn publish_transaction(&mut self, transaction: IncomingTransaction) -> Result<()> {
+        if transaction.ty == TransactionType::Transfer {
+            return Err(TransactionError::UnsupportedTransfer { tx: transaction.tx }.into());
+        }
         let client_id = transaction.client;
         let bucket = client_id as usize % self.channels.len();
         self.channels[bucket].send(transaction)?;
+        if let Workers::Pooled { pool, partitions } = &self.workers {
+            partitions[bucket].schedule(pool);
+        }
         Ok(())
     }
 
-    /// Outputs the current state of the clients to the provided writer
-    fn output(mut self, writer: impl Write) -> Result<()> {
+    /// Outputs the current state of the clients to the provided writer, logging the
+    /// [`PartitionStats`] of each partition along with the [`Stats`] of every partition combined
+    fn output_with_options(self, writer: impl Write, options: OutputOptions) -> Result<()> {
+        let partitions = self.output_with_stats(writer, options)?;
+        for stats in &partitions {
+            info!(
+                partition = stats.partition,
+                processed = stats.processed,
+                duration = ?stats.duration,
+                backlog = stats.backlog,
+                panicked = stats.panicked.len(),
+                discarded = stats.discarded,
+                "partition finished processing"
+            );
+        }
+        partitions
+            .iter()
+            .map(|s| s.transactions)
+            .sum::<Stats>()
+            .log();
+        Ok(())
+    }
+}
+
+impl StreamLikeEngine {
+    /// The same as [`SyncEngine::output_with_options`], however the [`PartitionStats`] of each
+    /// partition are also returned
+    ///
+    /// If any partition failed an error is returned without writing the output, unless the
+    /// engine was created [`with_lossy_output`](StreamLikeEngine::with_lossy_output) in which
+    /// case the failed partitions are left out of the output and the returned stats. A partition
+    /// which was discarded after a panic is not a failure and is still included, see
+    /// [`WorkerPanicPolicy::Discard`].
+    pub fn output_with_stats(
+        mut self,
+        writer: impl Write,
+        options: OutputOptions,
+    ) -> Result<Vec<PartitionStats>> {
+        let backlogs = self.channels.iter().map(Sender::len).collect::<Vec<_>>();
         // Close the channels
         self.channels.clear();
 
-        // Finish up the tasks
-        let clients = self
-            .join_handles
-            .into_iter()
-            .enumerate()
-            .filter_map(|(i, h)| match h.join() {
-                Ok(c) => {
-                    match c {
-                        Ok(c) => {
-                            Some(c.clients())
-                        }
-                        Err(e) => {
-                            error!(error = %e, "an error occured on thread {}. the results from it are being ignored as we can't be sure of the validity of them", i);
-                            None
+        let mut stats = Vec::with_capacity(backlogs.len());
+
+        // Finish up the tasks, each partition is only waited on once the previous partition has
+        // been handled so a streamed partition is written before the next one is joined
+        let outputs: Box<dyn Iterator<Item = Result<WorkerOutput>>> = match self.workers {
+            Workers::Threads(join_handles) => {
+                Box::new(join_handles.into_iter().enumerate().map(|(i, h)| {
+                    h.join().unwrap_or_else(|e| {
+                        error!(
+                            error = ?e, "failed to join thread handle from thread {}, data has been lost",
+                            i
+                        );
+                        Err(eyre!("the thread running partition {} panicked", i))
+                    })
+                }))
+            }
+            Workers::Pooled { partitions, .. } => {
+                Box::new(partitions.into_iter().map(|p| p.finish()))
+            }
+        };
+        let stream = self.stream_output && !self.sort_output && !options.sort_by_id;
+        let mut writer = StreamingOutput::with_options(writer, options);
+        let mut clients = vec![];
+        for (i, output) in outputs.enumerate() {
+            match output {
+                Ok((engine, s)) => {
+                    stats.push(PartitionStats {
+                        backlog: backlogs[i],
+                        ..s
+                    });
+                    let partition = engine.into_iter().flat_map(BasicEngine::clients);
+                    if stream {
+                        for client in partition {
+                            writer.write_client(&client)?;
                         }
+                        writer.flush()?;
+                    } else {
+                        clients.extend(partition);
                     }
-                },
+                }
+                Err(e) if self.lossy_output => {
+                    error!(error = %e, "an error occured on thread {}. the results from it are being ignored as we can't be sure of the validity of them", i);
+                }
                 Err(e) => {
-                    error!(
-                        error = ?e, "failed to join thread handle from thread {}, data has been lost",
+                    return Err(e.wrap_err(format!(
+                        "partition {} failed, so the state of its clients is unknown",
                         i
-                    );
-                    None
+                    )))
                 }
+            }
+        }
+        if self.sort_output {
+            clients.sort_unstable_by_key(|c| c.id);
+        }
+        writer.write_clients(&clients)?;
+        writer.finish()?;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::storage::Client;
+    use crate::transaction::{CustomTransaction, TransactionType};
+    use crate::Amount;
+
+    fn deposit(client: u16, tx: u32) -> Result<IncomingTransaction> {
+        Ok(IncomingTransaction {
+            ty: TransactionType::Deposit,
+            client,
+            tx,
+            amount: Some(Amount::new(1.0)?),
+            timestamp: None,
+            row_id: None,
+            reason: None,
+            dest: None,
+        })
+    }
+
+    #[test]
+    fn is_correct_when_every_client_maps_to_the_same_partition() -> Result<()> {
+        let partitions = StreamLikeEngine::default().channels.len() as u16;
+        let mut rows = vec![];
+        for (i, client) in (0..10).map(|c| c * partitions).enumerate() {
+            let tx = i as u32 * 3;
+            rows.push(deposit(client, tx)?);
+            rows.push(deposit(client, tx + 1)?);
+            for ty in [TransactionType::Dispute, TransactionType::Chargeback] {
+                rows.push(IncomingTransaction {
+                    ty,
+                    client,
+                    tx,
+                    amount: None,
+                    timestamp: None,
+                    row_id: None,
+                    reason: None,
+                    dest: None,
+                });
+            }
+            // Ignored as the account is now frozen
+            rows.push(deposit(client, tx + 2)?);
+        }
+
+        let mut expected = BasicEngine::default();
+        let mut engine = StreamLikeEngine::default();
+        for row in rows {
+            let copy = IncomingTransaction {
+                ty: row.ty.clone(),
+                row_id: row.row_id.clone(),
+                reason: row.reason.clone(),
+                ..row
+            };
+            expected.publish_transaction(copy)?;
+            engine.publish_transaction(row)?;
+        }
+
+        let mut result = vec![];
+        let stats = engine.output_with_stats(&mut result, OutputOptions::default())?;
+        let hot = stats
+            .iter()
+            .find(|s| s.partition == 0)
+            .expect("partition 0");
+        assert_eq!(hot.processed, 50, "expected every row to hit partition 0");
+
+        let mut expected_result = vec![];
+        expected.output(&mut expected_result)?;
+        let sorted = |output: &[u8]| -> Result<Vec<csv::StringRecord>> {
+            let mut rows = csv::Reader::from_reader(output)
+                .records()
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            rows.sort_by_key(|r| r[0].parse::<u16>().unwrap_or_default());
+            Ok(rows)
+        };
+        assert_eq!(sorted(&result)?, sorted(&expected_result)?);
+        assert_eq!(sorted(&result)?.len(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_the_work_done_by_each_partition() -> Result<()> {
+        let mut engine = StreamLikeEngine::default();
+        let partitions = engine.channels.len();
+
+        // Client 0 is hot, while every other partition only receives a single transaction
+        let mut tx = 0;
+        for _ in 0..100 {
+            tx += 1;
+            engine.publish_transaction(deposit(0, tx)?)?;
+        }
+        for client in 1..partitions {
+            tx += 1;
+            engine.publish_transaction(deposit(client as u16, tx)?)?;
+        }
+
+        let mut stats = engine.output_with_stats(vec![], OutputOptions::default())?;
+        stats.sort_by_key(|s| s.partition);
+        assert_eq!(stats.len(), partitions);
+        assert_eq!(stats[0].processed, 100);
+        for s in &stats[1..] {
+            assert_eq!(s.processed, 1, "partition {}", s.partition);
+            assert!(s.processed < stats[0].processed);
+        }
+        Ok(())
+    }
+
+    /// The transaction type which makes a partition panic while processing it, as the engine
+    /// itself doesn't panic on any input
+    const PANIC: &str = "panic";
+
+    /// A [`CustomTransaction`] which always panics when it's applied
+    struct Panic;
+
+    impl CustomTransaction for Panic {
+        fn apply(&self, _: &mut Client, transaction_id: u32, _: Option<Amount>) -> Result<()> {
+            panic!(
+                "processing transaction {} was requested to panic",
+                transaction_id
+            );
+        }
+    }
+
+    /// Creates an engine with a partition per cpu in the same way as [`StreamLikeEngine::new`],
+    /// however the engine of each partition has the [`Panic`] handler registered
+    fn engine_which_can_panic(policy: WorkerPanicPolicy) -> StreamLikeEngine {
+        let (channels, workers) = (0..num_cpus::get())
+            .map(|partition| {
+                let (s, r) = unbounded::<IncomingTransaction>();
+                let mut partition = Partition::new(partition, policy);
+                if let Some(engine) = partition.engine.as_mut() {
+                    engine.register_transaction_type(PANIC, Panic);
+                }
+                let handle = thread::spawn(move || {
+                    for msg in r {
+                        partition.process(msg)?;
+                    }
+                    Ok(partition.finish())
+                });
+                (s, handle)
             })
-            .flatten();
-        let mut writer = csv::Writer::from_writer(writer);
-        for client in clients {
-            writer.serialize(client)?;
+            .unzip();
+        StreamLikeEngine {
+            workers: Workers::Threads(workers),
+            channels,
+            sort_output: false,
+            lossy_output: false,
+            stream_output: false,
+        }
+    }
+
+    /// Publishes a pair of deposits for client 0, causing the partition to panic while
+    /// processing `tx` 2, followed by a regular deposit and a deposit for a client on another
+    /// partition
+    fn publish_panicking_workload(engine: &mut StreamLikeEngine) -> Result<u16> {
+        engine.publish_transaction(deposit(0, 1)?)?;
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Custom(PANIC.to_string()),
+            ..deposit(0, 2)?
+        })?;
+        engine.publish_transaction(deposit(0, 3)?)?;
+        let other = if engine.channels.len() > 1 { 1 } else { 0 };
+        engine.publish_transaction(deposit(other, 4)?)?;
+        Ok(other)
+    }
+
+    fn client_ids(output: &[u8]) -> Result<Vec<u16>> {
+        let mut ids = csv::Reader::from_reader(output)
+            .records()
+            .map(|r| Ok(r?[0].parse::<u16>()?))
+            .collect::<Result<Vec<_>>>()?;
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    #[test]
+    fn partition_stats_sum_to_the_stats_of_the_whole_input() -> Result<()> {
+        let rows = || -> Result<Vec<IncomingTransaction>> {
+            let mut rows = vec![];
+            for tx in 0..30 {
+                let client = (tx % 7) as u16;
+                rows.push(deposit(client, tx)?);
+                if tx % 4 == 0 {
+                    rows.push(IncomingTransaction {
+                        ty: TransactionType::Dispute,
+                        amount: None,
+                        ..deposit(client, tx)?
+                    });
+                }
+            }
+            Ok(rows)
+        };
+        let mut expected = BasicEngine::default();
+        expected.process(rows()?.into_iter().map(Ok::<_, color_eyre::Report>))?;
+        let mut engine = StreamLikeEngine::with_threads(3);
+        engine.process(rows()?.into_iter().map(Ok::<_, color_eyre::Report>))?;
+
+        let partitions = engine.output_with_stats(vec![], OutputOptions::default())?;
+        let total = partitions.iter().map(|s| s.transactions).sum::<Stats>();
+        assert_eq!(total, *expected.stats());
+        assert_eq!(total.disputes, 8);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_transfers_between_clients() -> Result<()> {
+        let mut engine = StreamLikeEngine::with_threads(2);
+        engine.publish_transaction(deposit(0, 1)?)?;
+        let err = engine
+            .publish_transaction(IncomingTransaction {
+                ty: TransactionType::Transfer,
+                dest: Some(1),
+                ..deposit(0, 2)?
+            })
+            .expect_err("the transfer should be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::UnsupportedTransfer { tx: 2 })
+        );
+
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        assert_eq!(client_ids(&result)?, vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_unknown_transaction_types() -> Result<()> {
+        let mut engine = StreamLikeEngine::with_threads(2);
+        engine.publish_transaction(deposit(0, 1)?)?;
+        engine.publish_transaction(IncomingTransaction {
+            ty: TransactionType::Custom("depost".to_string()),
+            ..deposit(0, 2)?
+        })?;
+        engine.publish_transaction(deposit(0, 3)?)?;
+
+        let partitions = engine.output_with_stats(vec![], OutputOptions::default())?;
+        let total = partitions.iter().map(|s| s.transactions).sum::<Stats>();
+        assert_eq!(total.deposits, 2);
+        assert_eq!(total.custom, 0);
+        assert_eq!(total.skipped_errors, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn discards_a_partition_which_panics() -> Result<()> {
+        let mut engine = engine_which_can_panic(WorkerPanicPolicy::Discard);
+        let other = publish_panicking_workload(&mut engine)?;
+
+        let mut result = vec![];
+        let stats = engine.output_with_stats(&mut result, OutputOptions::default())?;
+        let hot = stats
+            .iter()
+            .find(|s| s.partition == 0)
+            .expect("partition 0");
+        assert_eq!(hot.panicked, vec![InFlightTransaction { client: 0, tx: 2 }]);
+        if other == 0 {
+            assert_eq!(hot.discarded, 2);
+            assert!(client_ids(&result)?.is_empty());
+        } else {
+            assert_eq!(hot.discarded, 1);
+            assert_eq!(client_ids(&result)?, vec![other]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn skips_a_transaction_which_panics() -> Result<()> {
+        let mut engine = engine_which_can_panic(WorkerPanicPolicy::SkipTransaction);
+        let other = publish_panicking_workload(&mut engine)?;
+
+        let mut result = vec![];
+        let stats = engine.output_with_stats(&mut result, OutputOptions::default())?;
+        let hot = stats
+            .iter()
+            .find(|s| s.partition == 0)
+            .expect("partition 0");
+        assert_eq!(hot.panicked, vec![InFlightTransaction { client: 0, tx: 2 }]);
+        assert_eq!(hot.discarded, 0);
+        let mut expected = vec![0, other];
+        expected.dedup();
+        assert_eq!(client_ids(&result)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_publishing_to_a_full_partition_until_it_has_room() -> Result<()> {
+        let mut engine = StreamLikeEngine::with_capacity(1);
+        assert!(engine.channels.iter().all(|c| c.capacity() == Some(1)));
+
+        // Every transaction is sent to the same partition, so the queue is repeatedly filled
+        let partitions = engine.channels.len() as u16;
+        for tx in 0..500 {
+            engine.publish_transaction(deposit((tx % 5) as u16 * partitions, tx)?)?;
+        }
+
+        let mut result = vec![];
+        let stats = engine.output_with_stats(&mut result, OutputOptions::default())?;
+        assert_eq!(stats.iter().map(|s| s.processed).sum::<usize>(), 500);
+        assert_eq!(client_ids(&result)?.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn spawns_the_requested_number_of_threads() -> Result<()> {
+        let mut engine = StreamLikeEngine::with_threads(3);
+        assert_eq!(engine.channels.len(), 3);
+        for tx in 0..9 {
+            engine.publish_transaction(deposit(tx as u16, tx)?)?;
+        }
+        let stats = engine.output_with_stats(vec![], OutputOptions::default())?;
+        assert_eq!(stats.len(), 3);
+        assert!(stats.iter().all(|s| s.processed == 3));
+
+        assert_eq!(StreamLikeEngine::with_threads(0).channels.len(), 1);
+        Ok(())
+    }
+
+    /// Creates an engine with 2 partitions, where partition 0 failed and partition 1
+    /// processed a deposit for client 1
+    fn engine_with_a_failed_partition() -> Result<StreamLikeEngine> {
+        let mut partition = Partition::new(1, WorkerPanicPolicy::default());
+        partition.process(deposit(1, 1)?)?;
+        let failed = thread::spawn(|| Err(eyre!("the partition failed")));
+        let succeeded = thread::spawn(move || Ok(partition.finish()));
+        Ok(StreamLikeEngine {
+            workers: Workers::Threads(vec![failed, succeeded]),
+            channels: vec![unbounded().0, unbounded().0],
+            sort_output: false,
+            lossy_output: false,
+            stream_output: false,
+        })
+    }
+
+    #[test]
+    fn fails_to_output_when_a_partition_failed() -> Result<()> {
+        let engine = engine_with_a_failed_partition()?;
+
+        let mut result = vec![];
+        let error = engine
+            .output_with_stats(&mut result, OutputOptions::default())
+            .expect_err("partition 0 failed");
+        assert!(error.to_string().contains("partition 0 failed"));
+        assert!(result.is_empty(), "no partial output should be written");
+        Ok(())
+    }
+
+    #[test]
+    fn outputs_the_remaining_partitions_when_lossy() -> Result<()> {
+        let engine = engine_with_a_failed_partition()?.with_lossy_output();
+
+        let mut result = vec![];
+        let stats = engine.output_with_stats(&mut result, OutputOptions::default())?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].partition, 1);
+        assert_eq!(client_ids(&result)?, vec![1]);
+        Ok(())
+    }
+
+    /// A writer which records the number of transactions still queued for the `pending`
+    /// partition each time it's flushed
+    struct ObservingWriter {
+        pending: Arc<PooledPartition>,
+        flushes: Vec<usize>,
+    }
+
+    impl Write for ObservingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes.push(self.pending.queue.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_each_partition_before_finishing_the_next() -> Result<()> {
+        // Occupy every thread of the pool, so the partitions are only processed when finished
+        let pool = WorkerPool::new(2);
+        let (release, blocked) = unbounded::<()>();
+        for _ in 0..pool.threads() {
+            let blocked = blocked.clone();
+            pool.execute(move || {
+                let _ = blocked.recv();
+            });
+        }
+
+        let run = |engine: StreamLikeEngine| -> Result<Vec<usize>> {
+            let mut engine = engine;
+            engine.publish_transaction(deposit(0, 1)?)?;
+            engine.publish_transaction(deposit(1, 2)?)?;
+            let pending = match &engine.workers {
+                Workers::Pooled { partitions, .. } => Arc::clone(&partitions[1]),
+                Workers::Threads(_) => unreachable!("the engine is pooled"),
+            };
+            let mut writer = ObservingWriter {
+                pending,
+                flushes: vec![],
+            };
+            engine.output_with_stats(&mut writer, OutputOptions::default())?;
+            Ok(writer.flushes)
+        };
+
+        let policy = WorkerPanicPolicy::default();
+        let streamed = run(StreamLikeEngine::with_pool(&pool, policy).with_streaming_output())?;
+        assert_eq!(
+            streamed.first(),
+            Some(&1),
+            "the first partition should be written while the second is still queued"
+        );
+        let buffered = run(StreamLikeEngine::with_pool(&pool, policy))?;
+        assert!(buffered.iter().all(|&queued| queued == 0));
+        drop(release);
+        Ok(())
+    }
+
+    #[test]
+    fn streams_the_same_clients_as_the_buffered_output() -> Result<()> {
+        let run = |engine: StreamLikeEngine| -> Result<Vec<u16>> {
+            let mut engine = engine;
+            for tx in 0..100 {
+                engine.publish_transaction(deposit(tx as u16 % 20, tx)?)?;
+            }
+            let mut result = vec![];
+            engine.output_with_stats(&mut result, OutputOptions::default())?;
+            client_ids(&result)
+        };
+
+        let streamed = run(StreamLikeEngine::with_threads(4).with_streaming_output())?;
+        assert_eq!(streamed, (0..20).collect::<Vec<_>>());
+        assert_eq!(streamed, run(StreamLikeEngine::with_threads(4))?);
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_partitions_produce_identical_output() -> Result<()> {
+        let run = || -> Result<(Vec<u8>, Vec<PartitionStats>)> {
+            let mut engine = StreamLikeEngine::with_fixed_partitions(3);
+            for tx in 0..30 {
+                engine.publish_transaction(deposit(tx as u16 % 10, tx)?)?;
+            }
+            let mut result = vec![];
+            let mut stats = engine.output_with_stats(&mut result, OutputOptions::default())?;
+            stats.sort_by_key(|s| s.partition);
+            Ok((result, stats))
+        };
+
+        let (lhs, stats) = run()?;
+        let (rhs, _) = run()?;
+        assert_eq!(lhs, rhs);
+        assert_eq!(
+            stats.iter().map(|s| s.processed).collect::<Vec<_>>(),
+            vec![12, 9, 9],
+            "expected clients to be assigned by `client % 3`"
+        );
+        let ids = csv::Reader::from_reader(&*lhs)
+            .records()
+            .map(|r| Ok(r?[0].parse::<u16>()?))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn engines_can_share_a_worker_pool() -> Result<()> {
+        let pool = WorkerPool::new(2);
+        let mut engines = [
+            StreamLikeEngine::with_pool(&pool, WorkerPanicPolicy::default()),
+            StreamLikeEngine::with_pool(&pool, WorkerPanicPolicy::default()),
+        ];
+        for engine in &engines {
+            assert!(
+                matches!(&engine.workers, Workers::Pooled { partitions, .. } if partitions.len() == 2),
+                "expected the engine to run on the pool without spawning any threads"
+            );
+        }
+        for tx in 0..100 {
+            let engine = &mut engines[tx as usize % 2];
+            engine.publish_transaction(deposit(tx as u16 % 5, tx)?)?;
+        }
+
+        for engine in engines {
+            let mut result = vec![];
+            let stats = engine.output_with_stats(&mut result, OutputOptions::default())?;
+            assert_eq!(stats.iter().map(|s| s.processed).sum::<usize>(), 50);
+            assert_eq!(client_ids(&result)?, vec![0, 1, 2, 3, 4]);
+            let totals = csv::Reader::from_reader(&*result)
+                .records()
+                .map(|r| Ok(r?[3].to_string()))
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(totals, vec!["10.0000"; 5]);
         }
-        writer.flush()?;
+        assert_eq!(pool.threads(), 2);
         Ok(())
     }
 }
@@ -0,0 +1,301 @@
+//! The errors which can occur while ingesting & processing transactions
+//!
+//! These are returned wrapped in a [`color_eyre::Report`], so can be matched on by downcasting
+//!
+//! ```
+//! use lib::error::TransactionError;
+//!
+//! let report = color_eyre::Report::new(TransactionError::FieldTooLarge {
+//!     line: Some(2),
+//!     size: 2048,
+//!     limit: 1024,
+//! });
+//! assert!(matches!(
+//!     report.downcast_ref::<TransactionError>(),
+//!     Some(TransactionError::FieldTooLarge { .. })
+//! ));
+//! ```
+
+use std::fmt;
+
+use crate::transaction::TransactionType;
+
+/// A matchable representation of the errors that can occur while processing transactions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    /// A field within an input row exceeded the configured maximum size
+    FieldTooLarge {
+        /// The line of the input the row started on, if known
+        line: Option<u64>,
+        /// The size in bytes of the offending field
+        size: usize,
+        /// The maximum allowed size in bytes
+        limit: usize,
+    },
+    /// A dispute referenced a deposit which is older than the client's dispute window, see
+    /// [`Client::with_dispute_window`](crate::storage::Client::with_dispute_window)
+    DisputeWindowExpired {
+        /// The id of the disputed deposit
+        tx: u32,
+        /// When the deposit occurred, in seconds since the unix epoch
+        deposited_at: u64,
+        /// When the dispute occurred, in seconds since the unix epoch
+        disputed_at: u64,
+    },
+    /// A deposit or withdrawal reused the id of an existing transaction of a different type
+    ///
+    /// A deposit or withdrawal reusing the id of a transaction of the same type is instead
+    /// rejected with [`TransactionError::DuplicateTransaction`].
+    IdTypeConflict {
+        /// The id of the transaction
+        tx: u32,
+        /// The type of the transaction already using the id
+        existing: TransactionType,
+        /// The type of the transaction which attempted to reuse the id
+        attempted: TransactionType,
+    },
+    /// A deposit, withdrawal or transfer reused the id of a transaction which has already been
+    /// processed
+    DuplicateTransaction {
+        /// The id of the transaction
+        tx: u32,
+    },
+    /// A withdrawal or transfer was for more than the client's available funds
+    InsufficientFunds {
+        /// The id of the withdrawal or transfer
+        tx: u32,
+    },
+    /// A dispute, resolve or chargeback isn't a valid transition from the current state of the
+    /// transaction it references, eg. resolving a transaction which isn't under dispute
+    InvalidTransition {
+        /// The id of the referenced transaction
+        tx: u32,
+        /// The transition which was attempted
+        attempted: TransactionType,
+    },
+    /// The client's account is frozen, either because the transaction was a chargeback which
+    /// froze it, or because it had already been frozen
+    ///
+    /// No further transactions can be processed for the client, however this isn't a failure of
+    /// the input so the engines skip the transaction and continue processing the other clients.
+    FrozenAccount {
+        /// The id of the client whose account is frozen
+        client: u16,
+    },
+    /// One or more accounts were frozen during a run which was configured to fail if that
+    /// happened, see
+    /// [`BasicEngine::with_fail_on_freeze`](crate::engines::BasicEngine::with_fail_on_freeze)
+    ///
+    /// This is a business rule rather than a processing failure, so it's only returned once the
+    /// output has been written.
+    AccountsFrozen {
+        /// The number of accounts which were frozen
+        count: usize,
+    },
+    /// A deposit, withdrawal or transfer had an empty amount field, which is rejected unless
+    /// [`InputOptions::reject_empty_amounts`](crate::input::InputOptions::reject_empty_amounts)
+    /// is unset
+    ///
+    /// An amount column which is absent from the row entirely is still treated as no amount.
+    EmptyAmount {
+        /// The line of the input the row started on, if known
+        line: Option<u64>,
+        /// The id of the transaction
+        tx: u32,
+    },
+    /// A dispute of a deposit was rejected as the client no longer had enough available funds to
+    /// hold, see
+    /// [`Client::with_dispute_policy`](crate::storage::Client::with_dispute_policy)
+    InsufficientFundsForDispute {
+        /// The id of the disputed deposit
+        tx: u32,
+    },
+    /// A transaction would have overflowed a client's available or held funds, so it was
+    /// rejected rather than applied
+    Overflow {
+        /// The id of the rejected transaction
+        tx: u32,
+    },
+    /// A transfer was published to an engine which holds each client in isolation, so it can't
+    /// apply the transfer to both clients at once
+    ///
+    /// Only [`BasicEngine`](crate::engines::BasicEngine) applies transfers, the other engines
+    /// return this rather than silently producing different balances.
+    UnsupportedTransfer {
+        /// The id of the transfer
+        tx: u32,
+    },
+    /// The transaction type isn't one of the native types and no
+    /// [`CustomTransaction`](crate::transaction::CustomTransaction) handler has been registered
+    /// for it, eg. a typo of a native type such as `depost`
+    ///
+    /// Handlers can only be registered with [`BasicEngine`](crate::engines::BasicEngine), so for
+    /// every other engine this is the case for any custom transaction type.
+    UnknownTransactionType {
+        /// The id of the transaction
+        tx: u32,
+        /// The name of the custom transaction type
+        name: String,
+    },
+}
+
+impl TransactionError {
+    /// Identifies whether the error only rejects the single transaction which caused it, in
+    /// which case processing can continue for the client
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            Self::DisputeWindowExpired { .. }
+                | Self::IdTypeConflict { .. }
+                | Self::DuplicateTransaction { .. }
+                | Self::InsufficientFunds { .. }
+                | Self::InvalidTransition { .. }
+                | Self::InsufficientFundsForDispute { .. }
+                | Self::Overflow { .. }
+                | Self::UnknownTransactionType { .. }
+        )
+    }
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FieldTooLarge { line, size, limit } => {
+                write!(
+                    f,
+                    "a field of {} bytes exceeds the maximum of {} bytes",
+                    size, limit
+                )?;
+                if let Some(line) = line {
+                    write!(f, " on line {}", line)?;
+                }
+                Ok(())
+            }
+            Self::DisputeWindowExpired {
+                tx,
+                deposited_at,
+                disputed_at,
+            } => write!(
+                f,
+                "unable to dispute transaction {} as the dispute window has expired, it was deposited at {} and disputed at {}",
+                tx, deposited_at, disputed_at
+            ),
+            Self::IdTypeConflict {
+                tx,
+                existing,
+                attempted,
+            } => write!(
+                f,
+                "unable to process {} {} as the id is already used by a {}",
+                attempted.as_str(),
+                tx,
+                existing.as_str()
+            ),
+            Self::DuplicateTransaction { tx } => write!(
+                f,
+                "unable to process transaction {} as the id has already been processed",
+                tx
+            ),
+            Self::InsufficientFunds { tx } => write!(
+                f,
+                "unable to process transaction {} as the account does not have enough available funds",
+                tx
+            ),
+            Self::InvalidTransition { tx, attempted } => write!(
+                f,
+                "unable to {} transaction {} from its current state",
+                attempted.as_str(),
+                tx
+            ),
+            Self::FrozenAccount { client } => write!(
+                f,
+                "unable to carry out the transaction as the account of client {} is frozen",
+                client
+            ),
+            Self::AccountsFrozen { count } => write!(
+                f,
+                "{} account(s) were frozen while processing the transactions",
+                count
+            ),
+            Self::EmptyAmount { line, tx } => {
+                write!(f, "transaction {} has an empty amount", tx)?;
+                if let Some(line) = line {
+                    write!(f, " on line {}", line)?;
+                }
+                Ok(())
+            }
+            Self::InsufficientFundsForDispute { tx } => write!(
+                f,
+                "unable to dispute transaction {} as the available funds are insufficient",
+                tx
+            ),
+            Self::Overflow { tx } => write!(
+                f,
+                "unable to process transaction {} as the funds would overflow",
+                tx
+            ),
+            Self::UnsupportedTransfer { tx } => write!(
+                f,
+                "unable to process transfer {} as this engine can't apply a transaction to two clients at once",
+                tx
+            ),
+            Self::UnknownTransactionType { tx, name } => write!(
+                f,
+                "unable to process transaction {} as no handler has been registered for its type {}",
+                tx, name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+/// The reasons an [`Amount`](crate::Amount) can fail to be constructed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountParseError {
+    /// The value was less than `0.0`
+    Negative,
+    /// The value was not a valid decimal number
+    Invalid(String),
+    /// The value was too large to be represented
+    Overflow,
+    /// An `f32` could not be converted without losing precision, see
+    /// [`Amount::try_from_f32_exact`](crate::Amount::try_from_f32_exact)
+    Inexact,
+    /// The value had a leading `+`, which is only rejected when parsing strictly, see
+    /// [`Amount::from_str_strict`](crate::Amount::from_str_strict)
+    LeadingPlus,
+    /// The value was a negative zero _(eg. `-0.0`)_, which is only rejected when parsing strictly
+    NegativeZero,
+    /// The value had leading or trailing whitespace, which is only rejected when parsing strictly
+    Whitespace,
+}
+
+impl fmt::Display for AmountParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Negative => write!(f, "expected a value greater than or equal to 0.0"),
+            Self::Invalid(reason) => write!(f, "invalid amount: {}", reason),
+            Self::Overflow => write!(f, "the amount is too large to be represented"),
+            Self::Inexact => write!(
+                f,
+                "the value can't be exactly represented as an amount, construct it from a string instead"
+            ),
+            Self::LeadingPlus => write!(f, "expected no leading '+' sign"),
+            Self::NegativeZero => write!(f, "expected zero to be unsigned, found a negative zero"),
+            Self::Whitespace => write!(f, "expected no leading or trailing whitespace"),
+        }
+    }
+}
+
+impl std::error::Error for AmountParseError {}
+
+impl From<rust_decimal::Error> for AmountParseError {
+    fn from(e: rust_decimal::Error) -> Self {
+        match e {
+            rust_decimal::Error::ExceedsMaximumPossibleValue
+            | rust_decimal::Error::LessThanMinimumPossibleValue => Self::Overflow,
+            e => Self::Invalid(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,921 @@
+//! Helpers related to reading the incoming transactions that an engine should process
+
+use color_eyre::{eyre::WrapErr, Result};
+use csv::StringRecord;
+use serde::{de, Deserialize, Deserializer};
+
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::error::TransactionError;
+use crate::transaction::{IncomingTransaction, TransactionType};
+use crate::Amount;
+
+/// The default maximum size in bytes of any single field within an input row
+pub const DEFAULT_MAX_FIELD_SIZE: usize = 4096;
+
+/// The name of the column holding the amount, and its position if the input has no headers
+const AMOUNT_COLUMN: (&str, usize) = ("amount", 3);
+
+/// The format the incoming transactions are encoded in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// A csv file with a `type,client,tx,amount` header
+    #[default]
+    Csv,
+    /// A newline delimited json file, with an object per transaction
+    /// _eg. `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`_
+    ///
+    /// The amount can be either a string or a number, however a number is read as an `f64` so
+    /// an amount with more than 15 significant digits should be a string to retain its exact
+    /// precision
+    Ndjson,
+}
+
+/// Configuration for how the incoming transactions should be read
+#[derive(Debug, Clone)]
+pub struct InputOptions {
+    /// The format of the input, the remaining options only apply to [`InputFormat::Csv`]
+    pub format: InputFormat,
+    /// The character separating each field within a row
+    pub delimiter: u8,
+    /// The character separating the whole and fractional parts of an amount
+    ///
+    /// If this is `,` then the `delimiter` must be something else _(eg. `;`)_ in order for the
+    /// amount to be unambiguous
+    pub decimal_separator: char,
+    /// Any row containing a field larger than this many bytes is rejected with
+    /// [`TransactionError::FieldTooLarge`]
+    ///
+    /// This protects against a single crafted row _(eg. an amount field that is megabytes long)_
+    /// being deserialized and processed. The field is measured as it's read by
+    /// [`BoundedFields`], so an oversized field is discarded rather than held in memory.
+    pub max_field_size: usize,
+    /// When reading a directory of input files, whether files within its sub-directories should
+    /// also be read
+    pub recursive: bool,
+    /// Treats the first column of each row as an id assigned by an external system, which is
+    /// read into [`IncomingTransaction::row_id`] rather than being deserialized by position
+    /// or name
+    pub row_id: bool,
+    /// Rejects any amount which is oddly formatted rather than normalizing it, see
+    /// [`Amount::from_str_strict`]
+    pub strict_amounts: bool,
+    /// Rejects any deposit, withdrawal or transfer whose amount field is present but empty _(or
+    /// only whitespace)_ with [`TransactionError::EmptyAmount`], rather than treating it as having
+    /// no amount. This is set by default
+    ///
+    /// A row which doesn't have an amount column at all is unaffected, as that's how disputes,
+    /// resolves and chargebacks are usually written.
+    pub reject_empty_amounts: bool,
+}
+
+impl Default for InputOptions {
+    fn default() -> Self {
+        Self {
+            format: InputFormat::Csv,
+            delimiter: b',',
+            decimal_separator: '.',
+            max_field_size: DEFAULT_MAX_FIELD_SIZE,
+            recursive: false,
+            row_id: false,
+            strict_amounts: false,
+            reject_empty_amounts: true,
+        }
+    }
+}
+
+impl InputFormat {
+    /// The file extension expected for inputs of this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+impl InputOptions {
+    /// A [`csv::ReaderBuilder`] configured to read input in the expected format
+    ///
+    /// Only the headers are trimmed when [`InputOptions::strict_amounts`] is set, as any
+    /// whitespace surrounding an amount is rejected. The other fields are then trimmed by
+    /// [`deserialize`].
+    pub fn reader_builder(&self) -> csv::ReaderBuilder {
+        let trim = match self.strict_amounts {
+            true => csv::Trim::Headers,
+            false => csv::Trim::All,
+        };
+        let mut builder = csv::ReaderBuilder::new();
+        builder.flexible(true).trim(trim).delimiter(self.delimiter);
+        builder
+    }
+
+    /// A [`csv::Reader`] configured to read input in the expected format, which measures each
+    /// field as it's read so that any field larger than [`InputOptions::max_field_size`] is
+    /// discarded, see [`BoundedFields`]
+    ///
+    /// This is the reader expected by [`deserialize`].
+    pub fn reader<R: Read>(&self, reader: R) -> csv::Reader<BoundedFields<R>> {
+        self.reader_builder()
+            .from_reader(BoundedFields::new(reader, self))
+    }
+}
+
+/// A reader which measures each csv field as it's read, discarding the contents of any field
+/// larger than the maximum field size before it reaches the [`csv::Reader`]
+///
+/// The csv reader buffers each row in memory, so without this a single field could grow without
+/// bound. Each field is instead buffered here, up to the maximum size, before being passed on.
+/// Once a field exceeds the maximum its bytes are dropped as they're read and it's replaced by a
+/// placeholder just over the maximum size, so [`deserialize`] can reject its row with
+/// [`TransactionError::FieldTooLarge`] while every other row is still read.
+///
+/// This is created by [`InputOptions::reader`].
+pub struct BoundedFields<R> {
+    inner: R,
+    delimiter: u8,
+    limit: usize,
+    /// The bytes of the current field, which are only passed on once the field has ended
+    field: Vec<u8>,
+    /// The size of the current field, which may be larger than the bytes which were kept
+    size: usize,
+    quoting: Quoting,
+    /// The bytes which are ready to be read by the csv reader, from `offset` onwards
+    ready: Vec<u8>,
+    offset: usize,
+    /// The size of each field which was replaced, in the order they were read
+    oversized: Arc<Mutex<VecDeque<usize>>>,
+}
+
+/// Whether the field being read is quoted, following the quoting rules of the csv reader
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    Unquoted,
+    Quoted,
+    /// A quote was read within a quoted field, which either closes the field or escapes the
+    /// following quote
+    MaybeClosed,
+}
+
+impl<R: Read> BoundedFields<R> {
+    fn new(inner: R, options: &InputOptions) -> Self {
+        Self {
+            inner,
+            delimiter: options.delimiter,
+            limit: options.max_field_size,
+            field: vec![],
+            size: 0,
+            quoting: Quoting::Unquoted,
+            ready: vec![],
+            offset: 0,
+            oversized: Arc::default(),
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.quoting = match (self.quoting, byte) {
+            (Quoting::Unquoted, b'"') if self.size == 0 => Quoting::Quoted,
+            (Quoting::Quoted, b'"') => Quoting::MaybeClosed,
+            (Quoting::MaybeClosed, b'"') => Quoting::Quoted,
+            (Quoting::MaybeClosed, _) => Quoting::Unquoted,
+            (quoting, _) => quoting,
+        };
+        if self.quoting == Quoting::Unquoted
+            && (byte == self.delimiter || byte == b'\n' || byte == b'\r')
+        {
+            self.end_field();
+            self.ready.push(byte);
+            return;
+        }
+        self.size += 1;
+        if self.size <= self.limit {
+            self.field.push(byte);
+        } else {
+            self.field.clear();
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.size > self.limit {
+            self.ready.resize(self.ready.len() + self.limit + 1, b'x');
+            self.oversized
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push_back(self.size);
+        } else {
+            self.ready.append(&mut self.field);
+        }
+        self.size = 0;
+    }
+}
+
+impl<R: Read> Read for BoundedFields<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0; 8 * 1024];
+        while self.offset == self.ready.len() {
+            self.ready.clear();
+            self.offset = 0;
+            let read = self.inner.read(&mut chunk)?;
+            if read == 0 {
+                if self.size == 0 {
+                    return Ok(0);
+                }
+                self.end_field();
+            }
+            for &byte in &chunk[..read] {
+                self.push(byte);
+            }
+        }
+        let ready = &self.ready[self.offset..];
+        let read = ready.len().min(buf.len());
+        buf[..read].copy_from_slice(&ready[..read]);
+        self.offset += read;
+        Ok(read)
+    }
+}
+
+/// Finds every file within the directory with the extension of the configured [`InputFormat`],
+/// sorted by their path so that they're processed in a deterministic order
+///
+/// Sub-directories are only searched if [`InputOptions::recursive`] is set. With the `gzip`
+/// feature enabled, compressed files _(eg. `2022-01-01.csv.gz`)_ are also included.
+pub fn files_in_dir(dir: &Path, options: &InputOptions) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if options.recursive {
+                    dirs.push(path);
+                }
+            } else if has_extension(&path, options.format.extension()) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    #[cfg(feature = "gzip")]
+    if is_gzip(path) {
+        return has_extension(&path.with_extension(""), extension);
+    }
+    path.extension() == Some(OsStr::new(extension))
+}
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("gz"))
+}
+
+/// Opens the file at the provided path for reading, decompressing it if it has a `.gz` extension
+///
+/// Reading a gzip compressed file requires the `gzip` feature, without it an error is returned
+/// rather than attempting to parse the compressed bytes.
+pub fn open(path: &Path) -> Result<Box<dyn Read + Send + Sync>> {
+    let file = fs::File::open(path).wrap_err_with(|| format!("failed to open {:?}", path))?;
+    if !is_gzip(path) {
+        return Ok(Box::new(file));
+    }
+    #[cfg(feature = "gzip")]
+    return Ok(Box::new(flate2::read::GzDecoder::new(
+        std::io::BufReader::new(file),
+    )));
+    #[cfg(not(feature = "gzip"))]
+    Err(color_eyre::eyre::eyre!(
+        "unable to read {:?} as reading gzip compressed input requires the `gzip` feature",
+        path
+    ))
+}
+
+/// Deserializes each line of the reader into an [`IncomingTransaction`], where each line is a
+/// json object
+///
+/// The amount of each transaction can be either a string _(eg. `"1.5"`)_ or a number _(eg.
+/// `1.5`)_, see [`InputFormat::Ndjson`].
+pub fn deserialize_ndjson<R: Read>(reader: R) -> impl Iterator<Item = Result<IncomingTransaction>> {
+    serde_json::Deserializer::from_reader(reader)
+        .into_iter::<JsonTransaction>()
+        .map(|trx| Ok(trx?.into()))
+}
+
+/// The json representation of an [`IncomingTransaction`]
+///
+/// This only differs in that the amount may also be a json number, which can't be accepted by
+/// [`Amount`] itself as the csv deserializer would then infer the type of the field, rather than
+/// parsing the exact string.
+#[derive(Deserialize)]
+struct JsonTransaction {
+    #[serde(rename = "type")]
+    ty: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(default)]
+    amount: Option<JsonAmount>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    row_id: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+    #[serde(default)]
+    dest: Option<u16>,
+}
+
+impl From<JsonTransaction> for IncomingTransaction {
+    fn from(trx: JsonTransaction) -> Self {
+        Self {
+            ty: trx.ty,
+            client: trx.client,
+            tx: trx.tx,
+            amount: trx.amount.map(|a| a.0),
+            timestamp: trx.timestamp,
+            row_id: trx.row_id,
+            reason: trx.reason,
+            dest: trx.dest,
+        }
+    }
+}
+
+/// An amount which was either a json string or a json number
+struct JsonAmount(Amount);
+
+impl<'de> Deserialize<'de> for JsonAmount {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct JsonAmountVisitor;
+
+        impl<'de> de::Visitor<'de> for JsonAmountVisitor {
+            type Value = JsonAmount;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a decimal amount as either a string or a number")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Self::Value, E> {
+                v.parse().map(JsonAmount).map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Self::Value, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<Self::Value, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> std::result::Result<Self::Value, E> {
+                // The shortest representation which round-trips, so `0.1` is read as `0.1`
+                // rather than the closest `f64` to it
+                self.visit_str(&v.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(JsonAmountVisitor)
+    }
+}
+
+/// Deserializes each row of the reader into an [`IncomingTransaction`] according to the provided
+/// [`InputOptions`]
+///
+/// The reader should be created by [`InputOptions::reader`] with the same options.
+pub fn deserialize<R: Read>(
+    mut reader: csv::Reader<BoundedFields<R>>,
+    options: &InputOptions,
+) -> Result<impl Iterator<Item = Result<IncomingTransaction>>> {
+    let oversized = Arc::clone(&reader.get_ref().oversized);
+    let headers = if reader.has_headers() {
+        let headers = reader.headers()?;
+        // The header row never reaches the records below, so its oversized fields are taken from
+        // those recorded by the reader here, as otherwise they'd be reported against later rows
+        let replaced = headers
+            .iter()
+            .filter(|field| field.len() > options.max_field_size)
+            .count();
+        if replaced > 0 {
+            let size = oversized
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .drain(..)
+                .next()
+                .unwrap_or_default();
+            return Err(TransactionError::FieldTooLarge {
+                line: headers.position().map(|p| p.line()),
+                size,
+                limit: options.max_field_size,
+            }
+            .into());
+        }
+        Some(if options.row_id {
+            headers.iter().skip(1).collect()
+        } else {
+            headers.clone()
+        })
+    } else {
+        None
+    };
+    // The position is only assumed when there are no headers, as otherwise the column in that
+    // position holds something else
+    let amount_column = match &headers {
+        Some(headers) => headers.iter().position(|name| name == AMOUNT_COLUMN.0),
+        None => Some(AMOUNT_COLUMN.1),
+    };
+    let options = options.clone();
+    Ok(reader.into_records().map(move |record| {
+        let mut record = record?;
+        // Each oversized field was replaced by a placeholder when it was read, so its actual
+        // size is taken from those recorded by the reader
+        let replaced = record
+            .iter()
+            .filter(|field| field.len() > options.max_field_size)
+            .count();
+        if replaced > 0 {
+            let mut oversized = oversized.lock().unwrap_or_else(PoisonError::into_inner);
+            let replaced = replaced.min(oversized.len());
+            let size = oversized.drain(..replaced).next().unwrap_or_default();
+            return Err(TransactionError::FieldTooLarge {
+                line: record.position().map(|p| p.line()),
+                size,
+                limit: options.max_field_size,
+            }
+            .into());
+        }
+        let row_id = if options.row_id {
+            let row_id = record.get(0).map(str::to_string);
+            let mut remaining = record.iter().skip(1).collect::<StringRecord>();
+            remaining.set_position(record.position().cloned());
+            record = remaining;
+            row_id
+        } else {
+            None
+        };
+        if let (Some(amount_column), true) = (amount_column, options.decimal_separator != '.') {
+            record = normalize_decimal_separator(&record, amount_column, options.decimal_separator);
+        }
+        let empty_amount = amount_column.and_then(|c| record.get(c)) == Some("");
+        let strict_amount = match options.strict_amounts {
+            true => strictly_parse_amount(&mut record, amount_column)?,
+            false => None,
+        };
+        let mut trx: IncomingTransaction = record.deserialize(headers.as_ref())?;
+        if strict_amount.is_some() {
+            trx.amount = strict_amount;
+        }
+        if options.reject_empty_amounts
+            && matches!(
+                trx.ty,
+                TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+            )
+            && empty_amount
+        {
+            return Err(TransactionError::EmptyAmount {
+                line: record.position().map(|p| p.line()),
+                tx: trx.tx,
+            }
+            .into());
+        }
+        if row_id.is_some() {
+            trx.row_id = row_id;
+        }
+        Ok(trx)
+    }))
+}
+
+/// Parses the amount of an untrimmed record with [`Amount::from_str_strict`], trimming every
+/// other field
+///
+/// The parsed amount is removed from the record, so that it isn't parsed again when the record
+/// is deserialized.
+fn strictly_parse_amount(
+    record: &mut StringRecord,
+    amount_column: Option<usize>,
+) -> Result<Option<Amount>> {
+    let amount = amount_column
+        .and_then(|c| record.get(c))
+        .filter(|a| !a.is_empty())
+        .map(Amount::from_str_strict)
+        .transpose()
+        .wrap_err_with(|| {
+            format!(
+                "invalid amount on line {}",
+                record.position().map_or(0, |p| p.line())
+            )
+        })?;
+    let mut trimmed = record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match Some(i) == amount_column {
+            true if amount.is_some() => "",
+            true => field,
+            false => field.trim(),
+        })
+        .collect::<StringRecord>();
+    trimmed.set_position(record.position().cloned());
+    *record = trimmed;
+    Ok(amount)
+}
+
+/// Replaces the decimal separator within the amount column with a `.`
+fn normalize_decimal_separator(
+    record: &StringRecord,
+    amount_column: usize,
+    separator: char,
+) -> StringRecord {
+    let mut normalized = record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            if i == amount_column {
+                field.replace(separator, ".")
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<StringRecord>();
+    normalized.set_position(record.position().cloned());
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::error::AmountParseError;
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decompresses_gzip_input() -> Result<()> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("2022-01-01.csv.gz");
+        let mut encoder = GzEncoder::new(fs::File::create(&path)?, Compression::default());
+        encoder.write_all(b"type,client,tx,amount\ndeposit,1,1,1.5\n")?;
+        encoder.finish()?;
+
+        let options = InputOptions::default();
+        assert_eq!(files_in_dir(dir.path(), &options)?, vec![path.clone()]);
+        let reader = options.reader(open(&path)?);
+        let transactions = deserialize(reader, &options)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(
+            transactions[0].amount.map(|a| a.to_exact_string()),
+            Some("1.5000".to_string())
+        );
+        Ok(())
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn rejects_gzip_input_without_the_gzip_feature() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("spec.csv.gz");
+        fs::write(&path, b"")?;
+        let err = open(&path)
+            .err()
+            .expect("expected the gzip input to be rejected");
+        assert!(err.to_string().contains("`gzip` feature"));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_json_amounts_as_either_strings_or_numbers() -> Result<()> {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 0.1}
+{"type": "deposit", "client": 1, "tx": 3, "amount": 2}
+{"type": "dispute", "client": 1, "tx": 1}
+"#;
+        let amounts = deserialize_ndjson(input.as_bytes())
+            .map(|trx| Ok(trx?.amount.map(|a| a.to_exact_string())))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            amounts,
+            vec![
+                Some("1.5000".to_string()),
+                Some("0.1000".to_string()),
+                Some("2.0000".to_string()),
+                None
+            ]
+        );
+
+        let invalid = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": true}"#;
+        assert!(deserialize_ndjson(invalid.as_bytes())
+            .next()
+            .expect("expected the row")
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_rows_with_fields_larger_than_the_limit() -> Result<()> {
+        let input = format!(
+            "type,client,tx,amount\ndeposit,1,1,{}\ndeposit,1,2,1.0\n",
+            "1".repeat(1024 * 1024)
+        );
+        let options = InputOptions::default();
+        let reader = options.reader(input.as_bytes());
+        let mut iter = deserialize(reader, &options)?;
+
+        let err = iter
+            .next()
+            .expect("expected the oversized row")
+            .expect_err("expected the oversized row to be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::FieldTooLarge {
+                line: Some(2),
+                size: 1024 * 1024,
+                limit: DEFAULT_MAX_FIELD_SIZE,
+            })
+        );
+        let trx = iter.next().expect("expected the valid row")?;
+        assert_eq!(
+            trx.tx, 2,
+            "rows after the rejected one should still be read"
+        );
+        assert!(iter.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn discards_an_oversized_field_as_it_is_read() -> Result<()> {
+        // The quoted field contains delimiters, newlines and escaped quotes
+        let input = format!(
+            "type,client,tx,amount,reason\ndispute,1,1,,\"{}\"\ndeposit,1,2,1.0,\n",
+            "a,\n\"\"".repeat(1024)
+        );
+        let options = InputOptions {
+            max_field_size: 8,
+            ..Default::default()
+        };
+
+        let largest_fields = options
+            .reader(input.as_bytes())
+            .records()
+            .map(|r| Ok(r?.iter().map(str::len).max().unwrap_or_default()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(largest_fields, vec![9, 7]);
+
+        let mut iter = deserialize(options.reader(input.as_bytes()), &options)?;
+        let err = iter
+            .next()
+            .expect("expected the oversized row")
+            .expect_err("expected the oversized row to be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::FieldTooLarge {
+                line: Some(2),
+                size: 2 + 5 * 1024,
+                limit: 8,
+            })
+        );
+        assert_eq!(iter.next().expect("expected the valid row")?.tx, 2);
+        assert!(iter.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_an_oversized_header() -> Result<()> {
+        let input = format!(
+            "type,client,tx,amount,{}\ndeposit,1,1,1.0,\n",
+            "r".repeat(20)
+        );
+        let options = InputOptions {
+            max_field_size: 8,
+            ..Default::default()
+        };
+        let err = deserialize(options.reader(input.as_bytes()), &options)
+            .map(|_| ())
+            .expect_err("expected the oversized header to be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::FieldTooLarge {
+                line: Some(1),
+                size: 20,
+                limit: 8,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parses_amounts_with_a_decimal_comma() -> Result<()> {
+        let input = "type;client;tx;amount\ndeposit;1;1;1,50\nwithdrawal;1;2;2\n";
+        let options = InputOptions {
+            delimiter: b';',
+            decimal_separator: ',',
+            ..Default::default()
+        };
+        let reader = options.reader(input.as_bytes());
+        let transactions = deserialize(reader, &options)?.collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(transactions.len(), 2);
+        let amount = transactions[0].amount.expect("expected an amount");
+        assert_eq!(amount.to_exact_string(), "1.5000");
+        let amount = transactions[1].amount.expect("expected an amount");
+        assert_eq!(amount.to_exact_string(), "2.0000");
+        Ok(())
+    }
+
+    #[test]
+    fn decimal_comma_is_not_applied_by_default() -> Result<()> {
+        let input = "type;client;tx;amount\ndeposit;1;1;1,50\n";
+        let options = InputOptions {
+            delimiter: b';',
+            ..Default::default()
+        };
+        let reader = options.reader(input.as_bytes());
+        let result = deserialize(reader, &options)?
+            .next()
+            .expect("expected a row");
+        assert!(result.is_err(), "`1,50` is not a valid amount by default");
+        Ok(())
+    }
+
+    #[test]
+    fn reads_the_first_column_as_the_row_id() -> Result<()> {
+        let input = "id,type,client,tx,amount\nabc-1,deposit,1,1,1.5\n";
+        let options = InputOptions {
+            row_id: true,
+            ..Default::default()
+        };
+        let reader = options.reader(input.as_bytes());
+        let transactions = deserialize(reader, &options)?.collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].row_id.as_deref(), Some("abc-1"));
+        assert_eq!(transactions[0].client, 1);
+        let amount = transactions[0].amount.expect("expected an amount");
+        assert_eq!(amount.to_exact_string(), "1.5000");
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_oddly_formatted_amounts_in_strict_mode() -> Result<()> {
+        let input = "type,client,tx,amount\ndeposit,1,1,+1.00\ndeposit,1,2,-0.0\ndeposit,1,3,1.0\n";
+        let read = |options: &InputOptions| -> Result<Vec<Result<IncomingTransaction>>> {
+            let reader = options.reader(input.as_bytes());
+            Ok(deserialize(reader, options)?.collect())
+        };
+
+        let lenient = read(&InputOptions::default())?;
+        assert!(lenient[0].is_ok(), "`+1.00` is accepted by default");
+
+        let strict = read(&InputOptions {
+            strict_amounts: true,
+            ..Default::default()
+        })?;
+        let errors = strict
+            .iter()
+            .map(|r| {
+                r.as_ref()
+                    .err()
+                    .and_then(|e| e.downcast_ref::<AmountParseError>())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                Some(&AmountParseError::LeadingPlus),
+                Some(&AmountParseError::NegativeZero),
+                None
+            ]
+        );
+        assert!(strict[2].is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_whitespace_around_an_amount_in_strict_mode() -> Result<()> {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.0\n deposit ,1,2,1.5\n";
+        let options = InputOptions {
+            strict_amounts: true,
+            ..Default::default()
+        };
+        let rows = deserialize(options.reader(input.as_bytes()), &options)?.collect::<Vec<_>>();
+        assert_eq!(
+            rows[0]
+                .as_ref()
+                .err()
+                .and_then(|e| e.downcast_ref::<AmountParseError>()),
+            Some(&AmountParseError::Whitespace)
+        );
+        // Every other field is still trimmed
+        let trx = rows[1]
+            .as_ref()
+            .expect("only the amount is checked for whitespace");
+        assert_eq!(trx.ty, TransactionType::Deposit);
+        assert_eq!(trx.tx, 2);
+        assert_eq!(
+            trx.amount.map(|a| a.to_exact_string()),
+            Some("1.5000".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn does_not_assume_the_amount_column_when_the_headers_have_no_amount() -> Result<()> {
+        let input = "type,client,tx,timestamp\ndeposit,1,1,1000\n";
+        for options in [
+            InputOptions::default(),
+            InputOptions {
+                strict_amounts: true,
+                ..Default::default()
+            },
+            InputOptions {
+                decimal_separator: ',',
+                ..Default::default()
+            },
+        ] {
+            let rows = deserialize(options.reader(input.as_bytes()), &options)?.collect::<Vec<_>>();
+            let trx = rows[0]
+                .as_ref()
+                .expect("the row doesn't need an amount to be read");
+            assert!(trx.amount.is_none(), "the timestamp isn't an amount");
+            assert_eq!(trx.timestamp, Some(1000));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn distinguishes_an_empty_amount_from_a_missing_amount() -> Result<()> {
+        let read =
+            |input: &str, options: &InputOptions| -> Result<Vec<Result<IncomingTransaction>>> {
+                let reader = options.reader(input.as_bytes());
+                Ok(deserialize(reader, options)?.collect())
+            };
+        let options = InputOptions::default();
+
+        let empty = read(
+            "type,client,tx,amount
+deposit,1,1,
+deposit,1,2,  
+",
+            &options,
+        )?;
+        let errors = empty
+            .iter()
+            .map(|r| {
+                r.as_ref()
+                    .err()
+                    .and_then(|e| e.downcast_ref::<TransactionError>())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                Some(&TransactionError::EmptyAmount {
+                    line: Some(2),
+                    tx: 1
+                }),
+                Some(&TransactionError::EmptyAmount {
+                    line: Some(3),
+                    tx: 2
+                }),
+            ]
+        );
+
+        let lenient = read(
+            "type,client,tx,amount
+deposit,1,1,
+",
+            &InputOptions {
+                reject_empty_amounts: false,
+                ..Default::default()
+            },
+        )?;
+        assert!(lenient[0].as_ref().is_ok_and(|t| t.amount.is_none()));
+
+        // Without an amount column there's nothing to distinguish, so the amount is just missing
+        let missing = read(
+            "type,client,tx
+deposit,1,1
+",
+            &options,
+        )?;
+        assert!(missing[0].as_ref().is_ok_and(|t| t.amount.is_none()));
+
+        // Only deposits and withdrawals require an amount
+        let dispute = read(
+            "type,client,tx,amount
+dispute,1,1,
+",
+            &options,
+        )?;
+        assert!(dispute[0].is_ok());
+        Ok(())
+    }
+}
@@ -15,7 +15,8 @@
 //!
 //! ### Single-Threaded
 //!
-//! ```
+#![cfg_attr(feature = "basic_engine", doc = "```")]
+#![cfg_attr(not(feature = "basic_engine"), doc = "```ignore")]
 //! use lib::{SyncEngine, run_sync};
 //! use lib::engines::BasicEngine;
 //! use std::path::PathBuf;
@@ -28,7 +29,8 @@
 //!
 //! ### Multi-Threaded
 //!
-//! ```
+#![cfg_attr(feature = "stream_engine", doc = "```")]
+#![cfg_attr(not(feature = "stream_engine"), doc = "```ignore")]
 //! use lib::{SyncEngine, run_sync};
 //! use lib::engines::StreamLikeEngine;
 //! use std::path::PathBuf;
@@ -41,7 +43,8 @@
 //!
 //! ### Async
 //!
-//! ```rust
+#![cfg_attr(feature = "actor_engine", doc = "```")]
+#![cfg_attr(not(feature = "actor_engine"), doc = "```ignore")]
 //! use lib::{AsyncEngine, run_async};
 //! use lib::engines::ActorLikeEngine;
 //! use std::path::PathBuf;
@@ -57,9 +60,22 @@
 //! [`IncomingTransaction`]: crate::transaction::IncomingTransaction
 
 pub mod amount;
+#[cfg(feature = "result_cache")]
+pub mod cache;
 pub mod engines;
+pub mod error;
+pub mod input;
+#[cfg(feature = "metadata")]
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod output;
+pub mod stats;
 pub mod storage;
+pub mod summary;
 pub mod transaction;
+#[cfg(feature = "basic_engine")]
+pub mod verify;
 
 #[doc(inline)]
 pub use amount::Amount;
@@ -78,7 +94,20 @@ pub use engines::SyncEngine;
 #[doc(no_inline)]
 pub use clap::Parser;
 
-use std::path::PathBuf;
+#[cfg(feature = "sync")]
+use color_eyre::Report;
+
+use std::path::{Path, PathBuf};
+
+use input::{InputFormat, InputOptions, DEFAULT_MAX_FIELD_SIZE};
+use output::{OutputFormat, OutputOptions};
+use summary::ColorChoice;
+use transaction::IncomingTransaction;
+
+/// The number of parsed transactions that can be buffered between the parsing and processing
+/// threads in [`run_sync_pipelined`] before the parsing thread blocks
+#[cfg(feature = "sync")]
+const PIPELINE_CAPACITY: usize = 1024;
 
 /// A very simple command line argument parser to read a path from the first argument passed to the
 /// binary
@@ -91,20 +120,246 @@ use std::path::PathBuf;
 /// ```
 #[derive(Parser)]
 pub struct Cli {
+    /// The input file, or a directory containing input files which will be processed in order
+    /// of their filename
     #[clap(parse(from_os_str))]
     pub path: PathBuf,
+    /// When the path is a directory, also reads the input files within its sub-directories
+    #[clap(long)]
+    pub recursive: bool,
+    /// Treats the first column of each row as an id assigned by an external system, which is
+    /// included in any error relating to the row
+    #[clap(long)]
+    pub row_id: bool,
+    /// Rejects any amount with a leading `+`, a negative zero or surrounding whitespace, rather
+    /// than normalizing it
+    #[clap(long)]
+    pub strict_amounts: bool,
+    /// Ignores any deposit or withdrawal with an empty amount field, rather than rejecting it
+    #[clap(long)]
+    pub allow_empty_amounts: bool,
+    /// The format of the input file
+    #[clap(long, value_enum, default_value_t = InputFormat::Csv)]
+    pub input_format: InputFormat,
+    /// Only processes the transactions of these clients _(eg. `--include 1,2`)_
+    #[clap(long, value_name = "CLIENTS", use_value_delimiter = true)]
+    pub include: Vec<u16>,
+    /// Skips the transactions of these clients _(eg. `--exclude 3,4`)_
+    #[clap(long, value_name = "CLIENTS", use_value_delimiter = true)]
+    pub exclude: Vec<u16>,
+    /// Appends a `has_open_disputes` column to the output
+    #[clap(long)]
+    pub with_dispute_flag: bool,
+    /// Shuffles the input across clients using the provided seed, while preserving the order of
+    /// each client's transactions
+    #[clap(long, value_name = "SEED")]
+    pub shuffle: Option<u64>,
+    /// Writes the amounts in the output as floating point numbers rather than exact decimal strings
+    #[clap(long)]
+    pub numeric_amounts: bool,
+    /// Rounds the amounts in the output to this many decimal places, this is display-only and
+    /// doesn't affect the precision used while processing
+    #[clap(long, value_name = "DP", value_parser = clap::value_parser!(u32).range(0..=4))]
+    pub output_precision: Option<u32>,
+    /// The format the clients are written to `stdout` in
+    #[clap(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+    /// Writes the clients in ascending order of their id, so the output is identical across runs
+    #[clap(long)]
+    pub sort_by_id: bool,
+    /// Rather than writing the output, verifies that it matches this expected csv file. Every
+    /// differing client and field is listed on `stderr`, and the process exits with a non-zero
+    /// status if there are any
+    #[clap(long, value_name = "EXPECTED", parse(from_os_str))]
+    pub verify: Option<PathBuf>,
+    /// Writes an audit trail of each client's balances after every transaction to this file, which
+    /// includes a `row_id` column when `--row-id` is set
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    pub audit: Option<PathBuf>,
+    /// Prints the total available and held funds across every client to `stderr`
+    #[clap(long)]
+    pub book_totals: bool,
+    /// Writes a JSON document describing the run _(the input's path and hash, the engine, the
+    /// crate version, a timestamp and the number of transactions)_ to this file once processing
+    /// is complete
+    #[cfg(feature = "metadata")]
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    pub emit_metadata: Option<PathBuf>,
+    /// Whether the summaries printed to `stderr` are colorized, by default they are only
+    /// colorized when `stderr` is a terminal. This never affects the csv output
+    #[clap(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+    /// Prevents clients that have only had ignored transactions from appearing in the output
+    #[clap(long)]
+    pub prune_empty_clients: bool,
+    /// Exits with an error once the output has been written if any account was frozen
+    #[clap(long)]
+    pub fail_on_freeze: bool,
+    /// Holds any dispute, resolve or chargeback which arrives before the deposit or withdrawal it
+    /// references for up to this many rows, applying it once that transaction arrives
+    #[clap(long, value_name = "ROWS")]
+    pub out_of_order_lookahead: Option<usize>,
+    /// Rejects any row containing a field larger than this many bytes
+    #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_FIELD_SIZE)]
+    pub max_field_size: usize,
+    /// The character separating each field of the input
+    #[clap(long, default_value = ",", value_parser = parse_ascii_char)]
+    pub delimiter: u8,
+    /// The character separating the whole and fractional parts of an amount in the input
+    #[clap(long, default_value = ".", value_parser = parse_ascii_char)]
+    pub decimal_separator: u8,
+}
+
+fn parse_ascii_char(value: &str) -> Result<u8, String> {
+    match value.as_bytes() {
+        [c] if c.is_ascii() => Ok(*c),
+        _ => Err("expected a single ascii character".to_string()),
+    }
+}
+
+impl Cli {
+    /// Builds the [`InputOptions`] requested through the command line arguments
+    pub fn input_options(&self) -> InputOptions {
+        InputOptions {
+            format: self.input_format,
+            delimiter: self.delimiter,
+            decimal_separator: self.decimal_separator as char,
+            max_field_size: self.max_field_size,
+            recursive: self.recursive,
+            row_id: self.row_id,
+            strict_amounts: self.strict_amounts,
+            reject_empty_amounts: !self.allow_empty_amounts,
+        }
+    }
+
+    /// Identifies whether the transactions of the provided client should be processed, based on
+    /// the `--include` and `--exclude` arguments
+    pub fn includes_client(&self, client: u16) -> bool {
+        (self.include.is_empty() || self.include.contains(&client))
+            && !self.exclude.contains(&client)
+    }
+
+    /// Builds the [`OutputOptions`] requested through the command line arguments
+    pub fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            with_dispute_flag: self.with_dispute_flag,
+            numeric_amounts: self.numeric_amounts,
+            precision: self.output_precision,
+            format: self.output_format,
+            sort_by_id: self.sort_by_id,
+            ..Default::default()
+        }
+    }
+}
+
+/// Opens the file at the provided path, returning an iterator which deserializes each row
+/// into an [`IncomingTransaction`] according to the provided [`InputOptions`]
+///
+/// If the path is a directory, every input file within it is read as a single stream in order of
+/// their filename, see [`input::files_in_dir`].
+pub fn transactions_from_path(
+    path: &Path,
+    options: &InputOptions,
+) -> color_eyre::Result<impl Iterator<Item = color_eyre::Result<IncomingTransaction>>> {
+    if !path.is_dir() {
+        return transactions_from_file(path, options);
+    }
+    let files = input::files_in_dir(path, options)?;
+    let options = options.clone();
+    let transactions: Box<dyn Iterator<Item = color_eyre::Result<IncomingTransaction>>> =
+        Box::new(files.into_iter().flat_map(move |file| {
+            match transactions_from_file(&file, &options) {
+                Ok(transactions) => transactions,
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            }
+        }));
+    Ok(transactions)
+}
+
+fn transactions_from_file(
+    path: &Path,
+    options: &InputOptions,
+) -> color_eyre::Result<Box<dyn Iterator<Item = color_eyre::Result<IncomingTransaction>>>> {
+    let transactions: Box<dyn Iterator<Item = color_eyre::Result<IncomingTransaction>>> =
+        match options.format {
+            InputFormat::Csv => {
+                let reader = options.reader(input::open(path)?);
+                Box::new(input::deserialize(reader, options)?)
+            }
+            InputFormat::Ndjson => {
+                let reader = std::io::BufReader::new(input::open(path)?);
+                Box::new(input::deserialize_ndjson(reader))
+            }
+        };
+    Ok(transactions)
+}
+
+/// A helper function to read a csv file from the provided path and process it synchronously,
+/// returning the engine rather than writing its output
+///
+/// This allows the caller to inspect the processed engine, or control how it's output. A file
+/// with a `.gz` extension is decompressed as it's read, see [`input::open`].
+///
+/// # Examples
+///
+#[cfg_attr(feature = "basic_engine", doc = "```")]
+#[cfg_attr(not(feature = "basic_engine"), doc = "```ignore")]
+/// use lib::engines::BasicEngine;
+/// use lib::process_file;
+///
+/// let engine = process_file("./test_assets/simple/spec.csv".into(), BasicEngine::default())?;
+/// assert_eq!(engine.client(1).unwrap().available_amount().to_exact_string(), "1.8750");
+/// # Ok::<(), color_eyre::Report>(())
+/// ```
+#[cfg(feature = "sync")]
+pub fn process_file<E: SyncEngine>(path: PathBuf, engine: E) -> color_eyre::Result<E> {
+    process_reader(input::open(&path)?, engine)
+}
+
+/// The same as [`process_file`], however the csv is read from the provided reader _(eg. `stdin`,
+/// a `TcpStream` or an in-memory buffer)_ rather than a file
+///
+/// The csv is read with the default [`InputOptions`], so it's validated in the same manner as
+/// the input read by [`transactions_from_path`].
+///
+/// # Examples
+///
+#[cfg_attr(feature = "basic_engine", doc = "```")]
+#[cfg_attr(not(feature = "basic_engine"), doc = "```ignore")]
+/// use lib::engines::BasicEngine;
+/// use lib::process_reader;
+///
+/// let input = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+/// let engine = process_reader(input.as_bytes(), BasicEngine::default())?;
+/// assert_eq!(engine.client(1).unwrap().available_amount().to_exact_string(), "1.5000");
+/// # Ok::<(), color_eyre::Report>(())
+/// ```
+#[cfg(feature = "sync")]
+pub fn process_reader<E: SyncEngine>(
+    reader: impl std::io::Read,
+    mut engine: E,
+) -> color_eyre::Result<E> {
+    let options = InputOptions::default();
+    let reader = options.reader(reader);
+    engine.process(input::deserialize(reader, &options)?)?;
+    Ok(engine)
 }
 
 /// A helper function to read a csv file from the provided path, process it synchronously and
 /// write the result to `stdout`
 #[cfg(feature = "sync")]
-pub fn run_sync(path: PathBuf, mut engine: impl SyncEngine) -> color_eyre::Result<()> {
-    let mut reader = csv::ReaderBuilder::new()
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .from_path(path)?;
-    let iter = reader.deserialize::<transaction::IncomingTransaction>();
-    engine.process(iter)?;
+pub fn run_sync(path: PathBuf, engine: impl SyncEngine) -> color_eyre::Result<()> {
+    run_sync_with_options(path, engine, OutputOptions::default())
+}
+
+/// The same as [`run_sync`], however the csv is read from the provided reader rather than a
+/// file, see [`process_reader`]
+#[cfg(feature = "sync")]
+pub fn run_sync_reader(
+    reader: impl std::io::Read,
+    engine: impl SyncEngine,
+) -> color_eyre::Result<()> {
+    let engine = process_reader(reader, engine)?;
     let mut writer = csv::WriterBuilder::new()
         .from_writer(std::io::stdout())
         .into_inner()?;
@@ -112,22 +367,192 @@ pub fn run_sync(path: PathBuf, mut engine: impl SyncEngine) -> color_eyre::Resul
     Ok(())
 }
 
+/// The same as [`run_sync`], however the result is written using the provided [`OutputOptions`]
+#[cfg(feature = "sync")]
+pub fn run_sync_with_options(
+    path: PathBuf,
+    engine: impl SyncEngine,
+    options: OutputOptions,
+) -> color_eyre::Result<()> {
+    let engine = process_file(path, engine)?;
+    let mut writer = csv::WriterBuilder::new()
+        .from_writer(std::io::stdout())
+        .into_inner()?;
+    engine.output_with_options(&mut writer, options)?;
+    Ok(())
+}
+
+/// A helper function to read a newline delimited json file from the provided path, process it
+/// synchronously and write the result to `stdout`
+///
+/// See [`input::deserialize_ndjson`] for the expected format of each line.
+#[cfg(feature = "sync")]
+pub fn run_sync_json(path: PathBuf, mut engine: impl SyncEngine) -> color_eyre::Result<()> {
+    let reader = std::io::BufReader::new(input::open(&path)?);
+    engine.process(input::deserialize_ndjson(reader))?;
+    let mut writer = csv::WriterBuilder::new()
+        .from_writer(std::io::stdout())
+        .into_inner()?;
+    engine.output(&mut writer)?;
+    Ok(())
+}
+
+/// The same as [`run_sync_with_options`], however the input is read with the provided
+/// [`InputOptions`] and is first reordered across clients via [`transaction::shuffle`]
+///
+/// _Note:_ this reads the entire input into memory before processing.
+#[cfg(feature = "sync")]
+pub fn run_sync_shuffled(
+    path: PathBuf,
+    mut engine: impl SyncEngine,
+    seed: u64,
+    input_options: &InputOptions,
+    options: OutputOptions,
+) -> color_eyre::Result<()> {
+    let reader = input_options.reader(input::open(&path)?);
+    let transactions = input::deserialize(reader, input_options)?.collect::<Result<Vec<_>, _>>()?;
+    engine.process(
+        transaction::shuffle(transactions, seed)
+            .into_iter()
+            .map(Ok::<_, Report>),
+    )?;
+    let mut writer = csv::WriterBuilder::new()
+        .from_writer(std::io::stdout())
+        .into_inner()?;
+    engine.output_with_options(&mut writer, options)?;
+    Ok(())
+}
+
+/// A helper function to read a csv file from the provided path, process it synchronously and
+/// write the result to `stdout`
+///
+/// Unlike [`run_sync`], the csv rows are deserialized on a separate thread to the one processing
+/// them, allowing the I/O & parsing to overlap with the processing. The transactions are still
+/// processed one at a time and in order, so the result is identical to [`run_sync`].
+///
+/// The csv is read with the provided [`InputOptions`].
+#[cfg(feature = "sync")]
+pub fn run_sync_pipelined(
+    path: PathBuf,
+    mut engine: impl SyncEngine,
+    input_options: &InputOptions,
+) -> color_eyre::Result<()> {
+    let reader = input_options.reader(input::open(&path)?);
+    process_pipelined(reader, input_options, &mut engine)?;
+    let mut writer = csv::WriterBuilder::new()
+        .from_writer(std::io::stdout())
+        .into_inner()?;
+    engine.output(&mut writer)?;
+    Ok(())
+}
+
+/// Deserializes the rows of the provided reader on a separate thread, passing them through a
+/// bounded channel to be processed by the engine on the current thread
+///
+/// The reader should be created by [`InputOptions::reader`] with the same options, see
+/// [`input::deserialize`].
+///
+/// If the engine stops processing early due to an error, the parsing thread will also stop.
+#[cfg(feature = "sync")]
+pub fn process_pipelined<R: std::io::Read + Send + 'static>(
+    reader: csv::Reader<input::BoundedFields<R>>,
+    options: &InputOptions,
+    engine: &mut impl SyncEngine,
+) -> color_eyre::Result<()> {
+    let transactions = input::deserialize(reader, options)?;
+    let (sender, receiver) = std::sync::mpsc::sync_channel(PIPELINE_CAPACITY);
+    let parser = std::thread::spawn(move || {
+        for trx in transactions {
+            if sender.send(trx).is_err() {
+                // The processing side has hung up, so there's no reason to continue parsing
+                break;
+            }
+        }
+    });
+    let result = engine.process(receiver.into_iter());
+    if parser.join().is_err() {
+        return Err(color_eyre::eyre::eyre!("the csv parsing thread panicked"));
+    }
+    result
+}
+
+/// The same as [`process_file`], however the file is processed asynchronously
+#[cfg(feature = "async")]
+pub async fn process_file_async<E: AsyncEngine + Send + Sync>(
+    path: PathBuf,
+    engine: E,
+) -> color_eyre::Result<E> {
+    process_reader_async(input::open(&path)?, engine).await
+}
+
+/// The same as [`process_reader`], however the csv is processed asynchronously
+///
+/// _Note:_ the reader is read from synchronously, so reading a slow source _(eg. a socket)_ will
+/// block the task processing it.
+#[cfg(feature = "async")]
+pub async fn process_reader_async<E: AsyncEngine + Send + Sync>(
+    reader: impl std::io::Read + Send + Sync,
+    mut engine: E,
+) -> color_eyre::Result<E> {
+    let options = InputOptions::default();
+    let reader = options.reader(reader);
+    engine
+        .process(input::deserialize(reader, &options)?)
+        .await?;
+    Ok(engine)
+}
+
 /// A helper function to read a csv file from the provided path, process it asynchronously and
 /// write the result to `stdout`
 #[cfg(feature = "async")]
 pub async fn run_async(
+    path: PathBuf,
+    engine: impl AsyncEngine + Send + Sync,
+) -> color_eyre::Result<()> {
+    run_async_with_options(path, engine, OutputOptions::default()).await
+}
+
+/// The same as [`run_async`], however the csv is read from the provided reader rather than a
+/// file, see [`process_reader_async`]
+#[cfg(feature = "async")]
+pub async fn run_async_reader(
+    reader: impl std::io::Read + Send + Sync,
+    engine: impl AsyncEngine + Send + Sync,
+) -> color_eyre::Result<()> {
+    let engine = process_reader_async(reader, engine).await?;
+    let mut writer = csv::WriterBuilder::new()
+        .from_writer(std::io::stdout())
+        .into_inner()?;
+    engine.output(&mut writer).await?;
+    Ok(())
+}
+
+/// The same as [`run_sync_json`], however the file is processed asynchronously
+#[cfg(feature = "async")]
+pub async fn run_async_json(
     path: PathBuf,
     mut engine: impl AsyncEngine + Send + Sync,
 ) -> color_eyre::Result<()> {
-    let mut reader = csv::ReaderBuilder::new()
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .from_path(path)?;
-    let iter = reader.deserialize::<transaction::IncomingTransaction>();
-    engine.process(iter).await?;
+    let reader = std::io::BufReader::new(input::open(&path)?);
+    engine.process(input::deserialize_ndjson(reader)).await?;
     let mut writer = csv::WriterBuilder::new()
         .from_writer(std::io::stdout())
         .into_inner()?;
     engine.output(&mut writer).await?;
     Ok(())
 }
+
+/// The same as [`run_async`], however the result is written using the provided [`OutputOptions`]
+#[cfg(feature = "async")]
+pub async fn run_async_with_options(
+    path: PathBuf,
+    engine: impl AsyncEngine + Send + Sync,
+    options: OutputOptions,
+) -> color_eyre::Result<()> {
+    let engine = process_file_async(path, engine).await?;
+    let mut writer = csv::WriterBuilder::new()
+        .from_writer(std::io::stdout())
+        .into_inner()?;
+    engine.output_with_options(&mut writer, options).await?;
+    Ok(())
+}
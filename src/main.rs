@@ -1,12 +1,107 @@
 use clap::Parser;
-use color_eyre::Result;
+use color_eyre::{Report, Result};
 
-use lib::engines::BasicEngine;
-use lib::{run_sync, Cli};
+use std::fs::File;
+use std::io::{self, IsTerminal};
+
+use lib::engines::{AuditingEngine, BasicEngine};
+#[cfg(feature = "metadata")]
+use lib::metadata::RunMetadata;
+use lib::summary::write_summary;
+use lib::transaction::{shuffle, IncomingTransaction};
+use lib::verify::verify_with_options;
+use lib::{transactions_from_path, Cli, SyncEngine};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
     let args = Cli::parse();
-    run_sync(args.path, BasicEngine::default())?;
+    let options = args.output_options();
+
+    if let Some(ref expected) = args.verify {
+        let report = verify_with_options(&args.path, expected, &args.input_options())?;
+        for mismatch in &report.mismatches {
+            eprintln!("{}", mismatch);
+        }
+        if !report.is_match() {
+            eprintln!("{} mismatches found", report.mismatches.len());
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut engine = BasicEngine::default();
+    if args.prune_empty_clients {
+        engine = engine.with_empty_client_pruning();
+    }
+    if args.fail_on_freeze {
+        engine = engine.with_fail_on_freeze();
+    }
+    if let Some(rows) = args.out_of_order_lookahead {
+        engine = engine.with_out_of_order_lookahead(rows);
+    }
+    let transactions = transactions_from_path(&args.path, &args.input_options())?;
+    let engine = match args.audit {
+        Some(ref path) => {
+            let mut engine = AuditingEngine::new(engine, File::create(path)?);
+            if args.input_options().row_id {
+                engine = engine.with_row_id();
+            }
+            process(&mut engine, transactions, &args)?;
+            engine.into_inner()?
+        }
+        None => {
+            process(&mut engine, transactions, &args)?;
+            engine
+        }
+    };
+
+    #[cfg(feature = "metadata")]
+    if let Some(ref path) = args.emit_metadata {
+        RunMetadata::new(
+            &args.path,
+            &args.input_options(),
+            BasicEngine::NAME,
+            engine.stats(),
+        )?
+        .write(File::create(path)?)?;
+    }
+
+    if args.book_totals {
+        match engine.book_totals() {
+            Some((available, held)) => {
+                let stderr = io::stderr();
+                let colorize = args.color.should_colorize(stderr.is_terminal());
+                write_summary(
+                    stderr.lock(),
+                    &[
+                        ("total available", &available.to_exact_string()),
+                        ("total held", &held.to_exact_string()),
+                    ],
+                    colorize,
+                )?;
+            }
+            None => eprintln!("unable to calculate the book totals as they overflow"),
+        }
+    }
+
+    engine.output_with_options(io::stdout(), options)?;
     Ok(())
 }
+
+fn process(
+    engine: &mut impl SyncEngine,
+    transactions: impl Iterator<Item = Result<IncomingTransaction>>,
+    args: &Cli,
+) -> Result<()> {
+    let filter = |client| args.includes_client(client);
+    match args.shuffle {
+        Some(seed) => {
+            let transactions = transactions.collect::<Result<Vec<_>, _>>()?;
+            engine.process_filtered(
+                shuffle(transactions, seed).into_iter().map(Ok::<_, Report>),
+                filter,
+            )
+        }
+        None => engine.process_filtered(transactions, filter),
+    }
+}
@@ -0,0 +1,87 @@
+//! Metadata describing how an output was produced, which can be written as a sidecar to the
+//! output for auditability _(see `--emit-metadata`)_
+//!
+//! ```
+//! use lib::input::InputOptions;
+//! use lib::metadata::RunMetadata;
+//! use lib::stats::Stats;
+//! use std::path::Path;
+//!
+//! let path = Path::new("./test_assets/simple/spec.csv");
+//! let metadata = RunMetadata::new(path, &InputOptions::default(), "basic", &Stats::default())?;
+//! assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
+//!
+//! let mut json = vec![];
+//! metadata.write(&mut json)?;
+//! # Ok::<(), color_eyre::Report>(())
+//! ```
+
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::input::{self, InputOptions};
+use crate::stats::Stats;
+
+/// The provenance of a single run over an input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// The path of the input, as it was provided
+    pub input: PathBuf,
+    /// The hex encoded SHA-256 hash of the input, if the input is a directory this is the hash
+    /// of every input file within it concatenated in the order they were processed
+    pub input_sha256: String,
+    /// The name of the engine which processed the input _(eg. `basic`)_
+    pub engine: String,
+    /// The version of this crate
+    pub version: String,
+    /// When the metadata was generated in seconds since the unix epoch
+    pub timestamp: u64,
+    /// The number of transactions which were processed from the input, see
+    /// [`Stats::transactions`]
+    pub transactions: usize,
+}
+
+impl RunMetadata {
+    /// Gathers the metadata of a run from the stats of the engine which processed it, hashing
+    /// the input found at `path`
+    pub fn new(path: &Path, options: &InputOptions, engine: &str, stats: &Stats) -> Result<Self> {
+        Ok(Self {
+            input: path.to_path_buf(),
+            input_sha256: hash_path(path, options)?,
+            engine: engine.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            transactions: stats.transactions(),
+        })
+    }
+
+    /// Writes the metadata to the provided writer as a JSON document
+    pub fn write(&self, mut writer: impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// Hashes the input found at `path`, reading every input file within it if it's a directory
+fn hash_path(path: &Path, options: &InputOptions) -> Result<String> {
+    let files = match path.is_dir() {
+        true => input::files_in_dir(path, options)?,
+        false => vec![path.to_path_buf()],
+    };
+    let mut hasher = Sha256::new();
+    for file in files {
+        io::copy(&mut File::open(file)?, &mut hasher)?;
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
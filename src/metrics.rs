@@ -0,0 +1,164 @@
+//! Emits metrics about the transactions being processed through the [`metrics`] facade, allowing
+//! any `metrics` compatible exporter _(eg. Prometheus)_ to be wired up
+//!
+//! This module is only available with the `metrics` feature, without it no metrics are recorded.
+//! Metrics are currently emitted by the [`BasicEngine`], and therefore also by the engines built
+//! on top of it.
+//!
+//! | name | type | description |
+//! | ---- | ---- | ----------- |
+//! | `trx_transactions_processed_total` | counter | transactions processed, labelled by `type` |
+//! | `trx_frozen_accounts_total` | counter | accounts which have been frozen |
+//! | `trx_errors_total` | counter | transactions which were rejected or failed to process |
+//! | `trx_open_disputes` | gauge | transactions which are currently under dispute |
+//!
+//! [`metrics`]: ::metrics
+//! [`BasicEngine`]: crate::engines::BasicEngine
+
+use ::metrics::{counter, describe_counter, describe_gauge, gauge, SharedString};
+
+use crate::transaction::TransactionType;
+
+/// The number of transactions processed, labelled by their `type`
+pub const TRANSACTIONS_PROCESSED: &str = "trx_transactions_processed_total";
+/// The number of accounts which have been frozen
+pub const FROZEN_ACCOUNTS: &str = "trx_frozen_accounts_total";
+/// The number of transactions which were rejected or failed to process
+pub const ERRORS: &str = "trx_errors_total";
+/// The number of transactions which are currently under dispute
+pub const OPEN_DISPUTES: &str = "trx_open_disputes";
+
+/// Registers a description of each metric with the installed recorder, this is optional however
+/// some exporters include the descriptions in their output
+pub fn describe() {
+    describe_counter!(
+        TRANSACTIONS_PROCESSED,
+        "transactions processed, labelled by type"
+    );
+    describe_counter!(FROZEN_ACCOUNTS, "accounts which have been frozen");
+    describe_counter!(
+        ERRORS,
+        "transactions which were rejected or failed to process"
+    );
+    describe_gauge!(
+        OPEN_DISPUTES,
+        "transactions which are currently under dispute"
+    );
+}
+
+pub(crate) fn record_transaction(ty: &TransactionType) {
+    let label: SharedString = match ty {
+        TransactionType::Custom(name) => name.clone().into(),
+        _ => SharedString::const_str(builtin_label(ty)),
+    };
+    counter!(TRANSACTIONS_PROCESSED, "type" => label).increment(1);
+}
+
+pub(crate) fn record_frozen_account() {
+    counter!(FROZEN_ACCOUNTS).increment(1);
+}
+
+pub(crate) fn record_error() {
+    counter!(ERRORS).increment(1);
+}
+
+/// Records a change in the number of a client's open disputes from `before` to `after`
+pub(crate) fn record_open_disputes_change(before: usize, after: usize) {
+    if after > before {
+        gauge!(OPEN_DISPUTES).increment((after - before) as f64);
+    } else if before > after {
+        gauge!(OPEN_DISPUTES).decrement((before - after) as f64);
+    }
+}
+
+fn builtin_label(ty: &TransactionType) -> &'static str {
+    match ty {
+        TransactionType::Deposit => "deposit",
+        TransactionType::Withdrawal => "withdrawal",
+        TransactionType::Dispute => "dispute",
+        TransactionType::Resolve => "resolve",
+        TransactionType::Chargeback => "chargeback",
+        TransactionType::Transfer => "transfer",
+        TransactionType::Heartbeat => "heartbeat",
+        TransactionType::Custom(_) => "custom",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use color_eyre::Result;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use pretty_assertions::assert_eq;
+
+    use crate::engines::BasicEngine;
+    use crate::transaction::IncomingTransaction;
+    use crate::SyncEngine;
+
+    /// The `(name, type label, value)` of each recorded metric
+    type Metrics = Vec<(String, Option<String>, DebugValue)>;
+
+    /// The value of the metric with the provided name, and `type` label if provided
+    fn value<'a>(metrics: &'a Metrics, name: &str, ty: Option<&str>) -> Option<&'a DebugValue> {
+        metrics
+            .iter()
+            .find(|(n, t, _)| n == name && t.as_deref() == ty)
+            .map(|(.., value)| value)
+    }
+
+    #[test]
+    fn records_the_metrics_of_each_transaction() -> Result<()> {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,3.0\ndeposit,2,3,1.0\ndispute,1,1,\ndispute,1,2,\nchargeback,1,1,\ndispute,2,3,\nwithdrawal,2,3,1.0\n";
+
+        metrics::with_local_recorder(&recorder, || -> Result<()> {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_reader(input.as_bytes());
+            let mut engine = BasicEngine::default();
+            engine.process(reader.deserialize::<IncomingTransaction>())
+        })?;
+
+        let metrics: Metrics = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let ty = key
+                    .labels()
+                    .find(|l| l.key() == "type")
+                    .map(|l| l.value().to_string());
+                (key.name().to_string(), ty, value)
+            })
+            .collect();
+        let counter = |name, ty| value(&metrics, name, ty);
+        assert_eq!(
+            counter(TRANSACTIONS_PROCESSED, Some("deposit")),
+            Some(&DebugValue::Counter(3))
+        );
+        assert_eq!(
+            counter(TRANSACTIONS_PROCESSED, Some("dispute")),
+            Some(&DebugValue::Counter(3))
+        );
+        assert_eq!(
+            counter(TRANSACTIONS_PROCESSED, Some("chargeback")),
+            Some(&DebugValue::Counter(1))
+        );
+        assert_eq!(
+            counter(FROZEN_ACCOUNTS, None),
+            Some(&DebugValue::Counter(1))
+        );
+        // The withdrawal reuses the id of a deposit
+        assert_eq!(counter(ERRORS, None), Some(&DebugValue::Counter(1)));
+        // Both of client 1's disputes are closed once the account is frozen
+        assert_eq!(
+            counter(OPEN_DISPUTES, None),
+            Some(&DebugValue::Gauge(1.0.into()))
+        );
+        Ok(())
+    }
+}
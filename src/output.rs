@@ -0,0 +1,486 @@
+//! Helpers related to writing the results of an engine out once processing is complete
+
+use color_eyre::{eyre::eyre, Result};
+use serde::Serialize;
+use tracing::{error, warn};
+
+use std::io::{BufWriter, Write};
+
+use crate::storage::Client;
+
+/// Configuration for how the final state of each client should be written out
+///
+/// By default only the `client`, `available`, `held`, `total` and `locked` columns are written.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Appends a `has_open_disputes` column identifying whether the client has any transactions
+    /// that are currently under dispute
+    pub with_dispute_flag: bool,
+    /// How a client should be written if their total funds overflow
+    pub total_overflow: TotalOverflow,
+    /// Writes the amounts as floating point numbers _(eg. `10.5`)_, rather than as decimal
+    /// strings to exactly 4 decimal places _(eg. `10.5000`)_
+    ///
+    /// The exact decimal string is the default, as it doesn't depend on how floats are formatted.
+    /// The amounts are written as an `f64`, which retains all 4 decimal places for balances up to
+    /// the hundreds of billions, beyond that they may lose precision.
+    pub numeric_amounts: bool,
+    /// Rounds each of the amounts to this many decimal places _(at most 4)_ as they are written,
+    /// eg. `2` to write currency in cents
+    ///
+    /// This is display-only, every amount is still processed to 4 decimal places. As the
+    /// `available`, `held` and `total` columns are each rounded independently, the written
+    /// `total` may not exactly equal the written `available` plus `held`.
+    pub precision: Option<u32>,
+    /// The format the clients are written in
+    pub format: OutputFormat,
+    /// Writes the clients in ascending order of their id, rather than in whichever order the
+    /// engine holds them
+    ///
+    /// This makes the output reproducible across runs, however every client has to be collected
+    /// before any are written, so engines which would otherwise write each client as soon as
+    /// it's ready _(eg. the `ActorLikeEngine`)_ hold all of them in memory.
+    pub sort_by_id: bool,
+}
+
+/// The format the final state of the clients is written in
+///
+/// Every format writes the same fields, as each client is serialized through the same
+/// implementation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// A csv file with a `client,available,held,total,locked` header
+    #[default]
+    Csv,
+    /// A json array with an object per client
+    /// _eg. `[{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}]`_
+    Json,
+}
+
+/// How to write a client whose `available` and `held` funds are each valid, but whose `total`
+/// can't be represented
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TotalOverflow {
+    /// Writes the client with an empty `total` column
+    #[default]
+    Empty,
+    /// Leaves the client out of the output entirely
+    Skip,
+}
+
+/// A serializable view of a [`Client`] alongside the [`OutputOptions`] it should be written with
+pub(crate) struct ClientRecord<'a> {
+    client: &'a Client,
+    options: &'a OutputOptions,
+}
+
+impl<'a> ClientRecord<'a> {
+    pub(crate) fn new(client: &'a Client, options: &'a OutputOptions) -> Self {
+        Self { client, options }
+    }
+}
+
+impl Serialize for ClientRecord<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.client.serialize_with_options(serializer, self.options)
+    }
+}
+
+/// A thin RAII wrapper around a [`csv::Writer`] which guarantees the underlying writer is flushed
+/// once it goes out of scope.
+///
+/// Rows written through a [`csv::Writer`] are buffered, so if a caller forgets to make the final
+/// call to `flush` any buffered rows are silently lost. Dropping a [`StreamingOutput`] will always
+/// attempt a final flush, however as [`Drop`] can't return a [`Result`] any error that occurs is
+/// only logged. Call [`StreamingOutput::flush`] explicitly if you need to handle the error.
+///
+/// If [`OutputOptions::format`] is [`OutputFormat::Json`] the records are instead written as the
+/// elements of a json array, which is closed by [`StreamingOutput::finish`] or once the output is
+/// dropped after writing a record.
+///
+/// ## Examples
+///
+/// ```
+/// use lib::output::StreamingOutput;
+/// use lib::storage::Client;
+///
+/// let mut result = vec![];
+/// {
+///     let mut output = StreamingOutput::new(&mut result);
+///     output.serialize(&Client::new(1)).unwrap();
+/// }
+/// assert!(!result.is_empty());
+/// ```
+pub struct StreamingOutput<W: Write> {
+    writer: Sink<W>,
+    options: OutputOptions,
+    clients_written: usize,
+}
+
+/// The underlying writer for each [`OutputFormat`]
+enum Sink<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json {
+        writer: BufWriter<W>,
+        /// Whether any record has been written, ie. whether the array has been opened
+        opened: bool,
+        closed: bool,
+    },
+}
+
+impl<W: Write> Sink<W> {
+    fn serialize(&mut self, record: impl Serialize) -> Result<()> {
+        match self {
+            Self::Csv(writer) => writer.serialize(record)?,
+            Self::Json {
+                writer,
+                opened,
+                closed,
+            } => {
+                if *closed {
+                    return Err(eyre!("the json array has already been closed"));
+                }
+                writer.write_all(if *opened { b"," } else { b"[" })?;
+                *opened = true;
+                serde_json::to_writer(&mut *writer, &record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the json array, writing an empty array if no records were written
+    fn close(&mut self) -> Result<()> {
+        if let Self::Json {
+            writer,
+            opened,
+            closed,
+        } = self
+        {
+            if !*closed {
+                writer.write_all(if *opened { b"]\n" } else { b"[]\n" })?;
+                *closed = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Csv(writer) => writer.flush()?,
+            Self::Json { writer, .. } => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> StreamingOutput<W> {
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, OutputOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: OutputOptions) -> Self {
+        let writer = match options.format {
+            OutputFormat::Csv => Sink::Csv(Box::new(csv::Writer::from_writer(writer))),
+            OutputFormat::Json => Sink::Json {
+                writer: BufWriter::new(writer),
+                opened: false,
+                closed: false,
+            },
+        };
+        Self {
+            writer,
+            options,
+            clients_written: 0,
+        }
+    }
+
+    /// Serializes a client as a csv row _(or json object)_, respecting the configured
+    /// [`OutputOptions`]
+    pub fn write_client(&mut self, client: &Client) -> Result<()> {
+        if self.options.total_overflow == TotalOverflow::Skip && client.total_amount().is_none() {
+            warn!(
+                "the total funds for client {} overflowed, it will be left out of the output",
+                client.id
+            );
+            return Ok(());
+        }
+        self.writer
+            .serialize(ClientRecord::new(client, &self.options))?;
+        self.clients_written += 1;
+        Ok(())
+    }
+
+    /// Writes each of the clients via [`StreamingOutput::write_client`], in order of their id if
+    /// [`OutputOptions::sort_by_id`] is set
+    pub fn write_clients<'a>(
+        &mut self,
+        clients: impl IntoIterator<Item = &'a Client>,
+    ) -> Result<()> {
+        if !self.options.sort_by_id {
+            return clients
+                .into_iter()
+                .try_for_each(|client| self.write_client(client));
+        }
+        let mut clients = clients.into_iter().collect::<Vec<_>>();
+        clients.sort_unstable_by_key(|c| c.id);
+        clients
+            .into_iter()
+            .try_for_each(|client| self.write_client(client))
+    }
+
+    /// Completes the output of the clients, flushing any buffered rows
+    ///
+    /// As the header is only written alongside the first row, if no clients have been written
+    /// the header is written on its own so that the output is always a valid csv. For json
+    /// output this closes the array.
+    pub fn finish(&mut self) -> Result<()> {
+        match &mut self.writer {
+            Sink::Csv(writer) if self.clients_written == 0 => {
+                let mut header = vec!["client", "available", "held", "total", "locked"];
+                if self.options.with_dispute_flag {
+                    header.push("has_open_disputes");
+                }
+                writer.write_record(header)?;
+            }
+            Sink::Csv(_) => {}
+            Sink::Json { .. } => self.writer.close()?,
+        }
+        self.flush()
+    }
+
+    /// Serializes a single record as a csv row _(or json object)_
+    pub fn serialize(&mut self, record: impl Serialize) -> Result<()> {
+        self.writer.serialize(record)
+    }
+
+    /// Flushes any buffered rows through to the underlying writer
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Drop for StreamingOutput<W> {
+    fn drop(&mut self) {
+        // An array which was never opened is left empty, so nothing is written if the output was
+        // abandoned before any client was written _(eg. as processing failed)_
+        let close = match self.writer {
+            Sink::Json { opened, .. } if opened => self.writer.close(),
+            _ => Ok(()),
+        };
+        if let Err(e) = close.and_then(|_| self.writer.flush()) {
+            error!(error = %e, "failed to flush the output when dropping the writer, some rows may have been lost");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::storage::ClientStorage;
+    use crate::transaction::TransactionType;
+    use crate::Amount;
+
+    #[test]
+    fn flushes_all_rows_when_dropped_without_an_explicit_flush() -> Result<()> {
+        let mut result = vec![];
+        {
+            let mut output = StreamingOutput::new(&mut result);
+            for id in 1..=3 {
+                output.serialize(Client::new(id))?;
+            }
+        }
+
+        let rows = csv::ReaderBuilder::new()
+            .from_reader(&*result)
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(rows.len(), 3, "expected every row to have been flushed");
+        for (row, id) in rows.iter().zip(1..=3) {
+            assert_eq!(&row[0], id.to_string());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn writes_the_header_when_no_clients_are_written() -> Result<()> {
+        let mut result = vec![];
+        {
+            let mut output = StreamingOutput::new(&mut result);
+            output.finish()?;
+        }
+        assert_eq!(
+            String::from_utf8(result)?,
+            "client,available,held,total,locked\n"
+        );
+
+        // The header matches the one written alongside a row
+        let mut result = vec![];
+        {
+            let mut output = StreamingOutput::new(&mut result);
+            output.write_client(&Client::new(1))?;
+            output.finish()?;
+        }
+        assert!(String::from_utf8(result)?.starts_with("client,available,held,total,locked\n1,"));
+        Ok(())
+    }
+
+    #[test]
+    fn writes_the_same_fields_as_json() -> Result<()> {
+        let options = OutputOptions {
+            format: OutputFormat::Json,
+            ..Default::default()
+        };
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some(Amount::try_from(1.5f32)?))?;
+
+        let mut result = vec![];
+        {
+            let mut output = StreamingOutput::with_options(&mut result, options.clone());
+            output.write_client(&client)?;
+            output.write_client(&Client::new(2))?;
+            output.finish()?;
+        }
+        assert_eq!(
+            String::from_utf8(result)?,
+            concat!(
+                r#"[{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false},"#,
+                r#"{"client":2,"available":"0.0000","held":"0.0000","total":"0.0000","locked":false}]"#,
+                "\n"
+            )
+        );
+
+        let mut result = vec![];
+        StreamingOutput::with_options(&mut result, options.clone()).finish()?;
+        assert_eq!(String::from_utf8(result)?, "[]\n");
+
+        // The array is still closed if the output is dropped without being finished
+        let mut result = vec![];
+        StreamingOutput::with_options(&mut result, options).write_client(&client)?;
+        let clients: Vec<serde_json::Value> = serde_json::from_slice(&result)?;
+        assert_eq!(clients.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_flag_reflects_whether_a_dispute_is_open() -> Result<()> {
+        let options = OutputOptions {
+            with_dispute_flag: true,
+            ..Default::default()
+        };
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some(Amount::try_from(10f32)?))?;
+        client.process_transaction(1, TransactionType::Dispute, None)?;
+
+        let write = |client: &Client| -> Result<csv::StringRecord> {
+            let mut result = vec![];
+            {
+                let mut output = StreamingOutput::with_options(&mut result, options.clone());
+                output.write_client(client)?;
+            }
+            let mut reader = csv::ReaderBuilder::new().from_reader(&*result);
+            assert_eq!(&reader.headers()?[5], "has_open_disputes");
+            Ok(reader.records().next().expect("expected a row")?)
+        };
+
+        assert_eq!(&write(&client)?[5], "true");
+        client.process_transaction(1, TransactionType::Resolve, None)?;
+        assert_eq!(&write(&client)?[5], "false");
+        Ok(())
+    }
+
+    #[test]
+    fn handles_a_total_which_overflows() -> Result<()> {
+        let near_max = Amount::try_from(5e28f32)?;
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some(near_max))?;
+        client.process_transaction(1, TransactionType::Dispute, None)?;
+        client.process_transaction(2, TransactionType::Deposit, Some(near_max))?;
+        assert!(client.total_amount().is_none());
+
+        let write = |total_overflow| -> Result<Vec<csv::StringRecord>> {
+            let mut result = vec![];
+            {
+                let options = OutputOptions {
+                    total_overflow,
+                    ..Default::default()
+                };
+                let mut output = StreamingOutput::with_options(&mut result, options);
+                output.write_client(&client)?;
+                output.write_client(&Client::new(2))?;
+            }
+            Ok(csv::Reader::from_reader(&*result)
+                .records()
+                .collect::<std::result::Result<Vec<_>, _>>()?)
+        };
+
+        let rows = write(TotalOverflow::Empty)?;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][0], "1");
+        assert_eq!(
+            &rows[0][3], "",
+            "expected the overflowing total to be empty"
+        );
+        assert_eq!(&rows[1][0], "2");
+
+        let rows = write(TotalOverflow::Skip)?;
+        assert_eq!(
+            rows.len(),
+            1,
+            "expected the overflowing client to be skipped"
+        );
+        assert_eq!(&rows[0][0], "2");
+        Ok(())
+    }
+
+    #[test]
+    fn rounds_the_amounts_to_the_requested_precision() -> Result<()> {
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some("10.1250".parse()?))?;
+        client.process_transaction(2, TransactionType::Deposit, Some("0.0050".parse()?))?;
+        client.process_transaction(2, TransactionType::Dispute, None)?;
+
+        let write = |options: OutputOptions| -> Result<csv::StringRecord> {
+            let mut result = vec![];
+            {
+                let mut output = StreamingOutput::with_options(&mut result, options);
+                output.write_client(&client)?;
+            }
+            let mut rows = csv::Reader::from_reader(&*result).into_records();
+            Ok(rows.next().expect("a single row")?)
+        };
+
+        let exact = write(OutputOptions::default())?;
+        assert_eq!(
+            exact.iter().take(4).collect::<Vec<_>>(),
+            vec!["1", "10.1250", "0.0050", "10.1300"]
+        );
+
+        let options = OutputOptions {
+            precision: Some(2),
+            ..Default::default()
+        };
+        let rounded = write(options.clone())?;
+        // Each column is rounded independently (using bankers rounding), so the total no longer
+        // equals the available plus held funds
+        assert_eq!(
+            rounded.iter().take(4).collect::<Vec<_>>(),
+            vec!["1", "10.12", "0.00", "10.13"]
+        );
+
+        let numeric = write(OutputOptions {
+            numeric_amounts: true,
+            ..options
+        })?;
+        assert_eq!(
+            numeric.iter().take(4).collect::<Vec<_>>(),
+            vec!["1", "10.12", "0.0", "10.13"]
+        );
+        Ok(())
+    }
+}
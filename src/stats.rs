@@ -0,0 +1,318 @@
+//! Lightweight statistics which can be gathered while transactions are processed, without
+//! having to retain every transaction
+
+use tracing::info;
+
+use std::iter::Sum;
+use std::ops::AddAssign;
+
+#[cfg(feature = "basic_engine")]
+use crate::transaction::TransactionType;
+use crate::Amount;
+
+/// Counts of the transactions processed by an engine, eg. for dashboarding the throughput and
+/// dispute rate of a run
+///
+/// The counts can be emitted as the fields of a single [`tracing`] event via [`Stats::log`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of deposits processed
+    pub deposits: usize,
+    /// The number of withdrawals processed
+    pub withdrawals: usize,
+    /// The number of disputes processed
+    pub disputes: usize,
+    /// The number of resolves processed
+    pub resolves: usize,
+    /// The number of chargebacks processed
+    pub chargebacks: usize,
+    /// The number of transfers processed
+    pub transfers: usize,
+    /// The number of transactions of a custom type processed _(see
+    /// [`CustomTransaction`](crate::transaction::CustomTransaction))_
+    pub custom: usize,
+    /// The number of accounts which were frozen
+    pub frozen_accounts: usize,
+    /// The number of transactions which were rejected with a recoverable error, in which case
+    /// processing carried on without them _(see
+    /// [`TransactionError::is_recoverable`](crate::error::TransactionError::is_recoverable))_
+    pub skipped_errors: usize,
+}
+
+impl Stats {
+    /// Counts a transaction of the provided type, heartbeats aren't counted
+    #[cfg(feature = "basic_engine")]
+    pub(crate) fn record(&mut self, ty: &TransactionType) {
+        match ty {
+            TransactionType::Deposit => self.deposits += 1,
+            TransactionType::Withdrawal => self.withdrawals += 1,
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Resolve => self.resolves += 1,
+            TransactionType::Chargeback => self.chargebacks += 1,
+            TransactionType::Transfer => self.transfers += 1,
+            TransactionType::Custom(_) => self.custom += 1,
+            TransactionType::Heartbeat => {}
+        }
+    }
+
+    /// The total number of transactions processed
+    pub fn transactions(&self) -> usize {
+        self.deposits
+            + self.withdrawals
+            + self.disputes
+            + self.resolves
+            + self.chargebacks
+            + self.transfers
+            + self.custom
+    }
+
+    /// Emits the counts as the fields of a single `INFO` level [`tracing`] event
+    pub fn log(&self) {
+        info!(
+            deposits = self.deposits,
+            withdrawals = self.withdrawals,
+            disputes = self.disputes,
+            resolves = self.resolves,
+            chargebacks = self.chargebacks,
+            transfers = self.transfers,
+            custom = self.custom,
+            frozen_accounts = self.frozen_accounts,
+            skipped_errors = self.skipped_errors,
+            "processed {} transactions",
+            self.transactions()
+        );
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.deposits += rhs.deposits;
+        self.withdrawals += rhs.withdrawals;
+        self.disputes += rhs.disputes;
+        self.resolves += rhs.resolves;
+        self.chargebacks += rhs.chargebacks;
+        self.transfers += rhs.transfers;
+        self.custom += rhs.custom;
+        self.frozen_accounts += rhs.frozen_accounts;
+        self.skipped_errors += rhs.skipped_errors;
+    }
+}
+
+/// Aggregates the stats of several engines, eg. each partition of a
+/// [`StreamLikeEngine`](crate::engines::StreamLikeEngine)
+impl Sum for Stats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |mut total, stats| {
+            total += stats;
+            total
+        })
+    }
+}
+
+/// Streaming estimates of the distribution of transaction amounts
+///
+/// The quantiles are estimated with the P² algorithm _(Jain & Chlamtac, 1985)_, which uses a
+/// constant amount of memory regardless of how many amounts are recorded. The estimates are
+/// exact until 5 amounts have been recorded.
+///
+/// As with [`Amount`], the estimates should be formatted with [`Amount::to_exact_string`].
+///
+/// ```
+/// use lib::stats::AmountStats;
+/// use lib::Amount;
+///
+/// let mut stats = AmountStats::default();
+/// for amount in ["1.0", "2.0", "3.0"] {
+///     stats.record(amount.parse::<Amount>().unwrap());
+/// }
+/// assert_eq!(stats.count(), 3);
+/// assert_eq!(stats.median().unwrap().to_exact_string(), "2.0000");
+/// ```
+#[derive(Clone)]
+pub struct AmountStats {
+    count: usize,
+    median: P2Quantile,
+    p95: P2Quantile,
+}
+
+impl Default for AmountStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            median: P2Quantile::new(0.5),
+            p95: P2Quantile::new(0.95),
+        }
+    }
+}
+
+impl AmountStats {
+    /// Records an amount in the distribution
+    pub fn record(&mut self, amount: Amount) {
+        let value = amount.to_f64_lossy();
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.median.record(value);
+        self.p95.record(value);
+    }
+
+    /// The number of amounts which have been recorded
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The estimated median amount, or `None` if no amounts have been recorded
+    pub fn median(&self) -> Option<Amount> {
+        self.median.estimate().and_then(Amount::from_f64_lossy)
+    }
+
+    /// The estimated 95th percentile amount, or `None` if no amounts have been recorded
+    pub fn p95(&self) -> Option<Amount> {
+        self.p95.estimate().and_then(Amount::from_f64_lossy)
+    }
+}
+
+/// An estimator of a single quantile using the P² algorithm
+#[derive(Clone)]
+struct P2Quantile {
+    p: f64,
+    /// The first 5 observations, used until the markers can be initialised
+    initial: Vec<f64>,
+    /// The heights of the markers
+    heights: [f64; 5],
+    /// The actual positions of the markers
+    positions: [f64; 5],
+    /// The desired positions of the markers
+    desired: [f64; 5],
+    /// The increments applied to the desired positions on each observation
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> Self {
+        Self {
+            p,
+            initial: Vec::with_capacity(5),
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn record(&mut self, value: f64) {
+        if self.initial.len() < 5 {
+            self.initial.push(value);
+            if self.initial.len() == 5 {
+                self.initial.sort_by(f64::total_cmp);
+                self.heights.copy_from_slice(&self.initial);
+            }
+            return;
+        }
+
+        // Find the cell the value falls in, extending the extremes if required
+        let k = if value < self.heights[0] {
+            self.heights[0] = value;
+            0
+        } else if value >= self.heights[4] {
+            self.heights[4] = value;
+            3
+        } else {
+            (0..4).find(|&i| value < self.heights[i + 1]).unwrap_or(3)
+        };
+
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        // Adjust the heights of the middle markers if they're off their desired positions
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0)
+            {
+                let d = d.signum();
+                let height = self.parabolic(i, d);
+                self.heights[i] = if self.heights[i - 1] < height && height < self.heights[i + 1] {
+                    height
+                } else {
+                    self.linear(i, d)
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+    }
+
+    fn estimate(&self) -> Option<f64> {
+        if self.initial.len() < 5 {
+            if self.initial.is_empty() {
+                return None;
+            }
+            let mut sorted = self.initial.clone();
+            sorted.sort_by(f64::total_cmp);
+            let index = (self.p * (sorted.len() - 1) as f64).round() as usize;
+            return Some(sorted[index]);
+        }
+        Some(self.heights[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use color_eyre::Result;
+    use pretty_assertions::assert_eq;
+
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    #[test]
+    fn has_no_estimates_until_an_amount_is_recorded() {
+        let stats = AmountStats::default();
+        assert_eq!(stats.count(), 0);
+        assert!(stats.median().is_none());
+        assert!(stats.p95().is_none());
+    }
+
+    #[test]
+    fn estimates_the_quantiles_of_a_uniform_distribution() -> Result<()> {
+        let mut amounts = (1..=10_000).collect::<Vec<_>>();
+        amounts.shuffle(&mut StdRng::seed_from_u64(7));
+
+        let mut stats = AmountStats::default();
+        for amount in amounts {
+            stats.record(amount.to_string().parse()?);
+        }
+        assert_eq!(stats.count(), 10_000);
+
+        let within = |estimate: Option<Amount>, expected: f64| {
+            let estimate = estimate.expect("expected an estimate").to_f64_lossy();
+            assert!(
+                (estimate - expected).abs() / expected < 0.02,
+                "expected {} to be within 2% of {}",
+                estimate,
+                expected
+            );
+        };
+        within(stats.median(), 5_000.0);
+        within(stats.p95(), 9_500.0);
+        Ok(())
+    }
+}
@@ -8,19 +8,26 @@ use color_eyre::{
 use fnv::FnvHashMap;
 use serde::{
     ser::{Error, SerializeStruct},
-    Serialize,
+    Deserialize, Serialize,
 };
-use tracing::{instrument, warn};
+use tracing::{info, instrument, warn};
 
-use std::{collections::hash_map::Entry, fmt};
+use std::{
+    collections::hash_map::{Entry, VacantEntry},
+    fmt,
+    time::Duration,
+};
 
+use crate::amount::{self, SignedAmount};
+use crate::error::TransactionError;
+use crate::output::OutputOptions;
 use crate::transaction::{Transaction, TransactionType};
 use crate::Amount;
 
 /// Represents the state and state transitions that a client must store
 /// in order to be processed by the payment engine.
 pub trait ClientStorage {
-    /// A getter method identifying whether this client's account is locked
+    /// A getter method used to retrieve the id of this client
     fn id(&self) -> u16;
 
     /// A getter method identifying whether this client's account is locked
@@ -40,17 +47,20 @@ pub trait ClientStorage {
     /// ## Errors
     ///
     /// This function should error if:
-    /// 1. The account status of the client is [`AccountStatus::Frozen`]
+    /// 1. The account status of the client is [`AccountStatus::Frozen`], including when the
+    ///    transaction is the chargeback which froze it _(see [`TransactionError::FrozenAccount`])_
     /// 2. An unexpected error occurs
+    /// 3. The transaction is rejected, eg. a duplicate transaction id, an invalid state
+    ///    transition, not enough funds to carry out a withdrawal or a deposit which would
+    ///    overflow the available funds
     ///
-    /// An error from this function indicates that processing should stop for this client
+    /// An error from this function indicates that processing should stop for this client, unless
+    /// it is a [`TransactionError`] which [is recoverable](TransactionError::is_recoverable)
     ///
     /// ## Ignores
     ///
-    /// This function should ignore _and return `Ok(())`_ for any invalid transactions, whether that be due to:
-    /// - Invalid state transtions
-    /// - Not enough funds to carry out a withdrawal
-    /// - Invalid data (eg. A deposit or withdrawal with no amount)
+    /// This function should ignore _and return `Ok(())`_ for any invalid data (eg. A deposit or
+    /// withdrawal with no amount)
     fn process_transaction(
         &mut self,
         transaction_id: u32,
@@ -61,17 +71,29 @@ pub trait ClientStorage {
     /// This will update the internally held totals on the funds and insert an entry in the
     /// transaction log
     ///
-    /// If this provided transaction id has already been processed, this should ignore it
-    fn deposit(&mut self, transaction_id: u32, amount: Amount);
+    /// If this provided transaction id has already been processed, this should error with
+    /// [`TransactionError::DuplicateTransaction`]
+    ///
+    /// This should error, without applying the deposit, if it would overflow the funds
+    fn deposit(&mut self, transaction_id: u32, amount: Amount) -> Result<()>;
 
     /// This will update the internally held totals on the funds and insert an entry in the
     /// transaction log
     ///
-    /// If this provided transaction id has already been processed, this should ignore it
-    fn withdraw(&mut self, transaction_id: u32, amount: Amount);
-    fn dispute(&mut self, transaction_id: u32, amount: Amount);
-    fn resolve(&mut self, transaction_id: u32, amount: Amount);
-    fn chargeback(&mut self, transaction_id: u32, amount: Amount);
+    /// If this provided transaction id has already been processed, this should error with
+    /// [`TransactionError::DuplicateTransaction`]
+    ///
+    /// This should error with [`TransactionError::InsufficientFunds`], without applying the
+    /// withdrawal, if there aren't enough available funds
+    fn withdraw(&mut self, transaction_id: u32, amount: Amount) -> Result<()>;
+
+    /// These will move the funds of the transaction between the available and held funds, and
+    /// update its entry in the transaction log
+    ///
+    /// These should error, without applying the transition, if it would overflow the funds
+    fn dispute(&mut self, transaction_id: u32, amount: Amount) -> Result<()>;
+    fn resolve(&mut self, transaction_id: u32, amount: Amount) -> Result<()>;
+    fn chargeback(&mut self, transaction_id: u32, amount: Amount) -> Result<()>;
 }
 
 /// Holds all transactional data related to a specific client.
@@ -89,7 +111,7 @@ pub trait ClientStorage {
 ///
 /// let mut client = Client::new(1);
 ///
-/// if let Err(e) = client.process_transaction(1, TransactionType::Deposit, Some(Amount::try_from(10f32).unwrap())) {
+/// if let Err(e) = client.process_transaction(1, TransactionType::Deposit, Some("10".parse::<Amount>().unwrap())) {
 ///     eprintln!("Error occurred {}", e);
 /// }
 /// ```
@@ -98,14 +120,89 @@ pub struct Client {
     pub id: u16,
     status: AccountStatus,
     transaction_log: FnvHashMap<u32, Option<Transaction>>,
+    /// The number of transactions in the log which are currently under dispute, this is kept up
+    /// to date as the log changes so that it doesn't have to be counted
+    open_disputes: usize,
     held: Amount,
     available: Amount,
+    charged_back: Vec<u32>,
+    reconciliations: Vec<Reconciliation>,
+    dispute_window: Option<Duration>,
+    dispute_policy: DisputePolicy,
+    /// Every state transition of the client's transactions, this is only recorded if enabled via
+    /// [`Client::with_history`]
+    history: Option<Vec<TransactionEvent>>,
+}
+
+/// A record of a client's balances being overwritten by [`Client::reconcile`]
+#[derive(Clone)]
+pub struct Reconciliation {
+    /// Why the balances were reconciled
+    pub reason: String,
+    /// The change made to the available funds
+    pub available_adjustment: SignedAmount,
+    /// The change made to the held funds
+    pub held_adjustment: SignedAmount,
+}
+
+/// A single state transition of one of a client's transactions, see [`Client::history`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionEvent {
+    /// The id of the transaction
+    pub tx: u32,
+    /// The state of the transaction before the transition, or `None` if it was a new transaction
+    pub from: Option<TransactionType>,
+    /// The state of the transaction after the transition
+    pub to: TransactionType,
+    /// When the transition occurred in seconds since the unix epoch, if it is known
+    pub timestamp: Option<u64>,
+}
+
+/// The complete state of a [`Client`], which can be persisted in order to later rebuild the
+/// client, see [`Client::snapshot`]
+///
+/// The transaction log is opaque, so it can only be captured from an existing client. However
+/// the balances aren't checked against the log when the client is restored _(eg. a client
+/// created with [`Client::with_state`] holds funds without any disputed transactions)_, so a
+/// snapshot should only be restored from a trusted source.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientSnapshot {
+    /// The Unique ID associated with the client
+    pub id: u16,
+    /// The status of the client's account
+    pub status: AccountStatus,
+    /// The client's held funds
+    #[serde(deserialize_with = "amount::deserialize_balance")]
+    pub held: Amount,
+    /// The client's available funds, which may be negative _(see [`DisputePolicy`])_
+    #[serde(deserialize_with = "amount::deserialize_balance")]
+    pub available: Amount,
+    /// The ids of the client's transactions which were charged back
+    pub charged_back: Vec<u32>,
+    transaction_log: FnvHashMap<u32, Option<Transaction>>,
+    /// The client's history, if it was being recorded _(see [`Client::with_history`])_
+    #[serde(default)]
+    pub history: Option<Vec<TransactionEvent>>,
+}
+
+/// Dictates how a dispute of a deposit is handled when the client no longer has the deposited
+/// funds available, eg. as they were withdrawn before the dispute arrived
+///
+/// Disputing a deposit moves its amount from the available funds to the held funds, so without
+/// enough available funds the dispute drives the available funds negative.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisputePolicy {
+    /// The dispute is applied, leaving the client with negative available funds _(the default)_
+    #[default]
+    AllowNegative,
+    /// The dispute is rejected with [`TransactionError::InsufficientFundsForDispute`]
+    RejectIfInsufficient,
 }
 
 /// An enum representation of the status of the account
 ///
 /// If an account is frozen, no further transactions can take place
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AccountStatus {
     Active,
     Frozen,
@@ -117,10 +214,754 @@ impl Client {
             id: client_id,
             status: AccountStatus::Active,
             transaction_log: FnvHashMap::with_capacity_and_hasher(28, Default::default()),
+            open_disputes: 0,
             available: Amount::default(),
             held: Amount::default(),
+            charged_back: Vec::new(),
+            reconciliations: Vec::new(),
+            dispute_window: None,
+            dispute_policy: DisputePolicy::default(),
+            history: None,
+        }
+    }
+
+    /// Creates a client which already holds the provided funds and status, but has not processed
+    /// any transactions, eg. for setting up a frozen account in tests or administrative tooling
+    ///
+    /// ```
+    /// use lib::storage::{AccountStatus, Client, ClientStorage};
+    /// use lib::Amount;
+    ///
+    /// let client = Client::with_state(
+    ///     1,
+    ///     "10".parse::<Amount>().unwrap(),
+    ///     Amount::default(),
+    ///     AccountStatus::Frozen,
+    /// );
+    /// assert!(client.is_locked());
+    /// ```
+    pub fn with_state(
+        client_id: u16,
+        available: Amount,
+        held: Amount,
+        status: AccountStatus,
+    ) -> Self {
+        Self {
+            available,
+            held,
+            status,
+            ..Self::new(client_id)
+        }
+    }
+
+    /// Rejects any dispute of a deposit which arrives more than `window` after the deposit, see
+    /// [`Client::process_transaction_at`]
+    pub fn with_dispute_window(mut self, window: Duration) -> Self {
+        self.dispute_window = Some(window);
+        self
+    }
+
+    /// Sets how a dispute of a deposit whose funds are no longer available is handled, by default
+    /// it is applied and the available funds go negative
+    pub fn with_dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.dispute_policy = policy;
+        self
+    }
+
+    /// Records every state transition of the client's transactions, see [`Client::history`]
+    ///
+    /// _Note:_ the history grows with every transaction processed for the client, and unlike the
+    /// transaction log it is never pruned.
+    pub fn with_history(mut self) -> Self {
+        self.history.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Captures the complete state of the client, eg. to checkpoint a long running engine
+    ///
+    /// The client's settings _(ie. its dispute window and [`DisputePolicy`])_ and its
+    /// [`Client::reconciliations`] aren't captured, any settings should be applied again once
+    /// the client is restored.
+    ///
+    /// ```
+    /// use lib::storage::{Client, ClientSnapshot, ClientStorage};
+    /// use lib::transaction::TransactionType;
+    ///
+    /// let mut client = Client::new(1);
+    /// client.process_transaction(1, TransactionType::Deposit, Some("10".parse()?))?;
+    ///
+    /// let json = serde_json::to_string(&client.snapshot())?;
+    /// let snapshot: ClientSnapshot = serde_json::from_str(&json)?;
+    /// let mut restored = Client::restore(snapshot);
+    /// restored.process_transaction(1, TransactionType::Dispute, None)?;
+    /// assert_eq!(restored.held_amount().to_exact_string(), "10.0000");
+    /// # Ok::<(), color_eyre::Report>(())
+    /// ```
+    pub fn snapshot(&self) -> ClientSnapshot {
+        ClientSnapshot {
+            id: self.id,
+            status: self.status,
+            held: self.held,
+            available: self.available,
+            charged_back: self.charged_back.clone(),
+            transaction_log: self.transaction_log.clone(),
+            history: self.history.clone(),
+        }
+    }
+
+    /// Rebuilds a client from a snapshot previously captured by [`Client::snapshot`]
+    ///
+    /// The snapshot is trusted as it is, see [`ClientSnapshot`].
+    pub fn restore(snapshot: ClientSnapshot) -> Self {
+        let open_disputes = snapshot
+            .transaction_log
+            .values()
+            .filter(|trx| matches!(trx, Some(Transaction::Dispute { .. })))
+            .count();
+        Self {
+            status: snapshot.status,
+            held: snapshot.held,
+            available: snapshot.available,
+            charged_back: snapshot.charged_back,
+            transaction_log: snapshot.transaction_log,
+            open_disputes,
+            history: snapshot.history,
+            ..Self::new(snapshot.id)
+        }
+    }
+
+    /// The state transitions of the client's transactions in the order they occurred, this is
+    /// empty unless the history was enabled via [`Client::with_history`]
+    ///
+    /// A transaction which was ignored or rejected _(eg. a withdrawal with insufficient funds)_
+    /// doesn't transition, so it isn't recorded. The history is append-only, so it still holds
+    /// the transactions which were cleared from the transaction log when the account was frozen.
+    pub fn history(&self) -> &[TransactionEvent] {
+        self.history.as_deref().unwrap_or_default()
+    }
+
+    /// Appends a state transition to the client's history, if it is being recorded
+    fn record_event(
+        &mut self,
+        tx: u32,
+        from: Option<TransactionType>,
+        to: TransactionType,
+        timestamp: Option<u64>,
+    ) {
+        if let Some(history) = self.history.as_mut() {
+            history.push(TransactionEvent {
+                tx,
+                from,
+                to,
+                timestamp,
+            });
         }
     }
+
+    /// Overwrites the client's balances with the authoritative values from an external ledger,
+    /// returning the net adjustment made to the client's total funds
+    ///
+    /// This is an administrative operation outside of the usual transaction processing, so it
+    /// applies regardless of the account's status. Every reconciliation is recorded, see
+    /// [`Client::reconciliations`].
+    pub fn reconcile(
+        &mut self,
+        authoritative_available: Amount,
+        authoritative_held: Amount,
+        reason: &str,
+    ) -> SignedAmount {
+        let available_adjustment = SignedAmount::between(self.available, authoritative_available);
+        let held_adjustment = SignedAmount::between(self.held, authoritative_held);
+        self.available = authoritative_available;
+        self.held = authoritative_held;
+        info!("reconciled the balances of client {}: {}", self.id, reason);
+        self.reconciliations.push(Reconciliation {
+            reason: reason.to_string(),
+            available_adjustment,
+            held_adjustment,
+        });
+        available_adjustment + held_adjustment
+    }
+
+    /// Re-enables an account which has been frozen by a chargeback, allowing transactions to be
+    /// processed for it again
+    ///
+    /// The transaction log is cleared when an account is frozen, so a reinstated account starts
+    /// with an empty history, ie. none of the transactions processed before the chargeback can be
+    /// disputed and their ids are free to be reused. The ids of the charged back transactions are
+    /// still retained, see [`Client::charged_back_transactions`].
+    ///
+    /// This errors if the account isn't frozen.
+    pub fn reinstate(&mut self) -> Result<()> {
+        if self.status != AccountStatus::Frozen {
+            return Err(eyre!(
+                "unable to reinstate client '{}' as the account is not frozen",
+                self.id
+            ));
+        }
+        info!("reinstated the account of client {}", self.id);
+        self.status = AccountStatus::Active;
+        Ok(())
+    }
+
+    /// Overwrites the client's held funds
+    ///
+    /// **Warning:** this bypasses the state machine entirely, so the held funds will no longer
+    /// match the disputes in the transaction log _(eg. resolving a dispute may release more than
+    /// is held)_. It's only intended for reconciliation tooling, and unlike
+    /// [`Client::reconcile`] it isn't recorded.
+    #[cfg(feature = "balance_overrides")]
+    pub fn set_held(&mut self, amount: Amount) {
+        warn!("overriding the held funds of client {}", self.id);
+        self.held = amount;
+    }
+
+    /// Overwrites the client's available funds
+    ///
+    /// **Warning:** this bypasses the state machine entirely, so the available funds will no
+    /// longer match the transactions which have been processed. It's only intended for
+    /// reconciliation tooling, and unlike [`Client::reconcile`] it isn't recorded.
+    #[cfg(feature = "balance_overrides")]
+    pub fn set_available(&mut self, amount: Amount) {
+        warn!("overriding the available funds of client {}", self.id);
+        self.available = amount;
+    }
+
+    /// Every reconciliation that has been applied to this client, in the order they occurred
+    pub fn reconciliations(&self) -> &[Reconciliation] {
+        &self.reconciliations
+    }
+
+    /// The funds currently available to this client
+    ///
+    /// This is the same as [`ClientStorage::available_funds`], without needing the trait to be in
+    /// scope. See [`Client::available_amount`] for the exact value.
+    pub fn available(&self) -> Result<f32> {
+        self.available_funds()
+    }
+
+    /// The funds currently held for this client
+    ///
+    /// This is the same as [`ClientStorage::held_funds`], without needing the trait to be in
+    /// scope. See [`Client::held_amount`] for the exact value.
+    pub fn held(&self) -> Result<f32> {
+        self.held_funds()
+    }
+
+    /// The total funds for this client
+    ///
+    /// This is the same as [`ClientStorage::total_funds`], without needing the trait to be in
+    /// scope. See [`Client::total_amount`] for the exact value.
+    pub fn total(&self) -> Result<f32> {
+        self.total_funds()
+    }
+
+    /// The current status of the client's account
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    /// The exact funds currently available to this client
+    pub fn available_amount(&self) -> Amount {
+        self.available
+    }
+
+    /// The exact funds currently held for this client
+    pub fn held_amount(&self) -> Amount {
+        self.held
+    }
+
+    /// The exact total funds for this client, or `None` if the sum of the available and held
+    /// funds can't be represented
+    pub fn total_amount(&self) -> Option<Amount> {
+        self.available.checked_add(self.held)
+    }
+
+    /// The ids of any transactions which have been charged back
+    ///
+    /// Unlike the rest of the transaction log, these are retained after the chargeback so that
+    /// the transaction which caused the account to be frozen can be identified.
+    pub fn charged_back_transactions(&self) -> &[u32] {
+        &self.charged_back
+    }
+
+    /// Identifies whether this client holds no state, ie. it has no funds, is active and has not
+    /// processed any transactions
+    pub fn is_empty(&self) -> bool {
+        self.status == AccountStatus::Active
+            && self.transaction_log.is_empty()
+            && self.available.is_zero()
+            && self.held.is_zero()
+    }
+
+    /// Identifies whether the provided transaction id has been processed for this client
+    pub fn contains_transaction(&self, transaction_id: u32) -> bool {
+        self.transaction_log.contains_key(&transaction_id)
+    }
+
+    /// Identifies whether this client currently has any transactions which are under dispute
+    pub fn has_open_disputes(&self) -> bool {
+        self.open_disputes > 0
+    }
+
+    /// The `(transaction id, reason)` of every transaction which is currently under dispute,
+    /// ordered by transaction id
+    pub fn open_disputes(&self) -> Vec<(u32, Option<&str>)> {
+        let mut disputes = self
+            .transaction_log
+            .iter()
+            .filter_map(|(tx, trx)| match trx {
+                Some(Transaction::Dispute { reason, .. }) => Some((*tx, reason.as_deref())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        disputes.sort_unstable_by_key(|(tx, _)| *tx);
+        disputes
+    }
+
+    /// Attaches a reason _(eg. `fraud`)_ to a transaction which is currently under dispute,
+    /// returning `false` if the transaction isn't under dispute
+    ///
+    /// The reason is metadata which doesn't affect processing, and is discarded once the dispute
+    /// is resolved or charged back.
+    pub fn set_dispute_reason(&mut self, transaction_id: u32, reason: String) -> bool {
+        match self.transaction_log.get_mut(&transaction_id) {
+            Some(Some(Transaction::Dispute { reason: r, .. })) => {
+                *r = Some(reason);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The number of transactions which are currently under dispute
+    ///
+    /// This is tracked as transactions are processed, so it is cheap to call regardless of the
+    /// size of the transaction log.
+    pub fn open_dispute_count(&self) -> usize {
+        self.open_disputes
+    }
+
+    /// Calculates what the client's `(available, held)` funds would be after processing the
+    /// provided transaction, without modifying the client
+    ///
+    /// This applies the same validation as [`ClientStorage::process_transaction`], however a
+    /// transaction which would be ignored _(eg. a deposit with no amount)_ is instead an error,
+    /// as there is no resulting balance to project.
+    ///
+    /// ```
+    /// use lib::storage::{Client, ClientStorage};
+    /// use lib::transaction::TransactionType;
+    /// use lib::Amount;
+    ///
+    /// let mut client = Client::new(1);
+    /// client.process_transaction(1, TransactionType::Deposit, Some("10".parse().unwrap())).unwrap();
+    ///
+    /// let (available, held) = client
+    ///     .project_balance(2, TransactionType::Withdrawal, Some("4".parse().unwrap()))
+    ///     .unwrap();
+    /// assert_eq!(available.to_exact_string(), "6.0000");
+    /// assert_eq!(held.to_exact_string(), "0.0000");
+    /// assert_eq!(client.available_amount().to_exact_string(), "10.0000");
+    /// ```
+    pub fn project_balance(
+        &self,
+        transaction_id: u32,
+        transaction_type: TransactionType,
+        amount: Option<Amount>,
+    ) -> Result<(Amount, Amount)> {
+        if self.is_locked() {
+            return Err(TransactionError::FrozenAccount { client: self.id }.into());
+        }
+        let (available, held) = (self.available, self.held);
+
+        match (
+            self.transaction_log.get(&transaction_id),
+            transaction_type,
+            amount,
+        ) {
+            (None, TransactionType::Deposit, Some(amount)) => available
+                .checked_add(amount)
+                .map(|available| (available, held))
+                .ok_or_else(|| eyre!("the deposit would overflow the available funds")),
+            (None, TransactionType::Withdrawal, Some(amount)) if available >= amount => {
+                Ok((available - amount, held))
+            }
+            (None, TransactionType::Withdrawal, Some(_)) => {
+                Err(TransactionError::InsufficientFunds { tx: transaction_id }.into())
+            }
+            (None, ty @ (TransactionType::Deposit | TransactionType::Withdrawal), None) => {
+                Err(eyre!(
+                    "unable to process {} when no amount is provided",
+                    ty.as_str()
+                ))
+            }
+            (None, TransactionType::Transfer, _) => Err(eyre!(
+                "unable to project a transfer as it also changes the destination client"
+            )),
+            (None, ty, _) => Err(eyre!(
+                "unable to process {} as transaction id {} does not exist for client {}",
+                ty.as_str(),
+                transaction_id,
+                self.id
+            )),
+            (Some(None), ty, _) => Err(rejected_transition(transaction_id, ty).into()),
+            (Some(Some(trx)), ty, _) => {
+                if matches!(ty, TransactionType::Deposit | TransactionType::Withdrawal)
+                    && trx.origin() != ty
+                {
+                    return Err(TransactionError::IdTypeConflict {
+                        tx: transaction_id,
+                        existing: trx.origin(),
+                        attempted: ty,
+                    }
+                    .into());
+                }
+                if ty == TransactionType::Dispute {
+                    self.check_dispute_policy(transaction_id, trx)?;
+                }
+                let is_withdrawal = trx.origin() == TransactionType::Withdrawal;
+                let transition = trx
+                    .clone()
+                    .transition(ty.clone())
+                    .map_err(|_| rejected_transition(transaction_id, ty))?;
+                let projected = match (transition, is_withdrawal) {
+                    (Transaction::Dispute { amount, .. }, false) => {
+                        (available.checked_sub(amount), held.checked_add(amount))
+                    }
+                    (Transaction::Dispute { amount, .. }, true) => {
+                        (Some(available), held.checked_add(amount))
+                    }
+                    (Transaction::Resolve { amount, .. }, false) => {
+                        (available.checked_add(amount), held.checked_sub(amount))
+                    }
+                    (Transaction::Resolve { amount, .. }, true) => {
+                        (Some(available), held.checked_sub(amount))
+                    }
+                    (Transaction::Chargeback { amount, .. }, false) => {
+                        (Some(available), held.checked_sub(amount))
+                    }
+                    (Transaction::Chargeback { amount, .. }, true) => {
+                        (available.checked_add(amount), held.checked_sub(amount))
+                    }
+                    _ => return Err(eyre!("an unexpected error occured, it should not be possible to make this transition")),
+                };
+                match projected {
+                    (Some(available), Some(held)) => Ok((available, held)),
+                    _ => Err(TransactionError::Overflow { tx: transaction_id }.into()),
+                }
+            }
+        }
+    }
+
+    /// The same as [`ClientStorage::process_transaction`], however the transaction occurred at
+    /// the provided `timestamp` _(in seconds since the unix epoch)_, if it is known
+    ///
+    /// The timestamp of a deposit is retained so that, if a dispute window has been set _(see
+    /// [`Client::with_dispute_window`])_, a dispute which arrives after the window has expired
+    /// is rejected with [`TransactionError::DisputeWindowExpired`]. A dispute is always allowed
+    /// if either it or the deposit it references has no timestamp.
+    #[instrument(level = "debug", skip(self, amount), fields(client_id = %self.id), err)]
+    pub fn process_transaction_at(
+        &mut self,
+        transaction_id: u32,
+        transaction_type: TransactionType,
+        amount: Option<Amount>,
+        timestamp: Option<u64>,
+    ) -> Result<()> {
+        if transaction_type == TransactionType::Heartbeat {
+            return Ok(());
+        }
+        // A client can't hold handlers, see `BasicEngine::register_transaction_type`
+        if let TransactionType::Custom(name) = transaction_type {
+            warn!(
+                "unable to process transaction type {:?} as no handler has been registered for it",
+                name
+            );
+            return Err(TransactionError::UnknownTransactionType {
+                tx: transaction_id,
+                name,
+            }
+            .into());
+        }
+        if self.is_locked() {
+            // A repeat of the dispute, resolve or chargeback which froze the account is a
+            // duplicate row rather than a new transaction, so it's safe to ignore
+            if matches!(
+                transaction_type,
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+            ) && self.charged_back.contains(&transaction_id)
+            {
+                warn!(
+                    "ignoring {:?} for transaction id {} as it has already been charged back",
+                    transaction_type, transaction_id
+                );
+                return Ok(());
+            }
+            warn!("unable to carry out transaction as account is frozen");
+            return Err(TransactionError::FrozenAccount { client: self.id }.into());
+        }
+
+        let current = match self.transaction_log.entry(transaction_id) {
+            // In this case we have a brand new transaction we've not seen before
+            //
+            // This is the hot path for the majority of inputs, so the vacant entry is used
+            // directly rather than looking up the transaction id a second time
+            Entry::Vacant(v) => {
+                match (transaction_type, amount) {
+                    (TransactionType::Deposit, Some(amount)) => {
+                        apply_deposit(v, &mut self.available, amount, timestamp)?;
+                        self.record_event(
+                            transaction_id,
+                            None,
+                            TransactionType::Deposit,
+                            timestamp,
+                        );
+                    }
+                    (TransactionType::Withdrawal, Some(amount)) => {
+                        apply_withdrawal(v, &mut self.available, amount)?;
+                        self.record_event(
+                            transaction_id,
+                            None,
+                            TransactionType::Withdrawal,
+                            timestamp,
+                        );
+                    }
+                    (ty @ (TransactionType::Deposit | TransactionType::Withdrawal), None) => warn!(
+                        "unable to process transition type {:?} when no amount is provided",
+                        ty
+                    ),
+                    (TransactionType::Transfer, _) => {
+                        warn!("unable to process a transfer for client {} as it must be applied by an engine holding both clients", self.id);
+                    }
+                    (ty, _) => {
+                        warn!("Unable to process transaction type {:?} as transaction id {} does not exist for client {}", ty, transaction_id, self.id);
+                    }
+                }
+                return Ok(());
+            }
+            Entry::Occupied(o) => o.get().clone(),
+        };
+
+        match current {
+            // We currently have a transaction stored under this id
+            //
+            // If an invalid state transition occurs we reject it, leaving the transaction log
+            // untouched
+            Some(trx) => {
+                if matches!(
+                    transaction_type,
+                    TransactionType::Deposit | TransactionType::Withdrawal
+                ) && trx.origin() != transaction_type
+                {
+                    warn!(
+                        "unable to process {:?} for transaction id {} as the id is already used by a {:?}",
+                        transaction_type, transaction_id, trx.origin()
+                    );
+                    return Err(TransactionError::IdTypeConflict {
+                        tx: transaction_id,
+                        existing: trx.origin(),
+                        attempted: transaction_type,
+                    }
+                    .into());
+                }
+                if transaction_type == TransactionType::Dispute {
+                    self.check_dispute_window(transaction_id, &trx, timestamp)?;
+                    self.check_dispute_policy(transaction_id, &trx)?;
+                }
+                let is_withdrawal = trx.origin() == TransactionType::Withdrawal;
+                let from = trx.state();
+                let state_change = match trx.transition(transaction_type.clone()) {
+                    Ok(state_change) => state_change,
+                    Err(_) => {
+                        return Err(rejected_transition(transaction_id, transaction_type).into())
+                    }
+                };
+                let to = state_change.state();
+                match (state_change, is_withdrawal) {
+                    (Transaction::Dispute { amount, .. }, false) => {
+                        self.dispute(transaction_id, amount)?
+                    }
+                    (Transaction::Dispute { amount, .. }, true) => {
+                        self.dispute_withdrawal(transaction_id, amount)?
+                    }
+                    (Transaction::Resolve { amount, .. }, false) => {
+                        self.resolve(transaction_id, amount)?
+                    }
+                    (Transaction::Resolve { amount, .. }, true) => {
+                        self.resolve_withdrawal(transaction_id, amount)?
+                    }
+                    (Transaction::Chargeback { amount, .. }, false) => {
+                        self.chargeback(transaction_id, amount)?;
+                        self.record_event(transaction_id, Some(from), to, timestamp);
+                        return Err(TransactionError::FrozenAccount { client: self.id }.into());
+                    }
+                    (Transaction::Chargeback { amount, .. }, true) => {
+                        self.chargeback_withdrawal(transaction_id, amount)?;
+                        self.record_event(transaction_id, Some(from), to, timestamp);
+                        return Err(TransactionError::FrozenAccount { client: self.id }.into());
+                    }
+                    _ => return Err(eyre!("an unexpected error occured, it should not be possible to make this transition"))
+                }
+                self.record_event(transaction_id, Some(from), to, timestamp);
+            }
+            // A transaction with this id has already been resolved in some manner
+            // - This handles duplicate transaction ids
+            None => {
+                warn!(
+                    "attempted to process transaction id: {} which has already been processed",
+                    transaction_id
+                );
+                return Err(rejected_transition(transaction_id, transaction_type).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Disputing a withdrawal holds the withdrawn funds, pending whether the withdrawal is
+    /// resolved _(it stands)_ or charged back _(it is reversed)_. The available funds are
+    /// unaffected, as they were already debited by the withdrawal.
+    fn dispute_withdrawal(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
+        self.held = checked(self.held.checked_add(amount), transaction_id)?;
+        self.update_log(
+            transaction_id,
+            Some(Transaction::Dispute {
+                amount,
+                reason: None,
+                origin: TransactionType::Withdrawal,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Resolving a disputed withdrawal means the withdrawal stands, so the held funds are
+    /// released without being returned to the available funds
+    fn resolve_withdrawal(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
+        self.held = checked(self.held.checked_sub(amount), transaction_id)?;
+        self.update_log(transaction_id, None);
+        Ok(())
+    }
+
+    /// Charging back a disputed withdrawal reverses it, so the held funds are returned to the
+    /// available funds before the account is frozen
+    fn chargeback_withdrawal(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
+        let available = checked(self.available.checked_add(amount), transaction_id)?;
+        self.chargeback(transaction_id, amount)?;
+        self.available = available;
+        Ok(())
+    }
+
+    /// Replaces the entry of the transaction in the log, keeping the count of open disputes up
+    /// to date
+    fn update_log(&mut self, transaction_id: u32, entry: Option<Transaction>) {
+        let is_dispute = matches!(entry, Some(Transaction::Dispute { .. }));
+        let was_dispute = matches!(
+            self.transaction_log.insert(transaction_id, entry),
+            Some(Some(Transaction::Dispute { .. }))
+        );
+        match (was_dispute, is_dispute) {
+            (false, true) => self.open_disputes += 1,
+            (true, false) => self.open_disputes -= 1,
+            _ => {}
+        }
+    }
+
+    fn check_dispute_window(
+        &self,
+        transaction_id: u32,
+        trx: &Transaction,
+        disputed_at: Option<u64>,
+    ) -> Result<()> {
+        if let (
+            Some(window),
+            &Transaction::Deposit {
+                timestamp: Some(deposited_at),
+                ..
+            },
+            Some(disputed_at),
+        ) = (self.dispute_window, trx, disputed_at)
+        {
+            if disputed_at.saturating_sub(deposited_at) > window.as_secs() {
+                warn!(
+                    "unable to dispute transaction id {} as the dispute window has expired",
+                    transaction_id
+                );
+                return Err(TransactionError::DisputeWindowExpired {
+                    tx: transaction_id,
+                    deposited_at,
+                    disputed_at,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects the dispute of a deposit which would leave the client with negative available
+    /// funds, if the client's [`DisputePolicy`] requires it
+    fn check_dispute_policy(&self, transaction_id: u32, trx: &Transaction) -> Result<()> {
+        if let (DisputePolicy::RejectIfInsufficient, &Transaction::Deposit { amount, .. }) =
+            (self.dispute_policy, trx)
+        {
+            if self.available < amount {
+                warn!(
+                    "unable to dispute transaction id {} as the account does not have enough available funds",
+                    transaction_id
+                );
+                return Err(
+                    TransactionError::InsufficientFundsForDispute { tx: transaction_id }.into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves `amount` from the available funds of this client into the available funds of the
+    /// `destination` client
+    ///
+    /// The transfer is atomic, either both clients are updated or neither is. It is rejected with
+    /// [`TransactionError::InsufficientFunds`] if this client doesn't have enough available
+    /// funds, with [`TransactionError::DuplicateTransaction`] if either client has already
+    /// processed the transaction id, or with [`TransactionError::FrozenAccount`] if either
+    /// account is frozen.
+    ///
+    /// The transfer is recorded against the transaction id of both clients, so the id can't be
+    /// reused by either of them. A transfer can't be disputed.
+    pub fn transfer(
+        &mut self,
+        destination: &mut Client,
+        transaction_id: u32,
+        amount: Amount,
+    ) -> Result<()> {
+        if let Some(client) = [&*self, &*destination].iter().find(|c| c.is_locked()) {
+            warn!("unable to carry out transfer as account is frozen");
+            return Err(TransactionError::FrozenAccount { client: client.id }.into());
+        }
+        if self.contains_transaction(transaction_id)
+            || destination.contains_transaction(transaction_id)
+        {
+            warn!(
+                "we have already processed transaction id {}, therefore we're rejecting this",
+                transaction_id
+            );
+            return Err(TransactionError::DuplicateTransaction { tx: transaction_id }.into());
+        }
+        if self.available < amount {
+            warn!("unable to transfer as the account does not have enough available funds");
+            return Err(TransactionError::InsufficientFunds { tx: transaction_id }.into());
+        }
+        destination.available = destination
+            .available
+            .checked_add(amount)
+            .ok_or(TransactionError::Overflow { tx: transaction_id })?;
+        self.available -= amount;
+        self.update_log(transaction_id, None);
+        destination.update_log(transaction_id, None);
+        for client in [self, destination] {
+            client.record_event(transaction_id, None, TransactionType::Transfer, None);
+        }
+        Ok(())
+    }
 }
 
 impl ClientStorage for Client {
@@ -135,7 +976,8 @@ impl ClientStorage for Client {
 
     /// A getter method used to calculate the total funds for this client
     fn total_funds(&self) -> Result<f32> {
-        (self.available + self.held)
+        self.total_amount()
+            .ok_or_else(|| eyre!("total funds for client {} overflowed", self.id))?
             .try_into()
             .wrap_err("unexpected error occurred when attempting to calculate total funds")
     }
@@ -161,100 +1003,43 @@ impl ClientStorage for Client {
     /// This function will error if:
     /// 1. The account status of the client is [`AccountStatus::Frozen`]
     /// 2. An unexpected error occurs
+    /// 3. The transaction is rejected, eg. a duplicate transaction id, an invalid state
+    ///    transition or not enough funds to carry out a withdrawal
     ///
-    /// An error from this function indicates that processing should stop for this client
+    /// An error from this function indicates that processing should stop for this client, unless
+    /// it is a [`TransactionError`] which [is recoverable](TransactionError::is_recoverable)
     ///
     /// ## Ignores
     ///
-    /// This function will ignore _and return `Ok(())`_ for any invalid transactions, whether that be due to:
-    /// - Invalid state transtions
-    /// - Not enough funds to carry out a withdrawal
-    /// - Invalid data (eg. A deposit or withdrawal with no amount)
-    #[instrument(level = "debug", skip(self, amount), fields(client_id = %self.id), err)]
+    /// This function will ignore _and return `Ok(())`_ for any invalid data (eg. A deposit or
+    /// withdrawal with no amount)
     fn process_transaction(
         &mut self,
         transaction_id: u32,
         transaction_type: TransactionType,
         amount: Option<Amount>,
     ) -> Result<()> {
-        if self.is_locked() {
-            warn!("unable to carry out transaction as account is frozen");
-            // TODO - Make this a matchable enum
-            return Err(eyre!(
-                "[FROZEN_ACCOUNT]: unable to carry out transaction when the account is frozen"
-            ));
-        }
-
-        // In this case we have a brand new transaction we've not seen before
-        if !self.transaction_log.contains_key(&transaction_id) {
-            match transaction_type {
-                TransactionType::Deposit if amount.is_some() => {
-                    self.deposit(transaction_id, amount.unwrap())
-                }
-                TransactionType::Withdrawal if amount.is_some() => {
-                    self.withdraw(transaction_id, amount.unwrap())
-                }
-                TransactionType::Deposit | TransactionType::Withdrawal => warn!(
-                    "unable to process transition type {:?} when no amount is provided",
-                    transaction_type
-                ),
-                _ => {
-                    warn!("Unable to process transaction type {:?} as transaction id {} does not exist for client {}", transaction_type, transaction_id, self.id);
-                }
-            }
-            return Ok(());
-        }
-
-        match self.transaction_log.remove(&transaction_id) {
-            // We currently have a transaction stored under this id
-            Some(Some(trx)) => {
-                match trx.transition(transaction_type) {
-                    Ok(state_change) => match state_change {
-                        Transaction::Dispute { amount } => self.dispute(transaction_id, amount),
-                        Transaction::Resolve { amount } => self.resolve(transaction_id, amount),
-                        Transaction::Chargeback { amount } => {
-                            self.chargeback(transaction_id, amount);
-                            return Err(eyre!("[FROZEN_ACCOUNT] this client account '{}' has been frozen, no further transactions can occur", self.id));
-                        },
-                        _ => return Err(eyre!("an unexpected error occured, it should not be possible to make this transition"))
-                    }
-                    // In this case, an invalid state transition has occurred
-                    // so we ignore it
-                    Err(_) => {
-                        self.transaction_log.insert(transaction_id, Some(trx));
-                    }
-                }
-            }
-            // A transaction with this id has already been resolved in some manner
-            // - This handles duplicate transaction ids
-            Some(None) => {
-                warn!(
-                    "attempted to process transaction id: {} which has already been processed",
-                    transaction_id
-                );
-                self.transaction_log.insert(transaction_id, None);
-            }
-
-            None => unreachable!("this is handled by the contains_key check above"),
-        }
-        Ok(())
+        self.process_transaction_at(transaction_id, transaction_type, amount, None)
     }
 
     /// This will update the internally held totals on the funds and insert an entry in the
     /// transaction log
     ///
-    /// If this provided transaction id has already been processed, this will ignore it
-    fn deposit(&mut self, transaction_id: u32, amount: Amount) {
+    /// If this provided transaction id has already been processed, this will error with
+    /// [`TransactionError::DuplicateTransaction`]
+    fn deposit(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
         match self.transaction_log.entry(transaction_id) {
             Entry::Occupied(_) => {
                 warn!(
-                    "we have already processed transaction id {}, therefore we're ignoring this",
+                    "we have already processed transaction id {}, therefore we're rejecting this",
                     transaction_id
                 );
+                Err(TransactionError::DuplicateTransaction { tx: transaction_id }.into())
             }
             Entry::Vacant(v) => {
-                v.insert(Some(Transaction::Deposit { amount }));
-                self.available += amount;
+                apply_deposit(v, &mut self.available, amount, None)?;
+                self.record_event(transaction_id, None, TransactionType::Deposit, None);
+                Ok(())
             }
         }
     }
@@ -262,37 +1047,45 @@ impl ClientStorage for Client {
     /// This will update the internally held totals on the funds and insert an entry in the
     /// transaction log
     ///
-    /// If this provided transaction id has already been processed, this will ignore it
-    fn withdraw(&mut self, transaction_id: u32, amount: Amount) {
+    /// If this provided transaction id has already been processed, this will error with
+    /// [`TransactionError::DuplicateTransaction`]
+    fn withdraw(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
         match self.transaction_log.entry(transaction_id) {
             Entry::Occupied(_) => {
                 warn!(
-                    "we have already processed transaction id {}, therefore we're ignoring this",
+                    "we have already processed transaction id {}, therefore we're rejecting this",
                     transaction_id
                 );
+                Err(TransactionError::DuplicateTransaction { tx: transaction_id }.into())
             }
             Entry::Vacant(v) => {
-                if self.available >= amount {
-                    self.available -= amount;
-                    v.insert(None);
-                } else {
-                    warn!("unable to withdraw as the account does not have enough available funds")
-                }
+                apply_withdrawal(v, &mut self.available, amount)?;
+                self.record_event(transaction_id, None, TransactionType::Withdrawal, None);
+                Ok(())
             }
         }
     }
 
-    fn dispute(&mut self, transaction_id: u32, amount: Amount) {
-        self.available -= amount;
-        self.held += amount;
-
-        self.transaction_log
-            .insert(transaction_id, Some(Transaction::Dispute { amount }));
+    fn dispute(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
+        let available = checked(self.available.checked_sub(amount), transaction_id)?;
+        self.held = checked(self.held.checked_add(amount), transaction_id)?;
+        self.available = available;
+
+        self.update_log(
+            transaction_id,
+            Some(Transaction::Dispute {
+                amount,
+                reason: None,
+                origin: TransactionType::Deposit,
+            }),
+        );
+        Ok(())
     }
 
-    fn resolve(&mut self, transaction_id: u32, amount: Amount) {
-        self.held -= amount;
-        self.available += amount;
+    fn resolve(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
+        let held = checked(self.held.checked_sub(amount), transaction_id)?;
+        self.available = checked(self.available.checked_add(amount), transaction_id)?;
+        self.held = held;
 
         // This is an optimization based off the **Valid State Transitions** assumption
         // in the readme.
@@ -300,12 +1093,14 @@ impl ClientStorage for Client {
         // If we enter this state, this transaction id can no longer be modified, therefore we can
         // completely remove the associated data. However we keep the transaction id so we don't
         // re-process if it gets passed through again
-        self.transaction_log.insert(transaction_id, None);
+        self.update_log(transaction_id, None);
+        Ok(())
     }
 
-    fn chargeback(&mut self, _transaction_id: u32, amount: Amount) {
-        self.held -= amount;
+    fn chargeback(&mut self, transaction_id: u32, amount: Amount) -> Result<()> {
+        self.held = checked(self.held.checked_sub(amount), transaction_id)?;
         self.status = AccountStatus::Frozen;
+        self.charged_back.push(transaction_id);
 
         // This is an optimization
         //
@@ -314,10 +1109,76 @@ impl ClientStorage for Client {
         // as we won't be processing any more transactions for this client so we can free up this memory
         // - This optimization is tied to the fact that this is a CLI app that runs once
         // - In a real life scenario ie. API, we could still make this optimization, but
-        // we would need a way to re-populate the transaction log should the account become
-        // unfrozen.
+        // a reinstated account starts with an empty history _(see `Client::reinstate`)_
+        // - The recorded history _(see `Client::history`)_ is kept separately, so it is unaffected
         self.transaction_log.clear();
+        self.open_disputes = 0;
+        Ok(())
+    }
+}
+
+/// The error for a transaction which can't be applied to the current state of the transaction
+/// it references, a deposit or withdrawal reusing the id of a transaction of the same type is a
+/// duplicate
+fn rejected_transition(transaction_id: u32, transaction_type: TransactionType) -> TransactionError {
+    match transaction_type {
+        TransactionType::Deposit | TransactionType::Withdrawal => {
+            TransactionError::DuplicateTransaction { tx: transaction_id }
+        }
+        attempted => TransactionError::InvalidTransition {
+            tx: transaction_id,
+            attempted,
+        },
+    }
+}
+
+/// Maps the result of a checked operation on the funds to [`TransactionError::Overflow`] if the
+/// funds would have overflowed
+fn checked(funds: Option<Amount>, transaction_id: u32) -> Result<Amount> {
+    funds.ok_or_else(|| {
+        warn!(
+            "unable to apply transaction id {} as the funds would overflow",
+            transaction_id
+        );
+        TransactionError::Overflow { tx: transaction_id }.into()
+    })
+}
+
+/// Records a brand new deposit in the transaction log, crediting the available funds
+///
+/// The deposit is rejected with [`TransactionError::Overflow`] if it would overflow the
+/// available funds
+fn apply_deposit(
+    entry: VacantEntry<'_, u32, Option<Transaction>>,
+    available: &mut Amount,
+    amount: Amount,
+    timestamp: Option<u64>,
+) -> Result<()> {
+    *available = available.checked_add(amount).ok_or_else(|| {
+        warn!("unable to deposit as the available funds would overflow");
+        TransactionError::Overflow { tx: *entry.key() }
+    })?;
+    entry.insert(Some(Transaction::Deposit { amount, timestamp }));
+    Ok(())
+}
+
+/// Records a brand new withdrawal in the transaction log, debiting the available funds
+///
+/// This errors with [`TransactionError::InsufficientFunds`] if there aren't enough available
+fn apply_withdrawal(
+    entry: VacantEntry<'_, u32, Option<Transaction>>,
+    available: &mut Amount,
+    amount: Amount,
+) -> Result<()> {
+    if *available < amount {
+        warn!("unable to withdraw as the account does not have enough available funds");
+        return Err(TransactionError::InsufficientFunds { tx: *entry.key() }.into());
     }
+    *available = available
+        .checked_sub(amount)
+        .ok_or(TransactionError::Overflow { tx: *entry.key() })?;
+    entry.insert(Some(Transaction::Withdrawal { amount }));
+    Ok(())
 }
 
 impl fmt::Debug for Client {
@@ -335,27 +1196,63 @@ impl Serialize for Client {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("Client", 5)?;
-        state.serialize_field("client", &self.id)?;
-        state.serialize_field(
-            "available",
-            &self
-                .available_funds()
-                .map_err(|e| Error::custom(e.to_string()))?,
-        )?;
-        state.serialize_field(
-            "held",
-            &self
-                .held_funds()
-                .map_err(|e| Error::custom(e.to_string()))?,
-        )?;
-        state.serialize_field(
-            "total",
-            &self
-                .total_funds()
-                .map_err(|e| Error::custom(e.to_string()))?,
-        )?;
+        self.serialize_with_options(serializer, &OutputOptions::default())
+    }
+}
+
+impl Client {
+    /// Serializes the client, including any of the optional columns enabled in the provided
+    /// [`OutputOptions`]
+    pub(crate) fn serialize_with_options<S>(
+        &self,
+        serializer: S,
+        options: &OutputOptions,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let len = if options.with_dispute_flag { 6 } else { 5 };
+        // The output contract is that the client id is written exactly as a `u16`. This binding
+        // is deliberately typed so that widening the id fails to compile rather than silently
+        // changing (or truncating) what is written out.
+        let client: u16 = self.id;
+        let mut state = serializer.serialize_struct("Client", len)?;
+        state.serialize_field("client", &client)?;
+        let round = |amount: Amount| match options.precision {
+            Some(dp) => amount.round_to(dp),
+            None => amount,
+        };
+        let to_string = |amount: Amount| match options.precision {
+            Some(dp) => amount.to_string_with_precision(dp),
+            None => amount.to_exact_string(),
+        };
+        // An overflowing total is written as an empty field rather than failing the whole
+        // output, see [`TotalOverflow`](crate::output::TotalOverflow)
+        let total = self.total_amount();
+        if total.is_none() {
+            warn!(
+                "the total funds for client {} overflowed, writing an empty total",
+                self.id
+            );
+        }
+        if options.numeric_amounts {
+            let to_f64 = |amount: Amount| -> std::result::Result<f64, S::Error> {
+                round(amount)
+                    .try_into()
+                    .map_err(|e: rust_decimal::Error| Error::custom(e.to_string()))
+            };
+            state.serialize_field("available", &to_f64(self.available)?)?;
+            state.serialize_field("held", &to_f64(self.held)?)?;
+            state.serialize_field("total", &total.map(to_f64).transpose()?)?;
+        } else {
+            state.serialize_field("available", &to_string(self.available))?;
+            state.serialize_field("held", &to_string(self.held))?;
+            state.serialize_field("total", &total.map(to_string))?;
+        }
         state.serialize_field("locked", &self.is_locked())?;
+        if options.with_dispute_flag {
+            state.serialize_field("has_open_disputes", &self.has_open_disputes())?;
+        }
         state.end()
     }
 }
@@ -365,6 +1262,7 @@ mod tests {
     use super::*;
 
     use pretty_assertions::assert_eq;
+    use rust_decimal::Decimal;
     use serde::Deserialize;
 
     const ALLOWABLE_ERROR: f32 = 0.000049;
@@ -375,12 +1273,45 @@ mod tests {
                 id: self.id,
                 status: self.status,
                 transaction_log: self.transaction_log.clone(),
+                open_disputes: self.open_disputes,
                 held: self.held,
                 available: self.available,
+                charged_back: self.charged_back.clone(),
+                reconciliations: self.reconciliations.clone(),
+                dispute_window: self.dispute_window,
+                dispute_policy: self.dispute_policy,
+                history: self.history.clone(),
             }
         }
     }
 
+    #[test]
+    fn serializes_a_client_constructed_in_a_frozen_state() -> Result<()> {
+        let mut client = Client::with_state(
+            7,
+            Amount::new(10.5)?,
+            Amount::new(2.0)?,
+            AccountStatus::Frozen,
+        );
+        let mut result = vec![];
+        {
+            let mut writer = csv::Writer::from_writer(&mut result);
+            writer.serialize(&client)?;
+            writer.flush()?;
+        }
+        assert_eq!(
+            String::from_utf8(result)?,
+            "client,available,held,total,locked\n7,10.5000,2.0000,12.5000,true\n"
+        );
+        assert!(
+            client
+                .process_transaction(1, TransactionType::Deposit, Some(Amount::new(1.0)?))
+                .is_err(),
+            "a frozen client should reject any transaction"
+        );
+        Ok(())
+    }
+
     #[test]
     fn can_be_serialized() -> Result<()> {
         let client = client_with_state();
@@ -394,9 +1325,9 @@ mod tests {
         #[derive(Debug, Deserialize)]
         struct Test {
             client: u16,
-            available: f32,
-            held: f32,
-            total: f32,
+            available: String,
+            held: String,
+            total: String,
             locked: bool,
         }
 
@@ -407,35 +1338,211 @@ mod tests {
         for result in rdr.deserialize::<Test>() {
             let res = result?;
             assert_eq!(res.client, client.id);
-            assert_eq!(res.available, client.available_funds()?);
-            assert_eq!(res.held, client.held_funds()?);
-            assert_eq!(res.total, client.total_funds()?);
+            assert_eq!(res.available, client.available.to_exact_string());
+            assert_eq!(res.held, client.held.to_exact_string());
+            assert_eq!(
+                res.total,
+                (client.available + client.held).to_exact_string()
+            );
             assert_eq!(res.locked, client.is_locked());
         }
         Ok(())
     }
 
+    #[test]
+    fn serializes_amounts_as_exact_decimal_strings() -> Result<()> {
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some("10.5".parse()?))?;
+        client.process_transaction(2, TransactionType::Deposit, Some("0.25".parse()?))?;
+        client.process_transaction(2, TransactionType::Dispute, None)?;
+
+        let write = |options: OutputOptions| -> Result<String> {
+            let mut result = vec![];
+            {
+                let mut writer = csv::Writer::from_writer(&mut result);
+                writer.serialize(crate::output::ClientRecord::new(&client, &options))?;
+                writer.flush()?;
+            }
+            Ok(String::from_utf8(result)?)
+        };
+
+        assert_eq!(
+            write(OutputOptions::default())?,
+            "client,available,held,total,locked\n1,10.5000,0.2500,10.7500,false\n"
+        );
+        let numeric = OutputOptions {
+            numeric_amounts: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            write(numeric)?,
+            "client,available,held,total,locked\n1,10.5,0.25,10.75,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serializes_a_large_balance_without_losing_its_fractional_tail() -> Result<()> {
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some("10000".parse()?))?;
+        client.process_transaction(2, TransactionType::Deposit, Some("0.0001".parse()?))?;
+        client.process_transaction(3, TransactionType::Deposit, Some("123456789.1234".parse()?))?;
+        client.process_transaction(3, TransactionType::Dispute, None)?;
+
+        for options in [
+            OutputOptions::default(),
+            OutputOptions {
+                numeric_amounts: true,
+                ..Default::default()
+            },
+        ] {
+            let mut result = vec![];
+            {
+                let mut writer = csv::Writer::from_writer(&mut result);
+                writer.serialize(crate::output::ClientRecord::new(&client, &options))?;
+                writer.flush()?;
+            }
+            let mut reader = csv::Reader::from_reader(&*result);
+            let record = reader.records().next().expect("a client row")?;
+            let parsed = |i: usize| -> Result<Amount> { Ok(record[i].parse()?) };
+            assert_eq!(*parsed(1)?, *"10000.0001".parse::<Amount>()?);
+            assert_eq!(*parsed(2)?, *"123456789.1234".parse::<Amount>()?);
+            assert_eq!(*parsed(3)?, *"123466789.1235".parse::<Amount>()?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn client_id_round_trips_for_the_full_u16_range() -> Result<()> {
+        let mut result = vec![];
+        {
+            let mut writer = csv::Writer::from_writer(&mut result);
+            for id in 0..=u16::MAX {
+                writer.serialize(Client::new(id))?;
+            }
+            writer.flush()?;
+        }
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(&*result);
+        let ids = rdr
+            .records()
+            .map(|r| Ok(r?[0].parse::<u16>()?))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(ids.len(), u16::MAX as usize + 1);
+        assert_eq!(ids.first(), Some(&0));
+        assert_eq!(ids.last(), Some(&u16::MAX));
+        assert!(
+            ids.iter().zip(0..=u16::MAX).all(|(lhs, rhs)| *lhs == rhs),
+            "every client id should round trip exactly"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn is_only_empty_when_no_state_is_held() -> Result<()> {
+        let mut client = Client::new(1);
+        assert!(client.is_empty(), "a new client should be empty");
+        client.process_transaction(1, TransactionType::Resolve, None)?;
+        assert!(
+            client.is_empty(),
+            "an ignored transaction should not add state"
+        );
+        client.process_transaction(2, TransactionType::Deposit, Some(Amount::new(1.0)?))?;
+        assert!(!client.is_empty(), "a deposit should add state");
+        assert!(!client_with_state().is_empty());
+        Ok(())
+    }
+
     #[test]
     fn errors_if_the_account_is_frozen() -> Result<()> {
         let mut client = client_with_state();
         client.status = AccountStatus::Frozen;
         let tx_id = 1;
-        for tx in &[
+        for tx in [
             TransactionType::Deposit,
             TransactionType::Withdrawal,
             TransactionType::Dispute,
             TransactionType::Resolve,
             TransactionType::Chargeback,
         ] {
-            let res = client.process_transaction(tx_id, *tx, None);
-            assert!(
-                res.is_err(),
-                "if the account is frozen we should always error"
+            let res = client.process_transaction(tx_id, tx, None);
+            assert_eq!(
+                res.expect_err("if the account is frozen we should always error")
+                    .downcast_ref::<TransactionError>(),
+                Some(&TransactionError::FrozenAccount { client: 1 })
             );
         }
         Ok(())
     }
 
+    #[cfg(feature = "balance_overrides")]
+    #[test]
+    fn serializes_an_overridden_held_amount() -> Result<()> {
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some("10".parse()?))?;
+        client.set_held("2.5".parse()?);
+
+        let mut result = vec![];
+        {
+            let mut writer = csv::Writer::from_writer(&mut result);
+            writer.serialize(&client)?;
+            writer.flush()?;
+        }
+        assert_eq!(
+            String::from_utf8(result)?,
+            "client,available,held,total,locked\n1,10.0000,2.5000,12.5000,false\n"
+        );
+
+        client.set_available("1".parse()?);
+        assert_eq!(
+            client
+                .total_amount()
+                .map(Amount::to_exact_string)
+                .as_deref(),
+            Some("3.5000")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn exposes_the_balances_and_status_of_the_client() -> Result<()> {
+        let mut client = client_with_state();
+        assert_eq!(client.available()?, client.available_funds()?);
+        assert_eq!(client.held()?, client.held_funds()?);
+        assert_eq!(client.total()?, client.total_funds()?);
+        assert_eq!(client.status(), AccountStatus::Active);
+
+        let _ = client.process_transaction(2, TransactionType::Chargeback, None);
+        assert_eq!(client.status(), AccountStatus::Frozen);
+        Ok(())
+    }
+
+    #[test]
+    fn processes_transactions_again_once_reinstated() -> Result<()> {
+        let mut client = client_with_state();
+        assert!(
+            client.reinstate().is_err(),
+            "an active account can't be reinstated"
+        );
+        let _ = client.process_transaction(2, TransactionType::Chargeback, None);
+        assert!(client.is_locked());
+
+        client.reinstate()?;
+        assert!(!client.is_locked());
+        assert!(
+            client.transaction_log.is_empty(),
+            "a reinstated account starts with an empty history"
+        );
+        let available = client.available_amount();
+        client.process_transaction(4, TransactionType::Deposit, Some(Amount::new(5.0)?))?;
+        assert_eq!(*client.available_amount(), *available + *Amount::new(5.0)?);
+        client.process_transaction(4, TransactionType::Dispute, None)?;
+        assert_eq!(*client.held_amount(), *Amount::new(5.0)?);
+        Ok(())
+    }
+
     #[test]
     fn handles_deposit_with_a_new_transaction_id() -> Result<()> {
         let mut client = Client::new(1);
@@ -458,18 +1565,180 @@ mod tests {
             log_data.unwrap().is_some(),
             "the nested transaction id should be some"
         );
-        let is_deposit_type = matches!(log_data.unwrap().unwrap(), Transaction::Deposit { .. });
+        let is_deposit_type = matches!(
+            log_data.unwrap().as_ref().unwrap(),
+            Transaction::Deposit { .. }
+        );
         assert!(is_deposit_type, "the transaction should be of type deposit");
         Ok(())
     }
 
+    #[test]
+    fn processing_matches_applying_deposits_and_withdrawals_directly() -> Result<()> {
+        let rows = [
+            (1, TransactionType::Deposit, 10.5f32),
+            (2, TransactionType::Deposit, 3.25),
+            (1, TransactionType::Deposit, 99.0),
+            (3, TransactionType::Withdrawal, 4.0),
+            (4, TransactionType::Withdrawal, 400.0),
+            (3, TransactionType::Withdrawal, 1.0),
+            (5, TransactionType::Deposit, 0.0001),
+        ];
+        let mut processed = Client::new(1);
+        let mut applied = Client::new(1);
+        for (tx_id, ty, amt) in rows {
+            let amount = Amount::new(amt)?;
+            let applied_result = match ty {
+                TransactionType::Deposit => applied.deposit(tx_id, amount),
+                _ => applied.withdraw(tx_id, amount),
+            };
+            let processed_result = processed.process_transaction(tx_id, ty, Some(amount));
+            assert_eq!(
+                applied_result.err().map(|e| e.to_string()),
+                processed_result.err().map(|e| e.to_string())
+            );
+        }
+        check_has_not_mutated_state(applied.clone(), processed.clone())?;
+        assert_eq!(
+            applied.transaction_log.len(),
+            processed.transaction_log.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ignores_a_duplicate_chargeback_once_the_account_is_frozen() -> Result<()> {
+        let mut client = client_with_state();
+        let result = client.process_transaction(2, TransactionType::Chargeback, None);
+        assert!(
+            result.is_err(),
+            "the first chargeback should freeze the account"
+        );
+        assert!(client.is_locked());
+        let after_first = client.clone();
+
+        for ty in [TransactionType::Chargeback, TransactionType::Resolve] {
+            client.process_transaction(2, ty, None)?;
+        }
+        check_has_not_mutated_state(after_first, client.clone())?;
+        assert_eq!(client.charged_back_transactions(), &[2]);
+
+        // Any other transaction against the frozen account is still rejected
+        let result =
+            client.process_transaction(3, TransactionType::Deposit, Some(Amount::new(1.0)?));
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reconciles_to_an_authoritative_balance() -> Result<()> {
+        let mut client = client_with_state();
+
+        let adjustment =
+            client.reconcile("18.5".parse()?, "3.1492".parse()?, "ledger sync 2022-07-01");
+        assert_eq!(client.available_amount().to_exact_string(), "18.5000");
+        assert_eq!(client.held_amount().to_exact_string(), "3.1492");
+        assert!(adjustment.is_negative());
+        assert_eq!(adjustment.to_exact_string(), "-1.8200");
+
+        let reconciliations = client.reconciliations();
+        assert_eq!(reconciliations.len(), 1);
+        assert_eq!(reconciliations[0].reason, "ledger sync 2022-07-01");
+        assert_eq!(
+            reconciliations[0].available_adjustment.to_exact_string(),
+            "-1.8200"
+        );
+
+        // Reconciling again to the same balance is recorded, but makes no adjustment
+        let adjustment = client.reconcile(client.available, client.held, "no-op");
+        assert!(!adjustment.is_negative());
+        assert_eq!(adjustment.to_exact_string(), "0.0000");
+        assert_eq!(client.reconciliations().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_a_deposit_which_would_overflow_the_available_funds() -> Result<()> {
+        let mut client = Client::new(1);
+        let near_max = Amount::try_new(Decimal::MAX - Decimal::ONE)?;
+        client.process_transaction(1, TransactionType::Deposit, Some(near_max))?;
+        let before = client.clone();
+
+        let err = client
+            .process_transaction(2, TransactionType::Deposit, Some(Amount::new(2.0)?))
+            .expect_err("the deposit should overflow");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::Overflow { tx: 2 })
+        );
+        let err = client
+            .deposit(3, Amount::new(2.0)?)
+            .expect_err("the deposit should overflow");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::Overflow { tx: 3 })
+        );
+        assert_eq!(*client.available_amount(), *near_max);
+        assert!(!client.contains_transaction(2) && !client.contains_transaction(3));
+        check_has_not_mutated_state(before, client)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_a_dispute_or_resolve_which_would_overflow_the_funds() -> Result<()> {
+        let mut client = Client::new(1).with_history();
+        let near_max = Amount::try_new(Decimal::MAX - Decimal::ONE)?;
+        client.process_transaction(1, TransactionType::Deposit, Some(near_max))?;
+        client.process_transaction(1, TransactionType::Dispute, None)?;
+        client.process_transaction(2, TransactionType::Deposit, Some(Amount::new(2.0)?))?;
+        let events = client.history().len();
+
+        // Holding the funds of the second deposit would overflow the held funds
+        let err = client
+            .project_balance(2, TransactionType::Dispute, None)
+            .map(|_| ())
+            .expect_err("the projected dispute should overflow");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::Overflow { tx: 2 })
+        );
+        let err = client
+            .process_transaction(2, TransactionType::Dispute, None)
+            .expect_err("the dispute should overflow");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::Overflow { tx: 2 })
+        );
+        // Releasing the held funds of the first deposit would overflow the available funds
+        let err = client
+            .process_transaction(1, TransactionType::Resolve, None)
+            .expect_err("the resolve should overflow");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::Overflow { tx: 1 })
+        );
+
+        assert_eq!(client.available_amount().to_exact_string(), "2.0000");
+        assert_eq!(*client.held_amount(), *near_max);
+        assert_eq!(client.history().len(), events);
+        assert!(matches!(
+            client.transaction_log.get(&1),
+            Some(Some(Transaction::Dispute { .. }))
+        ));
+        assert!(matches!(
+            client.transaction_log.get(&2),
+            Some(Some(Transaction::Deposit { .. }))
+        ));
+        Ok(())
+    }
+
     #[test]
     fn handles_a_deposit_with_a_duplicate_transaction_id() -> Result<()> {
         let before = client_with_state();
         let mut after = before.clone();
         let tx_id = 1;
         let tx_amt = 1.23f32;
-        after.process_transaction(tx_id, TransactionType::Deposit, Some(Amount::new(tx_amt)?))?;
+        process_rejected(&mut after, tx_id, &TransactionType::Deposit, tx_amt)?;
         check_has_not_mutated_state(before, after)?;
 
         Ok(())
@@ -503,8 +1772,11 @@ mod tests {
             "the transaction id should be present in the log"
         );
         assert!(
-            log_data.unwrap().is_none(),
-            "we don't need to store any data for withdrawals other than the transaction id"
+            matches!(
+                log_data.unwrap().as_ref(),
+                Some(Transaction::Withdrawal { .. })
+            ),
+            "the withdrawal amount should be stored in case it's disputed"
         );
         Ok(())
     }
@@ -520,15 +1792,259 @@ mod tests {
             Some(Amount::new(tx_amt)?),
         )?;
         let mut after = before.clone();
-        after.process_transaction(
-            tx_id,
-            TransactionType::Withdrawal,
-            Some(Amount::new(tx_amt)?),
-        )?;
+        process_rejected(&mut after, tx_id, &TransactionType::Withdrawal, tx_amt)?;
+        check_has_not_mutated_state(before, after)?;
+        Ok(())
+    }
+
+    #[test]
+    fn projects_a_withdrawal_without_mutating_state() -> Result<()> {
+        let before = client_with_state();
+        let after = before.clone();
+        let (available, held) =
+            after.project_balance(3, TransactionType::Withdrawal, Some(Amount::new(1.23f32)?))?;
+        assert_eq!(
+            *available,
+            *before.available_amount() - *Amount::new(1.23f32)?
+        );
+        assert_eq!(*held, *before.held_amount());
+        check_has_not_mutated_state(before, after)?;
+        Ok(())
+    }
+
+    #[test]
+    fn errors_when_projecting_a_withdrawal_with_insufficient_funds() -> Result<()> {
+        let before = client_with_state();
+        let after = before.clone();
+        let result =
+            after.project_balance(3, TransactionType::Withdrawal, Some(Amount::new(100.0f32)?));
+        assert!(
+            result.is_err(),
+            "the withdrawal would be rejected so has no projected balance"
+        );
         check_has_not_mutated_state(before, after)?;
         Ok(())
     }
 
+    #[test]
+    fn transfers_available_funds_to_another_client() -> Result<()> {
+        let before = client_with_state();
+        let mut source = before.clone();
+        let mut destination = Client::new(2);
+        source.transfer(&mut destination, 3, Amount::new(1.23f32)?)?;
+        assert_eq!(source.available_funds()?, before.available_funds()? - 1.23);
+        assert_eq!(source.held_funds()?, before.held_funds()?);
+        assert_eq!(destination.available_funds()?, 1.23);
+        assert!(
+            source.contains_transaction(3) && destination.contains_transaction(3),
+            "the transfer should be recorded by both clients"
+        );
+
+        // The id is now used by both clients, so repeating the transfer is rejected
+        let (source_before, destination_before) = (source.clone(), destination.clone());
+        let err = source
+            .transfer(&mut destination, 3, Amount::new(1.23f32)?)
+            .expect_err("the repeated transfer should be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::DuplicateTransaction { tx: 3 })
+        );
+        check_has_not_mutated_state(source_before, source)?;
+        check_has_not_mutated_state(destination_before, destination)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_a_transfer_with_insufficient_funds() -> Result<()> {
+        let before = client_with_state();
+        let mut source = before.clone();
+        let mut destination = Client::new(2);
+        let err = source
+            .transfer(&mut destination, 3, Amount::new(100.0f32)?)
+            .expect_err("the transfer should be rejected");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::InsufficientFunds { tx: 3 })
+        );
+        check_has_not_mutated_state(before, source)?;
+        assert!(
+            destination.is_empty(),
+            "no part of the transfer should have been applied"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_a_transfer_to_a_frozen_account() -> Result<()> {
+        let before = client_with_state();
+        let mut source = before.clone();
+        let mut destination = Client::new(2);
+        destination.status = AccountStatus::Frozen;
+        let err = source
+            .transfer(&mut destination, 3, Amount::new(1.23f32)?)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TransactionError>(),
+            Some(TransactionError::FrozenAccount { client: 2 })
+        ));
+        check_has_not_mutated_state(before, source)?;
+        assert_eq!(destination.available_funds()?, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn records_a_history_which_survives_a_chargeback() -> Result<()> {
+        let event = |tx, from, to, timestamp| TransactionEvent {
+            tx,
+            from,
+            to,
+            timestamp,
+        };
+        let mut client = Client::new(1).with_history();
+        client.process_transaction_at(
+            1,
+            TransactionType::Deposit,
+            Some(Amount::new(10.0)?),
+            Some(5),
+        )?;
+        // Rejected as there aren't enough available funds, so it isn't recorded
+        let _ =
+            client.process_transaction(2, TransactionType::Withdrawal, Some(Amount::new(50.0)?));
+        client.process_transaction(3, TransactionType::Withdrawal, Some(Amount::new(2.0)?))?;
+        client.process_transaction_at(1, TransactionType::Dispute, None, Some(9))?;
+        let _ = client.process_transaction(1, TransactionType::Chargeback, None);
+        assert!(client.is_locked());
+        assert_eq!(client.transaction_log.len(), 0);
+        assert_eq!(
+            client.history(),
+            [
+                event(1, None, TransactionType::Deposit, Some(5)),
+                event(3, None, TransactionType::Withdrawal, None),
+                event(
+                    1,
+                    Some(TransactionType::Deposit),
+                    TransactionType::Dispute,
+                    Some(9)
+                ),
+                event(
+                    1,
+                    Some(TransactionType::Dispute),
+                    TransactionType::Chargeback,
+                    None
+                ),
+            ]
+        );
+
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some(Amount::new(10.0)?))?;
+        assert!(client.history().is_empty(), "the history should be opt-in");
+        Ok(())
+    }
+
+    #[test]
+    fn restores_a_client_from_a_serialized_snapshot() -> Result<()> {
+        let mut client = client_after_withdrawing_a_deposit(DisputePolicy::AllowNegative)?;
+        client.process_transaction(3, TransactionType::Deposit, Some(Amount::new(4.0)?))?;
+        client.process_transaction(1, TransactionType::Dispute, None)?;
+        client.set_dispute_reason(1, "fraud".to_string());
+        let client = client.with_history();
+
+        let json = serde_json::to_string(&client.snapshot())?;
+        let snapshot: ClientSnapshot = serde_json::from_str(&json)?;
+        assert!(
+            snapshot == client.snapshot(),
+            "the snapshot should round trip"
+        );
+        let mut restored = Client::restore(snapshot);
+        assert_eq!(restored.available_amount().to_exact_string(), "-4.0000");
+        assert_eq!(restored.held_amount().to_exact_string(), "10.0000");
+        assert_eq!(restored.open_disputes(), vec![(1, Some("fraud"))]);
+
+        // The restored client carries on from where the original left off
+        let _ = restored.process_transaction(1, TransactionType::Chargeback, None);
+        assert!(restored.is_locked());
+        assert_eq!(restored.charged_back_transactions(), &[1]);
+        let restored = Client::restore(serde_json::from_str(&serde_json::to_string(
+            &restored.snapshot(),
+        )?)?);
+        assert!(restored.is_locked());
+        assert_eq!(restored.history().len(), 1);
+        Ok(())
+    }
+
+    /// A client which deposited 10 under transaction id 1 before withdrawing 8 of it
+    fn client_after_withdrawing_a_deposit(policy: DisputePolicy) -> Result<Client> {
+        let mut client = Client::new(1).with_dispute_policy(policy);
+        client.process_transaction(1, TransactionType::Deposit, Some(Amount::new(10.0)?))?;
+        client.process_transaction(2, TransactionType::Withdrawal, Some(Amount::new(8.0)?))?;
+        Ok(client)
+    }
+
+    #[test]
+    fn allows_a_dispute_to_overdraw_the_available_funds_by_default() -> Result<()> {
+        let mut client = client_after_withdrawing_a_deposit(DisputePolicy::default())?;
+        client.process_transaction(1, TransactionType::Dispute, None)?;
+        assert_eq!(client.available_amount().to_exact_string(), "-8.0000");
+        assert_eq!(client.held_amount().to_exact_string(), "10.0000");
+        assert!(client.has_open_disputes());
+        Ok(())
+    }
+
+    #[test]
+    fn counts_the_open_disputes_as_they_are_opened_and_settled() -> Result<()> {
+        let mut client = Client::new(1);
+        for tx in 1..=3 {
+            client.process_transaction(tx, TransactionType::Deposit, Some(Amount::new(1.0)?))?;
+        }
+        client.process_transaction(1, TransactionType::Dispute, None)?;
+        client.process_transaction(2, TransactionType::Dispute, None)?;
+        // Disputing a transaction which is already under dispute is rejected
+        process_rejected(&mut client, 2, &TransactionType::Dispute, 1.0)?;
+        assert_eq!(client.open_dispute_count(), 2);
+
+        client.process_transaction(1, TransactionType::Resolve, None)?;
+        assert_eq!(client.open_dispute_count(), 1);
+        assert_eq!(Client::restore(client.snapshot()).open_dispute_count(), 1);
+
+        let err = client
+            .process_transaction(2, TransactionType::Chargeback, None)
+            .expect_err("the chargeback should freeze the account");
+        assert_eq!(
+            err.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::FrozenAccount { client: 1 })
+        );
+        assert_eq!(client.open_dispute_count(), 0);
+        assert!(!client.has_open_disputes());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_a_dispute_which_would_overdraw_the_available_funds() -> Result<()> {
+        let before = client_after_withdrawing_a_deposit(DisputePolicy::RejectIfInsufficient)?;
+        let mut after = before.clone();
+        let err = after
+            .process_transaction(1, TransactionType::Dispute, None)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TransactionError>(),
+            Some(TransactionError::InsufficientFundsForDispute { tx: 1 })
+        ));
+        assert!(
+            after
+                .project_balance(1, TransactionType::Dispute, None)
+                .is_err(),
+            "the projection should also reject the dispute"
+        );
+        check_has_not_mutated_state(before, after.clone())?;
+
+        // Once the funds are available again the dispute is accepted
+        after.process_transaction(3, TransactionType::Deposit, Some(Amount::new(8.0)?))?;
+        after.process_transaction(1, TransactionType::Dispute, None)?;
+        assert_eq!(after.available_amount().to_exact_string(), "0.0000");
+        assert_eq!(after.held_amount().to_exact_string(), "10.0000");
+        Ok(())
+    }
+
     #[test]
     fn handles_a_dispute_on_a_deposit() -> Result<()> {
         let mut client = client_with_state();
@@ -556,18 +2072,21 @@ mod tests {
             log_data.unwrap().is_some(),
             "the nested transaction id should be some"
         );
-        let tx_type = matches!(log_data.unwrap().unwrap(), Transaction::Dispute { .. });
+        let tx_type = matches!(
+            log_data.unwrap().as_ref().unwrap(),
+            Transaction::Dispute { .. }
+        );
         assert!(tx_type, "the transaction should be of type dispute");
         Ok(())
     }
 
     #[test]
-    fn should_ignore_duplicate_dispute_requests() -> Result<()> {
+    fn should_reject_duplicate_dispute_requests() -> Result<()> {
         let mut before = client_with_state();
         let tx_id = 1;
         before.process_transaction(tx_id, TransactionType::Dispute, None)?;
         let mut after = before.clone();
-        after.process_transaction(tx_id, TransactionType::Dispute, None)?;
+        process_rejected(&mut after, tx_id, &TransactionType::Dispute, 0.0)?;
 
         check_has_not_mutated_state(before, after)?;
         Ok(())
@@ -600,12 +2119,12 @@ mod tests {
     }
 
     #[test]
-    fn should_ignore_duplicate_resolve_requests() -> Result<()> {
+    fn should_reject_duplicate_resolve_requests() -> Result<()> {
         let mut before = client_with_state();
         let tx_id = 2;
         before.process_transaction(tx_id, TransactionType::Resolve, None)?;
         let mut after = before.clone();
-        after.process_transaction(tx_id, TransactionType::Resolve, None)?;
+        process_rejected(&mut after, tx_id, &TransactionType::Resolve, 0.0)?;
 
         check_has_not_mutated_state(before, after)?;
         Ok(())
@@ -617,9 +2136,12 @@ mod tests {
         let tx_id = 2;
         let previous_available_funds = client.available_funds()?;
         let err = client.process_transaction(tx_id, TransactionType::Chargeback, None);
-        assert!(
-            err.is_err(),
-            "expected a failure from a publish transaction as the account is locked"
+        assert_eq!(
+            err.expect_err(
+                "expected a failure from a publish transaction as the account is locked"
+            )
+            .downcast_ref::<TransactionError>(),
+            Some(&TransactionError::FrozenAccount { client: 1 })
         );
         assert_eq!(
             client.available_funds()?,
@@ -642,6 +2164,126 @@ mod tests {
             AccountStatus::Frozen,
             "the client should be frozen when a chargeback occurs"
         );
+        assert_eq!(
+            client.charged_back_transactions(),
+            &[tx_id],
+            "the charged back transaction should be recorded"
+        );
+        Ok(())
+    }
+
+    /// A client holding a single 1.23 withdrawal under transaction id 3
+    fn client_with_withdrawal() -> Result<Client> {
+        let mut client = client_with_state();
+        client.process_transaction(3, TransactionType::Withdrawal, Some(Amount::new(1.23f32)?))?;
+        Ok(client)
+    }
+
+    #[test]
+    fn handles_a_dispute_on_a_withdrawal() -> Result<()> {
+        let mut client = client_with_withdrawal()?;
+        let tx_id = 3;
+        let previous_available_balance = client.available_funds()?;
+        let previous_held_balance = client.held_funds()?;
+        client.process_transaction(tx_id, TransactionType::Dispute, None)?;
+        assert_eq!(
+            client.available_funds()?,
+            previous_available_balance,
+            "available funds were already debited by the withdrawal"
+        );
+        assert!(
+            (client.held_funds()? - (previous_held_balance + 1.23)).abs() < ALLOWABLE_ERROR,
+            "the withdrawn funds should now be held"
+        );
+
+        let log_data = client.transaction_log.get(&tx_id);
+        assert!(
+            matches!(
+                log_data.unwrap().as_ref(),
+                Some(Transaction::Dispute {
+                    origin: TransactionType::Withdrawal,
+                    ..
+                })
+            ),
+            "the transaction should be a dispute of a withdrawal"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_duplicate_withdrawal_dispute_requests() -> Result<()> {
+        let mut before = client_with_withdrawal()?;
+        let tx_id = 3;
+        before.process_transaction(tx_id, TransactionType::Dispute, None)?;
+        let mut after = before.clone();
+        process_rejected(&mut after, tx_id, &TransactionType::Dispute, 0.0)?;
+
+        check_has_not_mutated_state(before, after)?;
+        Ok(())
+    }
+
+    #[test]
+    fn handles_a_resolve_on_a_disputed_withdrawal() -> Result<()> {
+        let mut client = client_with_withdrawal()?;
+        let tx_id = 3;
+        let previous_available_funds = client.available_funds()?;
+        let previous_held_balance = client.held_funds()?;
+        client.process_transaction(tx_id, TransactionType::Dispute, None)?;
+        client.process_transaction(tx_id, TransactionType::Resolve, None)?;
+        assert_eq!(
+            client.available_funds()?,
+            previous_available_funds,
+            "the withdrawal stands, so available funds should be unchanged"
+        );
+        assert_eq!(
+            client.held_funds()?,
+            previous_held_balance,
+            "held funds should be released"
+        );
+
+        let log_data = client.transaction_log.get(&tx_id);
+        assert!(
+            log_data.is_some(),
+            "the transaction id should still be present in the log"
+        );
+        assert!(
+            log_data.unwrap().is_none(),
+            "we no longer need to hold transaction data"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn handles_a_chargeback_on_a_disputed_withdrawal() -> Result<()> {
+        let mut client = client_with_withdrawal()?;
+        let tx_id = 3;
+        let previous_available_funds = client.available_funds()?;
+        let previous_held_balance = client.held_funds()?;
+        client.process_transaction(tx_id, TransactionType::Dispute, None)?;
+        let err = client.process_transaction(tx_id, TransactionType::Chargeback, None);
+        assert!(
+            err.is_err(),
+            "expected a failure from a publish transaction as the account is locked"
+        );
+        assert!(
+            (client.available_funds()? - (previous_available_funds + 1.23)).abs() < ALLOWABLE_ERROR,
+            "the withdrawn funds should be returned when a chargeback occurs"
+        );
+        assert_eq!(
+            client.held_funds()?,
+            previous_held_balance,
+            "held funds should be released when a chargeback occurs"
+        );
+        assert_eq!(
+            client.status,
+            AccountStatus::Frozen,
+            "the client should be frozen when a chargeback occurs"
+        );
+        assert_eq!(
+            client.charged_back_transactions(),
+            &[tx_id],
+            "the charged back transaction should be recorded"
+        );
         Ok(())
     }
 
@@ -657,12 +2299,109 @@ mod tests {
             TransactionType::Chargeback,
         ] {
             let mut after = before.clone();
-            after.process_transaction(tx_id, *transition, Some(Amount::new(tx_amt)?))?;
+            process_rejected(&mut after, tx_id, transition, tx_amt)?;
             check_has_not_mutated_state(before.clone(), after)?;
         }
         Ok(())
     }
 
+    /// Processes a transaction which isn't a valid transition for the client, asserting that
+    /// it's rejected with [`TransactionError::IdTypeConflict`] if it's a deposit or withdrawal
+    /// reusing the id of the other type, otherwise with
+    /// [`TransactionError::DuplicateTransaction`] or [`TransactionError::InvalidTransition`]
+    fn process_rejected(
+        client: &mut Client,
+        tx_id: u32,
+        transition: &TransactionType,
+        tx_amt: f32,
+    ) -> Result<()> {
+        let existing = client
+            .transaction_log
+            .get(&tx_id)
+            .and_then(|trx| trx.as_ref().map(Transaction::origin));
+        let error = client
+            .process_transaction(tx_id, transition.clone(), Some(Amount::new(tx_amt)?))
+            .expect_err("expected the transaction to be rejected");
+        let expected = match (transition, existing) {
+            (TransactionType::Deposit | TransactionType::Withdrawal, Some(existing))
+                if existing != *transition =>
+            {
+                TransactionError::IdTypeConflict {
+                    tx: tx_id,
+                    existing,
+                    attempted: transition.clone(),
+                }
+            }
+            (TransactionType::Deposit | TransactionType::Withdrawal, _) => {
+                TransactionError::DuplicateTransaction { tx: tx_id }
+            }
+            _ => TransactionError::InvalidTransition {
+                tx: tx_id,
+                attempted: transition.clone(),
+            },
+        };
+        assert_eq!(error.downcast_ref::<TransactionError>(), Some(&expected));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_a_withdrawal_reusing_the_id_of_a_deposit() -> Result<()> {
+        let mut client = Client::new(1);
+        client.process_transaction(1, TransactionType::Deposit, Some(Amount::new(5.0)?))?;
+        let error = client
+            .process_transaction(1, TransactionType::Withdrawal, Some(Amount::new(2.0)?))
+            .expect_err("expected the id to conflict");
+        let error = error
+            .downcast_ref::<TransactionError>()
+            .expect("a transaction error");
+        assert_eq!(
+            error,
+            &TransactionError::IdTypeConflict {
+                tx: 1,
+                existing: TransactionType::Deposit,
+                attempted: TransactionType::Withdrawal,
+            }
+        );
+        assert!(error.is_recoverable());
+        assert_eq!(*client.available_amount(), *Amount::new(5.0)?);
+
+        // Reusing the id with the same type is a duplicate
+        process_rejected(&mut client, 1, &TransactionType::Deposit, 5.0)?;
+        assert_eq!(*client.available_amount(), *Amount::new(5.0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_disputes_outside_of_the_dispute_window() -> Result<()> {
+        const DAY: u64 = 24 * 60 * 60;
+        let mut client = Client::new(1).with_dispute_window(Duration::from_secs(30 * DAY));
+        let amount = Some(Amount::new(5.0)?);
+        client.process_transaction_at(1, TransactionType::Deposit, amount, Some(0))?;
+        client.process_transaction_at(2, TransactionType::Deposit, amount, Some(0))?;
+
+        client.process_transaction_at(1, TransactionType::Dispute, None, Some(30 * DAY))?;
+        assert_eq!(*client.held_amount(), *Amount::new(5.0)?);
+
+        let error = client
+            .process_transaction_at(2, TransactionType::Dispute, None, Some(31 * DAY))
+            .expect_err("the dispute window should have expired");
+        assert_eq!(
+            error.downcast_ref::<TransactionError>(),
+            Some(&TransactionError::DisputeWindowExpired {
+                tx: 2,
+                deposited_at: 0,
+                disputed_at: 31 * DAY,
+            })
+        );
+        assert_eq!(*client.held_amount(), *Amount::new(5.0)?);
+        assert_eq!(*client.available_amount(), *Amount::new(5.0)?);
+
+        // Without a timestamp the window can't be enforced
+        client.process_transaction(2, TransactionType::Dispute, None)?;
+        assert_eq!(*client.held_amount(), *Amount::new(10.0)?);
+        Ok(())
+    }
+
     #[test]
     fn handles_illegal_transitions_from_withdrawal() -> Result<()> {
         let mut before = client_with_state();
@@ -676,12 +2415,11 @@ mod tests {
 
         for transition in &[
             TransactionType::Deposit,
-            TransactionType::Dispute,
             TransactionType::Resolve,
             TransactionType::Chargeback,
         ] {
             let mut after = before.clone();
-            after.process_transaction(tx_id, *transition, Some(Amount::new(tx_amt)?))?;
+            process_rejected(&mut after, tx_id, transition, tx_amt)?;
             check_has_not_mutated_state(before.clone(), after)?;
         }
         Ok(())
@@ -695,7 +2433,7 @@ mod tests {
 
         for transition in &[TransactionType::Deposit, TransactionType::Withdrawal] {
             let mut after = before.clone();
-            after.process_transaction(tx_id, *transition, Some(Amount::new(tx_amt)?))?;
+            process_rejected(&mut after, tx_id, transition, tx_amt)?;
             check_has_not_mutated_state(before.clone(), after)?;
         }
         Ok(())
@@ -715,7 +2453,7 @@ mod tests {
             TransactionType::Chargeback,
         ] {
             let mut after = before.clone();
-            after.process_transaction(tx_id, *transition, Some(Amount::new(tx_amt)?))?;
+            process_rejected(&mut after, tx_id, transition, tx_amt)?;
             check_has_not_mutated_state(before.clone(), after)?;
         }
         Ok(())
@@ -725,14 +2463,33 @@ mod tests {
         let mut log: FnvHashMap<u32, Option<Transaction>> = Default::default();
         let available = Amount::new(20.32f32).unwrap();
         let held = Amount::new(3.14923f32).unwrap();
-        log.insert(1, Some(Transaction::Deposit { amount: available }));
-        log.insert(2, Some(Transaction::Dispute { amount: held }));
+        log.insert(
+            1,
+            Some(Transaction::Deposit {
+                amount: available,
+                timestamp: None,
+            }),
+        );
+        log.insert(
+            2,
+            Some(Transaction::Dispute {
+                amount: held,
+                reason: None,
+                origin: TransactionType::Deposit,
+            }),
+        );
         Client {
             id: 1,
             available,
             held,
             status: AccountStatus::Active,
             transaction_log: log,
+            open_disputes: 1,
+            charged_back: Vec::new(),
+            reconciliations: Vec::new(),
+            dispute_window: None,
+            dispute_policy: DisputePolicy::default(),
+            history: None,
         }
     }
 
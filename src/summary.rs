@@ -0,0 +1,124 @@
+//! Helpers related to writing the human readable summaries _(eg. `--book-totals`)_ which are
+//! printed alongside the csv output
+//!
+//! These summaries are colorized when they are written to a terminal, this never applies to the
+//! csv output itself.
+
+use std::io::{self, Write};
+
+/// The ANSI escape code for bold text
+const BOLD: &str = "\x1b[1m";
+/// The ANSI escape code for green text
+const GREEN: &str = "\x1b[32m";
+/// The ANSI escape code resetting any styling
+const RESET: &str = "\x1b[0m";
+
+/// Whether the human readable summaries should be colorized
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Colorizes the summaries only if they are written to a terminal
+    #[default]
+    Auto,
+    /// Always colorizes the summaries
+    Always,
+    /// Never colorizes the summaries
+    Never,
+}
+
+impl ColorChoice {
+    /// Resolves whether a summary should be colorized, given whether it is being written to a
+    /// terminal
+    pub fn should_colorize(self, is_terminal: bool) -> bool {
+        match self {
+            Self::Auto => is_terminal,
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// Writes each `(label, value)` entry of a summary on its own line, with the values aligned
+///
+/// ```
+/// use lib::summary::write_summary;
+///
+/// let mut result = vec![];
+/// write_summary(&mut result, &[("available", "1.0000"), ("held", "0.5000")], false).unwrap();
+/// assert_eq!(
+///     String::from_utf8(result).unwrap(),
+///     "available: 1.0000\nheld:      0.5000\n"
+/// );
+/// ```
+pub fn write_summary(
+    mut writer: impl Write,
+    entries: &[(&str, &str)],
+    colorize: bool,
+) -> io::Result<()> {
+    let width = entries
+        .iter()
+        .map(|(label, _)| label.len() + 1)
+        .max()
+        .unwrap_or_default();
+    for (label, value) in entries {
+        let label = format!("{}:", label);
+        if colorize {
+            writeln!(
+                writer,
+                "{}{:<width$}{} {}{}{}",
+                BOLD,
+                label,
+                RESET,
+                GREEN,
+                value,
+                RESET,
+                width = width
+            )?;
+        } else {
+            writeln!(writer, "{:<width$} {}", label, value, width = width)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use color_eyre::Result;
+    use pretty_assertions::assert_eq;
+
+    const ENTRIES: &[(&str, &str)] = &[("total available", "10.0000"), ("total held", "2.5000")];
+
+    #[test]
+    fn writes_plain_text_when_not_a_terminal() -> Result<()> {
+        assert!(!ColorChoice::Auto.should_colorize(false));
+        assert!(!ColorChoice::Never.should_colorize(true));
+
+        let mut result = vec![];
+        write_summary(
+            &mut result,
+            ENTRIES,
+            ColorChoice::Auto.should_colorize(false),
+        )?;
+        let result = String::from_utf8(result)?;
+        assert!(!result.contains('\x1b'), "expected no escape codes");
+        assert_eq!(
+            result,
+            "total available: 10.0000\ntotal held:      2.5000\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn colorizes_when_requested() -> Result<()> {
+        assert!(ColorChoice::Auto.should_colorize(true));
+        assert!(ColorChoice::Always.should_colorize(false));
+
+        let mut result = vec![];
+        write_summary(&mut result, ENTRIES, true)?;
+        let result = String::from_utf8(result)?;
+        assert!(result.starts_with(BOLD));
+        assert!(result.contains(&format!("{}10.0000{}", GREEN, RESET)));
+        Ok(())
+    }
+}
@@ -2,12 +2,20 @@
 //! transitions
 
 use color_eyre::{eyre::eyre, Result};
-use serde::{Deserialize, Serialize};
+use fnv::FnvHashMap;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{
+    de::{Error, Visitor},
+    Deserialize, Serialize,
+};
 use tracing::warn;
 
+use std::collections::VecDeque;
 use std::fmt;
+use std::sync::Arc;
 
 use crate::amount::Amount;
+use crate::storage::Client;
 
 /// The format of the expected input data
 #[derive(Deserialize, Serialize)]
@@ -17,6 +25,23 @@ pub struct IncomingTransaction {
     pub client: u16,
     pub tx: u32,
     pub amount: Option<Amount>,
+    /// When the transaction occurred in seconds since the unix epoch, this is an optional column
+    /// in the input data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// An id assigned to the row by an external system, this doesn't affect processing however
+    /// it is included in any error and audit trail relating to the transaction
+    /// _(see [`InputOptions::row_id`](crate::input::InputOptions::row_id))_
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row_id: Option<String>,
+    /// The reason given for a dispute _(eg. `fraud`)_, this is metadata which doesn't affect
+    /// processing and is ignored for every other type of transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The client receiving the funds of a [`TransactionType::Transfer`], this is an optional
+    /// column in the input data which is ignored for every other type of transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest: Option<u16>,
 }
 
 impl fmt::Debug for IncomingTransaction {
@@ -25,32 +50,243 @@ impl fmt::Debug for IncomingTransaction {
             .field("type", &self.ty)
             .field("client", &self.client)
             .field("tx", &self.tx)
+            .field("timestamp", &self.timestamp)
+            .field("row_id", &self.row_id)
+            .field("reason", &self.reason)
+            .field("dest", &self.dest)
             .finish()
     }
 }
 
+/// Reorders the transactions across clients, while preserving the relative order of each
+/// individual client's transactions.
+///
+/// The same `seed` will always produce the same ordering. This is primarily intended for
+/// testing, as any correct engine should produce identical results regardless of how the
+/// transactions of different clients are interleaved.
+pub fn shuffle(transactions: Vec<IncomingTransaction>, seed: u64) -> Vec<IncomingTransaction> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut order = Vec::with_capacity(transactions.len());
+    let mut queues: FnvHashMap<u16, VecDeque<IncomingTransaction>> = Default::default();
+    for trx in transactions {
+        order.push(trx.client);
+        queues.entry(trx.client).or_default().push_back(trx);
+    }
+    order.shuffle(&mut rng);
+    order
+        .into_iter()
+        .filter_map(|client| queues.get_mut(&client).and_then(VecDeque::pop_front))
+        .collect()
+}
+
 /// The types of transaction that can occur
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+///
+/// Any `type` value which isn't one of the built-in types is deserialized into
+/// [`TransactionType::Custom`], allowing it to be dispatched to a registered
+/// [`CustomTransaction`] handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves the `amount` from the available funds of the `client` into the available funds of
+    /// the `dest` client _(eg. `transfer,1,7,2.5,2`)_
+    ///
+    /// A transfer either moves the whole amount or nothing at all, see [`Client::transfer`].
+    /// As it changes two clients at once, it is only applied by
+    /// [`BasicEngine`](crate::engines::BasicEngine), every other engine rejects it.
+    Transfer,
+    /// A keep-alive row from a streaming feed, which is accepted and ignored by every engine
+    /// without creating the client
+    ///
+    /// The row must still be well formed, so the `client` and `tx` columns are required _(eg.
+    /// `heartbeat,0,0,`)_, however their values are ignored.
+    Heartbeat,
+    /// A domain specific transaction type that isn't natively understood by the engine
+    ///
+    /// These are only processed by [`BasicEngine`](crate::engines::BasicEngine), through the
+    /// handler registered for the name. Without a handler _(eg. a typo of a native type, or any
+    /// custom type published to another engine)_ the transaction is skipped as a
+    /// [`TransactionError::UnknownTransactionType`](crate::error::TransactionError::UnknownTransactionType).
+    Custom(String),
+}
+
+impl TransactionType {
+    /// The name of the transaction type as it appears in the input data
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Transfer => "transfer",
+            Self::Heartbeat => "heartbeat",
+            Self::Custom(name) => name,
+        }
+    }
+
+    /// Looks up one of the natively understood transaction types by its name
+    fn native(name: &str) -> Option<Self> {
+        let ty = match name {
+            "deposit" => Self::Deposit,
+            "withdrawal" => Self::Withdrawal,
+            "dispute" => Self::Dispute,
+            "resolve" => Self::Resolve,
+            "chargeback" => Self::Chargeback,
+            "transfer" => Self::Transfer,
+            "heartbeat" => Self::Heartbeat,
+            _ => return None,
+        };
+        Some(ty)
+    }
+}
+
+impl Serialize for TransactionType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Deserializes a [`TransactionType`] from the borrowed name where possible, so only a
+/// [`TransactionType::Custom`] allocates
+struct TransactionTypeVisitor;
+
+impl<'de> Visitor<'de> for TransactionTypeVisitor {
+    type Value = TransactionType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a non-empty transaction type")
+    }
+
+    fn visit_str<E: Error>(self, name: &str) -> Result<Self::Value, E> {
+        match name {
+            "" => Err(E::custom("expected a non-empty transaction type")),
+            _ => Ok(TransactionType::native(name)
+                .unwrap_or_else(|| TransactionType::Custom(name.to_string()))),
+        }
+    }
+
+    fn visit_string<E: Error>(self, name: String) -> Result<Self::Value, E> {
+        match TransactionType::native(&name) {
+            Some(ty) => Ok(ty),
+            None if name.is_empty() => Err(E::custom("expected a non-empty transaction type")),
+            None => Ok(TransactionType::Custom(name)),
+        }
+    }
 }
 
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(TransactionTypeVisitor)
+    }
+}
+
+/// A hook allowing domain specific transaction types _(eg. `Interest` or `Fee`)_ to be
+/// processed without them being natively supported by the engine.
+///
+/// Handlers are registered against the `type` value they should handle, see
+/// [`BasicEngine::register_transaction_type`]. Any incoming row with a matching `type` is
+/// then dispatched to the handler rather than [`ClientStorage::process_transaction`].
+///
+/// ## Examples
+///
+/// ```
+/// use color_eyre::Result;
+/// use lib::storage::{Client, ClientStorage};
+/// use lib::transaction::CustomTransaction;
+/// use lib::Amount;
+///
+/// /// Deducts the amount of the row from the client's available funds
+/// struct Fee;
+///
+/// impl CustomTransaction for Fee {
+///     fn apply(&self, client: &mut Client, transaction_id: u32, amount: Option<Amount>) -> Result<()> {
+///         if let Some(amount) = amount {
+///             client.withdraw(transaction_id, amount)?;
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// [`BasicEngine::register_transaction_type`]: crate::engines::BasicEngine::register_transaction_type
+/// [`ClientStorage::process_transaction`]: crate::storage::ClientStorage::process_transaction
+pub trait CustomTransaction {
+    /// Applies the transaction to the client
+    ///
+    /// This is only ever called for clients whose accounts are not locked. Similarly to
+    /// [`ClientStorage::process_transaction`], an error returned from this function indicates that
+    /// processing should stop.
+    ///
+    /// [`ClientStorage::process_transaction`]: crate::storage::ClientStorage::process_transaction
+    fn apply(&self, client: &mut Client, transaction_id: u32, amount: Option<Amount>)
+        -> Result<()>;
+}
+
+/// The registered [`CustomTransaction`] handlers, keyed by the `type` value they handle
+pub type CustomTransactionHandlers = FnvHashMap<String, Arc<dyn CustomTransaction + Send + Sync>>;
+
 #[allow(dead_code)]
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) enum Transaction {
-    Deposit { amount: Amount },
-    Withdrawal { amount: Amount },
-    Dispute { amount: Amount },
-    Resolve { amount: Amount },
-    Chargeback { amount: Amount },
+    Deposit {
+        amount: Amount,
+        timestamp: Option<u64>,
+    },
+    Withdrawal {
+        amount: Amount,
+    },
+    Dispute {
+        amount: Amount,
+        reason: Option<String>,
+        /// The type of the disputed transaction, ie. either a deposit or a withdrawal
+        origin: TransactionType,
+    },
+    Resolve {
+        amount: Amount,
+        /// The type of the resolved transaction, ie. either a deposit or a withdrawal
+        origin: TransactionType,
+    },
+    Chargeback {
+        amount: Amount,
+        /// The type of the charged back transaction, ie. either a deposit or a withdrawal
+        origin: TransactionType,
+    },
 }
 
 impl Transaction {
+    /// The type of the transaction which originally created this entry, ie. either a deposit or
+    /// a withdrawal
+    pub fn origin(&self) -> TransactionType {
+        match self {
+            Self::Deposit { .. } => TransactionType::Deposit,
+            Self::Withdrawal { .. } => TransactionType::Withdrawal,
+            Self::Dispute { origin, .. }
+            | Self::Resolve { origin, .. }
+            | Self::Chargeback { origin, .. } => origin.clone(),
+        }
+    }
+
+    /// The state the transaction is currently in
+    pub fn state(&self) -> TransactionType {
+        match self {
+            Self::Deposit { .. } => TransactionType::Deposit,
+            Self::Withdrawal { .. } => TransactionType::Withdrawal,
+            Self::Dispute { .. } => TransactionType::Dispute,
+            Self::Resolve { .. } => TransactionType::Resolve,
+            Self::Chargeback { .. } => TransactionType::Chargeback,
+        }
+    }
+
     /// Drives a transition from one transaction type to the next.
     ///
     /// This function will error if the attempted transition is invalid.
@@ -59,14 +295,25 @@ impl Transaction {
     /// function will error.
     pub fn transition(self, target: TransactionType) -> Result<Transaction> {
         let resp = match (self, target) {
-            (Transaction::Deposit { amount }, TransactionType::Dispute) => {
-                Transaction::Dispute { amount }
+            (Transaction::Deposit { amount, .. }, TransactionType::Dispute) => {
+                Transaction::Dispute {
+                    amount,
+                    reason: None,
+                    origin: TransactionType::Deposit,
+                }
             }
-            (Transaction::Dispute { amount }, TransactionType::Resolve) => {
-                Transaction::Resolve { amount }
+            (Transaction::Withdrawal { amount }, TransactionType::Dispute) => {
+                Transaction::Dispute {
+                    amount,
+                    reason: None,
+                    origin: TransactionType::Withdrawal,
+                }
             }
-            (Transaction::Dispute { amount }, TransactionType::Chargeback) => {
-                Transaction::Chargeback { amount }
+            (Transaction::Dispute { amount, origin, .. }, TransactionType::Resolve) => {
+                Transaction::Resolve { amount, origin }
+            }
+            (Transaction::Dispute { amount, origin, .. }, TransactionType::Chargeback) => {
+                Transaction::Chargeback { amount, origin }
             }
             (lhs, rhs) => {
                 let msg = format!("Invalid State Transition attempt. Attempted to transition from [{:?}] -> [{:?}]", lhs, rhs);
@@ -90,3 +337,72 @@ impl fmt::Debug for Transaction {
         write!(f, "{}", s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    fn transactions() -> Vec<IncomingTransaction> {
+        (0..60)
+            .map(|tx| IncomingTransaction {
+                ty: TransactionType::Deposit,
+                client: (tx % 3) as u16,
+                tx,
+                amount: None,
+                timestamp: None,
+                row_id: None,
+                reason: None,
+                dest: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_origin_of_a_disputed_withdrawal_once_it_is_settled() -> Result<()> {
+        for target in [TransactionType::Resolve, TransactionType::Chargeback] {
+            let withdrawal = Transaction::Withdrawal {
+                amount: Amount::new(1.0)?,
+            };
+            let settled = withdrawal
+                .transition(TransactionType::Dispute)?
+                .transition(target.clone())?;
+            assert_eq!(settled.state(), target);
+            assert_eq!(settled.origin(), TransactionType::Withdrawal);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shuffle_preserves_the_order_of_each_client() {
+        let shuffled = shuffle(transactions(), 42);
+        assert_eq!(shuffled.len(), 60);
+        for client in 0..3 {
+            let ids = shuffled
+                .iter()
+                .filter(|t| t.client == client)
+                .map(|t| t.tx)
+                .collect::<Vec<_>>();
+            let expected = (0..60)
+                .filter(|tx| tx % 3 == client as u32)
+                .collect::<Vec<_>>();
+            assert_eq!(ids, expected, "client {} should keep its order", client);
+        }
+    }
+
+    #[test]
+    fn shuffle_reorders_across_clients_deterministically() {
+        let original = transactions().iter().map(|t| t.tx).collect::<Vec<_>>();
+        let lhs = shuffle(transactions(), 42)
+            .iter()
+            .map(|t| t.tx)
+            .collect::<Vec<_>>();
+        let rhs = shuffle(transactions(), 42)
+            .iter()
+            .map(|t| t.tx)
+            .collect::<Vec<_>>();
+        assert_eq!(lhs, rhs, "the same seed should produce the same order");
+        assert_ne!(lhs, original, "the transactions should have been reordered");
+    }
+}
@@ -0,0 +1,225 @@
+//! Replays an input file through the [`BasicEngine`] and verifies the result matches an expected
+//! output file
+//!
+//! ```
+//! use lib::verify::verify;
+//! use std::path::Path;
+//!
+//! let report = verify(
+//!     Path::new("./test_assets/simple/spec.csv"),
+//!     Path::new("./test_assets/simple/expected.csv"),
+//! )
+//! .unwrap();
+//! assert!(report.is_match());
+//! ```
+
+use color_eyre::Result;
+use csv::{ReaderBuilder, StringRecord, Trim};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+use crate::engines::BasicEngine;
+use crate::input::InputOptions;
+use crate::{transactions_from_path, Amount, SyncEngine};
+
+/// The result of [`verify`], listing every difference between the expected and actual output
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Every difference found, ordered by client id
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerifyReport {
+    /// Identifies whether the actual output exactly matched the expected output
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// A single difference between the expected and actual output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The client was expected, however it wasn't in the output
+    MissingClient(u16),
+    /// The client was in the output, however it wasn't expected
+    UnexpectedClient(u16),
+    /// A field of the client differed from what was expected
+    Field {
+        /// The id of the client
+        client: u16,
+        /// The name of the column which differed
+        field: String,
+        /// The expected value
+        expected: String,
+        /// The value which was actually output, if the column was output at all
+        actual: Option<String>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingClient(client) => write!(f, "client {}: missing from the output", client),
+            Self::UnexpectedClient(client) => {
+                write!(f, "client {}: not in the expected output", client)
+            }
+            Self::Field {
+                client,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {}: expected {} to be {:?}, found {:?}",
+                client,
+                field,
+                expected,
+                actual.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// Processes the input at the provided path with a [`BasicEngine`] and compares the output
+/// against the expected csv, see [`verify_with_options`]
+pub fn verify(input: &Path, expected: &Path) -> Result<VerifyReport> {
+    verify_with_options(input, expected, &InputOptions::default())
+}
+
+/// The same as [`verify`], reading the input according to the provided [`InputOptions`]
+///
+/// Only the columns present in the expected csv are compared, and amounts are compared by value
+/// so `1.5` matches `1.5000`.
+pub fn verify_with_options(
+    input: &Path,
+    expected: &Path,
+    options: &InputOptions,
+) -> Result<VerifyReport> {
+    let mut engine = BasicEngine::default();
+    engine.process(transactions_from_path(input, options)?)?;
+    let mut actual = vec![];
+    engine.output(&mut actual)?;
+
+    let expected = read_clients(ReaderBuilder::new().trim(Trim::All).from_path(expected)?)?;
+    let actual = read_clients(ReaderBuilder::new().from_reader(actual.as_slice()))?;
+
+    let mut mismatches = vec![];
+    for (client, expected_row) in &expected {
+        let actual_row = match actual.get(client) {
+            Some(row) => row,
+            None => {
+                mismatches.push(Mismatch::MissingClient(*client));
+                continue;
+            }
+        };
+        for (field, expected_value) in expected_row {
+            let actual_value = actual_row
+                .iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, value)| value);
+            if !values_match(expected_value, actual_value.map(String::as_str)) {
+                mismatches.push(Mismatch::Field {
+                    client: *client,
+                    field: field.clone(),
+                    expected: expected_value.clone(),
+                    actual: actual_value.cloned(),
+                });
+            }
+        }
+    }
+    mismatches.extend(
+        actual
+            .keys()
+            .filter(|client| !expected.contains_key(client))
+            .map(|client| Mismatch::UnexpectedClient(*client)),
+    );
+    mismatches.sort_by_key(|m| match m {
+        Mismatch::MissingClient(client)
+        | Mismatch::UnexpectedClient(client)
+        | Mismatch::Field { client, .. } => *client,
+    });
+    Ok(VerifyReport { mismatches })
+}
+
+/// Reads every row of the csv keyed by the client id, with each row holding the
+/// `(column name, value)` of each of its fields in order
+fn read_clients<R: Read>(
+    mut reader: csv::Reader<R>,
+) -> Result<BTreeMap<u16, Vec<(String, String)>>> {
+    let headers = reader.headers()?.clone();
+    let mut clients = BTreeMap::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, value)| (header.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        let client = row
+            .iter()
+            .find(|(name, _)| name == "client")
+            .map(|(_, value)| value)
+            .ok_or_else(|| color_eyre::eyre::eyre!("expected a client column"))?
+            .parse::<u16>()?;
+        clients.insert(client, row);
+    }
+    Ok(clients)
+}
+
+fn values_match(expected: &str, actual: Option<&str>) -> bool {
+    let actual = match actual {
+        Some(actual) => actual,
+        None => return false,
+    };
+    match (expected.parse::<Amount>(), actual.parse::<Amount>()) {
+        (Ok(expected), Ok(actual)) => expected == actual,
+        _ => expected == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reports_a_match() -> Result<()> {
+        let report = verify(
+            Path::new("./test_assets/simple/spec.csv"),
+            Path::new("./test_assets/simple/expected.csv"),
+        )?;
+        assert_eq!(report, VerifyReport::default());
+        assert!(report.is_match());
+        Ok(())
+    }
+
+    #[test]
+    fn reports_each_mismatch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let expected = dir.path().join("expected.csv");
+        std::fs::write(
+            &expected,
+            "client, available, held, total, locked\n1, 1.875, 0.0000, 1.8750, true\n3, 0.0000, 0.0000, 0.0000, false\n",
+        )?;
+        let report = verify(Path::new("./test_assets/simple/spec.csv"), &expected)?;
+        assert!(!report.is_match());
+        assert_eq!(
+            report.mismatches,
+            vec![
+                Mismatch::Field {
+                    client: 1,
+                    field: "locked".to_string(),
+                    expected: "true".to_string(),
+                    actual: Some("false".to_string()),
+                },
+                Mismatch::UnexpectedClient(2),
+                Mismatch::MissingClient(3),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! Exercises the public API available under each feature, these tests are intended to be run with
+//! a range of feature sets _(eg. `cargo test --no-default-features --features basic_engine`)_ in
+//! order to catch any feature gating regressions.
+
+use color_eyre::Result;
+
+use lib::input::{self, InputOptions};
+use lib::storage::{Client, ClientStorage};
+use lib::transaction::TransactionType;
+use lib::Amount;
+
+const SPEC: &str = "./test_assets/simple/spec.csv";
+
+#[test]
+fn core_api_is_available_without_any_features() -> Result<()> {
+    let options = InputOptions::default();
+    let reader = options.reader(std::fs::File::open(SPEC)?);
+    let mut clients = std::collections::BTreeMap::new();
+    for trx in input::deserialize(reader, &options)? {
+        let trx = trx?;
+        clients
+            .entry(trx.client)
+            .or_insert_with(|| Client::new(trx.client))
+            .process_transaction(trx.tx, trx.ty, trx.amount)?;
+    }
+    assert_eq!(clients[&1].available_amount().to_exact_string(), "1.8750");
+
+    let mut client = Client::new(1);
+    client.process_transaction(1, TransactionType::Deposit, Some("1.5".parse::<Amount>()?))?;
+    assert!(!client.is_locked());
+    Ok(())
+}
+
+#[cfg(feature = "basic_engine")]
+#[test]
+fn basic_engine_is_usable_on_its_own() -> Result<()> {
+    use csv::{ReaderBuilder, Trim};
+    use lib::engines::BasicEngine;
+    use lib::transaction::IncomingTransaction;
+    use lib::SyncEngine;
+
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(SPEC)?;
+    let mut engine = BasicEngine::default();
+    engine.process(reader.deserialize::<IncomingTransaction>())?;
+    let mut result = vec![];
+    engine.output(&mut result)?;
+    assert_eq!(csv::Reader::from_reader(&*result).records().count(), 2);
+    Ok(())
+}
+
+#[cfg(feature = "stream_engine")]
+#[test]
+fn stream_engine_is_usable_on_its_own() -> Result<()> {
+    use csv::{ReaderBuilder, Trim};
+    use lib::engines::StreamLikeEngine;
+    use lib::transaction::IncomingTransaction;
+    use lib::SyncEngine;
+
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(SPEC)?;
+    let mut engine = StreamLikeEngine::default();
+    engine.process(reader.deserialize::<IncomingTransaction>())?;
+    let mut result = vec![];
+    engine.output(&mut result)?;
+    assert_eq!(csv::Reader::from_reader(&*result).records().count(), 2);
+    Ok(())
+}
+
+#[cfg(feature = "rayon_engine")]
+#[test]
+fn rayon_engine_is_usable_on_its_own() -> Result<()> {
+    use csv::{ReaderBuilder, Trim};
+    use lib::engines::RayonLikeEngine;
+    use lib::transaction::IncomingTransaction;
+    use lib::SyncEngine;
+
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(SPEC)?;
+    let mut engine = RayonLikeEngine::default();
+    engine.process(reader.deserialize::<IncomingTransaction>())?;
+    let mut result = vec![];
+    engine.output(&mut result)?;
+    assert_eq!(csv::Reader::from_reader(&*result).records().count(), 2);
+    Ok(())
+}
+
+#[cfg(feature = "actor_engine")]
+#[tokio::test]
+async fn actor_engine_is_usable_on_its_own() -> Result<()> {
+    use csv::{ReaderBuilder, Trim};
+    use lib::engines::ActorLikeEngine;
+    use lib::transaction::IncomingTransaction;
+    use lib::AsyncEngine;
+
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(SPEC)?;
+    let mut engine = ActorLikeEngine::default();
+    engine
+        .process(reader.deserialize::<IncomingTransaction>())
+        .await?;
+    let mut result = vec![];
+    engine.output(&mut result).await?;
+    assert_eq!(csv::Reader::from_reader(&*result).records().count(), 2);
+    Ok(())
+}
@@ -1,10 +1,22 @@
-use csv::{ReaderBuilder, Trim};
+use color_eyre::Report;
+use csv::{ReaderBuilder, StringRecord, Trim};
 use paste::paste;
 use pretty_assertions::assert_eq;
 
+#[cfg(feature = "rayon_engine")]
+use lib::engines::RayonLikeEngine;
 use lib::engines::{ActorLikeEngine, BasicEngine, StreamLikeEngine};
-use lib::transaction::IncomingTransaction;
-use lib::{AsyncEngine, SyncEngine};
+use lib::error::TransactionError;
+use lib::input::InputOptions;
+#[cfg(feature = "metadata")]
+use lib::metadata::RunMetadata;
+use lib::output::OutputOptions;
+use lib::storage::ClientStorage;
+use lib::transaction::{shuffle, IncomingTransaction};
+use lib::{
+    process_file, process_pipelined, process_reader, process_reader_async, transactions_from_path,
+    AsyncEngine, SyncEngine,
+};
 
 macro_rules! test_sync {
     ($dir:literal, $engine:ty) => {
@@ -95,12 +107,497 @@ test_sync! { "single_client", BasicEngine }
 test_sync! { "larger", BasicEngine }
 test_sync! { "beyond_4_dp", BasicEngine }
 
+test_sync! { "interleaved_disputes", BasicEngine }
+
 test_sync! { "simple", StreamLikeEngine }
 test_sync! { "single_client", StreamLikeEngine }
 test_sync! { "larger", StreamLikeEngine }
 test_sync! { "beyond_4_dp", StreamLikeEngine }
 
+test_sync! { "interleaved_disputes", StreamLikeEngine }
+
+#[cfg(feature = "rayon_engine")]
+mod rayon_like {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    test_sync! { "simple", RayonLikeEngine }
+    test_sync! { "single_client", RayonLikeEngine }
+    test_sync! { "larger", RayonLikeEngine }
+    test_sync! { "beyond_4_dp", RayonLikeEngine }
+
+    test_sync! { "interleaved_disputes", RayonLikeEngine }
+}
+
 test_async! { "simple", ActorLikeEngine }
 test_async! { "single_client", ActorLikeEngine }
 test_async! { "larger", ActorLikeEngine }
 test_async! { "beyond_4_dp", ActorLikeEngine }
+test_async! { "interleaved_disputes", ActorLikeEngine }
+
+fn sorted_records(output: &[u8]) -> Vec<StringRecord> {
+    let mut records = ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(output)
+        .records()
+        .filter_map(|r| r.ok())
+        .collect::<Vec<_>>();
+    records.sort_by_key(|k| k[0].to_string());
+    records
+}
+
+fn run_sync_engine(
+    mut engine: impl SyncEngine,
+    path: &str,
+) -> color_eyre::Result<Vec<StringRecord>> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_path(path)?;
+    engine.process(reader.deserialize::<IncomingTransaction>())?;
+    let mut result = vec![];
+    engine.output(&mut result)?;
+    Ok(sorted_records(&result))
+}
+
+#[tokio::test]
+async fn all_engines_produce_identical_output() -> color_eyre::Result<()> {
+    let path = "./test_assets/interleaved_disputes/spec.csv";
+
+    let basic = run_sync_engine(BasicEngine::default(), path)?;
+    let stream = run_sync_engine(StreamLikeEngine::default(), path)?;
+
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_path(path)?;
+    let mut engine = ActorLikeEngine::default();
+    engine
+        .process(reader.deserialize::<IncomingTransaction>())
+        .await?;
+    let mut result = vec![];
+    engine.output(&mut result).await?;
+    let actor = sorted_records(&result);
+
+    assert!(
+        !basic.is_empty(),
+        "expected the fixture to produce some clients"
+    );
+    assert_eq!(
+        basic, stream,
+        "expected lhs(basic) to equal rhs(stream-like)"
+    );
+    assert_eq!(basic, actor, "expected lhs(basic) to equal rhs(actor-like)");
+    Ok(())
+}
+
+#[test]
+fn process_file_returns_the_processed_engine() -> color_eyre::Result<()> {
+    let engine = process_file(
+        "./test_assets/larger/spec.csv".into(),
+        BasicEngine::default(),
+    )?;
+    let client = engine.client(3).expect("client 3 should exist");
+    assert_eq!(client.available_amount().to_exact_string(), "4.4713");
+    assert_eq!(client.held_amount().to_exact_string(), "0.0000");
+    assert!(engine.client(1).expect("client 1 should exist").is_locked());
+
+    // The engine can still be output by the caller once it's been processed
+    let mut result = vec![];
+    engine.output(&mut result)?;
+    assert_eq!(sorted_records(&result).len(), 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn processes_the_same_result_from_a_reader_as_from_a_file() -> color_eyre::Result<()> {
+    let path = "./test_assets/larger/spec.csv";
+    let input = std::fs::read(path)?;
+
+    let mut expected = vec![];
+    process_file(path.into(), BasicEngine::default())?.output(&mut expected)?;
+
+    let mut result = vec![];
+    process_reader(&*input, BasicEngine::default())?.output(&mut result)?;
+    assert_eq!(sorted_records(&result), sorted_records(&expected));
+
+    let mut result = vec![];
+    process_reader_async(&*input, ActorLikeEngine::default())
+        .await?
+        .output(&mut result)
+        .await?;
+    assert_eq!(sorted_records(&result), sorted_records(&expected));
+    Ok(())
+}
+
+#[tokio::test]
+async fn validates_a_reader_in_the_same_manner_as_a_file() -> color_eyre::Result<()> {
+    let input = format!(
+        "type,client,tx,amount\ndeposit,1,1,{}\n",
+        "1".repeat(InputOptions::default().max_field_size + 1)
+    );
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("spec.csv");
+    std::fs::write(&path, &input)?;
+
+    let is_too_large = |e: &Report| {
+        matches!(
+            e.downcast_ref::<TransactionError>(),
+            Some(TransactionError::FieldTooLarge { .. })
+        )
+    };
+    let err = process_file(path, BasicEngine::default())
+        .err()
+        .expect("the file should be rejected");
+    assert!(is_too_large(&err), "{:?}", err);
+    let err = process_reader(input.as_bytes(), BasicEngine::default())
+        .err()
+        .expect("the reader should be rejected");
+    assert!(is_too_large(&err), "{:?}", err);
+    let err = process_reader_async(input.as_bytes(), ActorLikeEngine::default())
+        .await
+        .err()
+        .expect("the reader should be rejected");
+    assert!(is_too_large(&err), "{:?}", err);
+    Ok(())
+}
+
+#[tokio::test]
+async fn every_engine_can_sort_its_output_by_client_id() -> color_eyre::Result<()> {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 0..200 {
+        input.push_str(&format!("deposit,{},{},1.0\n", (tx * 37) % 100, tx));
+    }
+    let options = OutputOptions {
+        sort_by_id: true,
+        ..Default::default()
+    };
+    let ids = |output: &[u8]| -> color_eyre::Result<Vec<u16>> {
+        csv::Reader::from_reader(output)
+            .records()
+            .map(|r| Ok(r?[0].parse::<u16>()?))
+            .collect()
+    };
+    let expected = (0..100).collect::<Vec<_>>();
+
+    let mut result = vec![];
+    process_reader(input.as_bytes(), BasicEngine::default())?
+        .output_with_options(&mut result, options.clone())?;
+    assert_eq!(ids(&result)?, expected);
+
+    let mut result = vec![];
+    process_reader(
+        input.as_bytes(),
+        StreamLikeEngine::default().with_streaming_output(),
+    )?
+    .output_with_options(&mut result, options.clone())?;
+    assert_eq!(ids(&result)?, expected);
+
+    #[cfg(feature = "rayon_engine")]
+    {
+        let mut result = vec![];
+        process_reader(input.as_bytes(), RayonLikeEngine::default())?
+            .output_with_options(&mut result, options.clone())?;
+        assert_eq!(ids(&result)?, expected);
+    }
+
+    let mut result = vec![];
+    process_reader_async(input.as_bytes(), ActorLikeEngine::default())
+        .await?
+        .output_with_options(&mut result, options)
+        .await?;
+    assert_eq!(ids(&result)?, expected);
+    Ok(())
+}
+
+#[test]
+fn pipelined_processing_matches_in_order_processing() -> color_eyre::Result<()> {
+    for dir in ["simple", "larger", "interleaved_disputes"] {
+        let path = format!("./test_assets/{}/spec.csv", dir);
+        let expected = run_sync_engine(BasicEngine::default(), &path)?;
+
+        let options = InputOptions::default();
+        let reader = options.reader(std::fs::File::open(&path)?);
+        let mut engine = BasicEngine::default();
+        process_pipelined(reader, &options, &mut engine)?;
+        let mut result = vec![];
+        engine.output(&mut result)?;
+
+        assert_eq!(
+            sorted_records(&result),
+            expected,
+            "expected lhs(pipelined) to equal rhs(in-order) for {}",
+            dir
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn pipelined_processing_uses_the_options_of_the_reader() -> color_eyre::Result<()> {
+    let input = format!(
+        "row_id,type,client,tx,amount\n{},deposit,1,1,1.5\n",
+        "x".repeat(5_000)
+    );
+    let options = InputOptions {
+        row_id: true,
+        max_field_size: 10_000,
+        ..Default::default()
+    };
+    let reader = options.reader(std::io::Cursor::new(input.into_bytes()));
+    let mut engine = BasicEngine::default();
+    process_pipelined(reader, &options, &mut engine)?;
+    assert_eq!(
+        engine
+            .client(1)
+            .map(|c| c.available_amount().to_exact_string()),
+        Some("1.5000".to_string())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn shuffled_input_produces_identical_output() -> color_eyre::Result<()> {
+    for dir in ["larger", "interleaved_disputes"] {
+        let path = format!("./test_assets/{}/spec.csv", dir);
+        let expected = run_sync_engine(BasicEngine::default(), &path)?;
+        let transactions = || -> color_eyre::Result<Vec<IncomingTransaction>> {
+            Ok(ReaderBuilder::new()
+                .trim(Trim::All)
+                .flexible(true)
+                .from_path(&path)?
+                .deserialize::<IncomingTransaction>()
+                .collect::<Result<Vec<_>, _>>()?)
+        };
+
+        for seed in 0..5 {
+            let mut engine = BasicEngine::default();
+            engine.process(
+                shuffle(transactions()?, seed)
+                    .into_iter()
+                    .map(Ok::<_, Report>),
+            )?;
+            let mut result = vec![];
+            engine.output(&mut result)?;
+            assert_eq!(sorted_records(&result), expected, "basic, seed {}", seed);
+
+            let mut engine = StreamLikeEngine::default();
+            engine.process(
+                shuffle(transactions()?, seed)
+                    .into_iter()
+                    .map(Ok::<_, Report>),
+            )?;
+            let mut result = vec![];
+            engine.output(&mut result)?;
+            assert_eq!(
+                sorted_records(&result),
+                expected,
+                "stream-like, seed {}",
+                seed
+            );
+
+            let mut engine = ActorLikeEngine::default();
+            engine
+                .process(
+                    shuffle(transactions()?, seed)
+                        .into_iter()
+                        .map(Ok::<_, Report>),
+                )
+                .await?;
+            let mut result = vec![];
+            engine.output(&mut result).await?;
+            assert_eq!(
+                sorted_records(&result),
+                expected,
+                "actor-like, seed {}",
+                seed
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn binary_reads_ndjson_input() -> color_eyre::Result<()> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args([
+            "--input-format",
+            "ndjson",
+            "./test_assets/simple_ndjson/spec.ndjson",
+        ])
+        .output()?;
+    assert!(output.status.success(), "expected the binary to succeed");
+
+    let expected = std::fs::read("./test_assets/simple_ndjson/expected.csv")?;
+    assert_eq!(sorted_records(&output.stdout), sorted_records(&expected));
+    Ok(())
+}
+
+#[test]
+fn binary_rejects_an_unknown_input_format() -> color_eyre::Result<()> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args(["--input-format", "xml", "./test_assets/simple/spec.csv"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(2), "expected a usage error");
+    assert!(String::from_utf8(output.stderr)?.contains("--input-format"));
+    Ok(())
+}
+
+#[test]
+fn binary_writes_plain_summaries_when_not_a_terminal() -> color_eyre::Result<()> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args(["--book-totals", "./test_assets/simple/spec.csv"])
+        .output()?;
+    assert!(output.status.success(), "expected the binary to succeed");
+
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(!stderr.contains('\x1b'), "expected no escape codes");
+    assert!(stderr.contains("total available: "));
+    assert!(stderr.contains("total held:      "));
+    assert!(!String::from_utf8(output.stdout)?.contains('\x1b'));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args([
+            "--book-totals",
+            "--color",
+            "always",
+            "./test_assets/simple/spec.csv",
+        ])
+        .output()?;
+    assert!(String::from_utf8(output.stderr)?.contains('\x1b'));
+    assert!(
+        !String::from_utf8(output.stdout)?.contains('\x1b'),
+        "the csv output should never be colorized"
+    );
+    Ok(())
+}
+
+#[test]
+fn binary_exits_with_an_error_when_verification_fails() -> color_eyre::Result<()> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args([
+            "--verify",
+            "./test_assets/simple/expected.csv",
+            "./test_assets/simple/spec.csv",
+        ])
+        .output()?;
+    assert!(output.status.success(), "expected the verification to pass");
+    assert!(output.stdout.is_empty());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args([
+            "--verify",
+            "./test_assets/single_client/expected.csv",
+            "./test_assets/simple/spec.csv",
+        ])
+        .output()?;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)?.contains("mismatches found"));
+    Ok(())
+}
+
+#[test]
+fn binary_exits_with_an_error_when_an_account_is_frozen() -> color_eyre::Result<()> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args(["--fail-on-freeze", "./test_assets/simple/spec.csv"])
+        .output()?;
+    assert!(output.status.success(), "no accounts were frozen");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .args(["--fail-on-freeze", "./test_assets/larger/spec.csv"])
+        .output()?;
+    assert!(
+        !output.status.success(),
+        "client 1 was frozen by a chargeback"
+    );
+    assert_eq!(
+        sorted_records(&output.stdout).len(),
+        3,
+        "the output should still be written"
+    );
+    Ok(())
+}
+
+#[cfg(feature = "metadata")]
+#[test]
+fn binary_emits_metadata_describing_the_run() -> color_eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("metadata.json");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_trx"))
+        .arg("--emit-metadata")
+        .arg(&path)
+        .arg("./test_assets/simple/spec.csv")
+        .output()?;
+    assert!(output.status.success());
+
+    let metadata: RunMetadata = serde_json::from_reader(std::fs::File::open(path)?)?;
+    assert_eq!(metadata.engine, "basic");
+    assert_eq!(metadata.transactions, 5);
+    assert_eq!(metadata.input_sha256.len(), 64);
+    Ok(())
+}
+
+#[test]
+fn processes_every_file_in_a_directory_in_filename_order() -> color_eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(
+        dir.path().join("2022-01-02.csv"),
+        "type,client,tx,amount\nwithdrawal,1,3,2.0\ndispute,2,2,\n",
+    )?;
+    std::fs::write(
+        dir.path().join("2022-01-01.csv"),
+        "type,client,tx,amount\ndeposit,1,1,3.0\ndeposit,2,2,1.5\n",
+    )?;
+    std::fs::write(dir.path().join("notes.txt"), "not an input file")?;
+    let nested = dir.path().join("nested");
+    std::fs::create_dir(&nested)?;
+    std::fs::write(
+        nested.join("2022-01-03.csv"),
+        "type,client,tx,amount\ndeposit,3,4,1.0\n",
+    )?;
+
+    let run = |recursive| -> color_eyre::Result<Vec<StringRecord>> {
+        let options = InputOptions {
+            recursive,
+            ..Default::default()
+        };
+        let mut engine = BasicEngine::default();
+        engine.process(transactions_from_path(dir.path(), &options)?)?;
+        let mut result = vec![];
+        engine.output(&mut result)?;
+        Ok(sorted_records(&result))
+    };
+
+    assert_eq!(
+        run(false)?,
+        vec![
+            StringRecord::from(vec!["1", "1.0000", "0.0000", "1.0000", "false"]),
+            StringRecord::from(vec!["2", "0.0000", "1.5000", "1.5000", "false"]),
+        ]
+    );
+    let recursive = run(true)?;
+    assert_eq!(recursive.len(), 3, "expected the nested file to be read");
+    assert_eq!(
+        recursive[2],
+        StringRecord::from(vec!["3", "1.0000", "0.0000", "1.0000", "false"])
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn outputs_only_the_header_when_nothing_was_processed() -> color_eyre::Result<()> {
+    let header = "client,available,held,total,locked\n";
+
+    let mut result = vec![];
+    BasicEngine::default().output(&mut result)?;
+    assert_eq!(String::from_utf8(result)?, header, "basic");
+
+    let mut result = vec![];
+    StreamLikeEngine::default().output(&mut result)?;
+    assert_eq!(String::from_utf8(result)?, header, "stream-like");
+
+    let mut result = vec![];
+    ActorLikeEngine::default().output(&mut result).await?;
+    assert_eq!(String::from_utf8(result)?, header, "actor-like");
+    Ok(())
+}